@@ -0,0 +1,149 @@
+use std::{fs, path::Path};
+
+use rsx::{cdrom::{disc, iso9660::IsoFilesystem}, cpu::trace::{self, TraceEntry, TraceReader}, psf::{Psf, PsfPlayer}};
+
+const SAMPLE_RATE: u32 = 44100;
+// used when neither the command line nor the psf's tags give a length
+const DEFAULT_SONG_LENGTH: f64 = 180.0;
+
+// `iso ls <image> [dir]` lists a directory, `iso extract <image> <path> [output]`
+// extracts a file and `iso cnf <image>` prints the disc's boot configuration
+pub fn run_iso(args: &[String]) -> Result<(), String> {
+  let usage = "usage: iso ls <image> [dir] | iso extract <image> <path> [output] | iso cnf <image>";
+
+  let (command, image) = match args {
+    [command, image, ..] => (command.as_str(), image),
+    _ => return Err(usage.to_string())
+  };
+
+  let mut disc = disc::open_disc(Path::new(image))?;
+
+  let mut fs = IsoFilesystem::new(disc.as_mut())?;
+
+  match command {
+    "ls" => {
+      let dir = args.get(2).map(|dir| dir.as_str()).unwrap_or("/");
+
+      for entry in fs.list(dir)? {
+        if entry.is_directory {
+          println!("{:>10}  {}/", "<dir>", entry.name);
+        } else {
+          println!("{:>10}  {}", entry.size, entry.name);
+        }
+      }
+    }
+    "extract" => {
+      let path = args.get(2).ok_or(usage)?;
+
+      let entry = fs.find(path)?;
+      let output = args.get(3).cloned().unwrap_or_else(|| entry.name.clone());
+
+      fs::write(&output, fs.read_file(&entry)?).map_err(|e| format!("couldn't write {output}: {e}"))?;
+
+      println!("extracted {} to {output}", entry.name);
+    }
+    "cnf" => {
+      let cnf = fs.system_cnf()?;
+
+      println!("volume: {}", fs.volume_id);
+      println!("boot: {}", cnf.boot_path());
+
+      for (name, value) in [("tcb", cnf.tcb), ("event", cnf.event), ("stack", cnf.stack)] {
+        if let Some(value) = value {
+          println!("{name}: {value:x}");
+        }
+      }
+    }
+    _ => return Err(usage.to_string())
+  }
+
+  Ok(())
+}
+
+// `trace-diff <trace> <trace>` prints the first instruction two binary traces differ at
+pub fn run_trace_diff(args: &[String]) -> Result<(), String> {
+  let [left, right] = args else {
+    return Err("usage: trace-diff <trace> <trace>".to_string());
+  };
+
+  let Some(divergence) = trace::diff(TraceReader::open(Path::new(left))?, TraceReader::open(Path::new(right))?)? else {
+    println!("the traces are identical");
+
+    return Ok(());
+  };
+
+  let describe = |entry: Option<TraceEntry>| entry.map_or("end of trace".to_string(), |entry| entry.to_string());
+
+  if let Some(previous) = divergence.previous {
+    println!("last common instruction:\n  {previous}");
+  }
+
+  println!("{left}:\n  {}", describe(divergence.left));
+  println!("{right}:\n  {}", describe(divergence.right));
+
+  Err(format!("the traces diverge at instruction {}", divergence.index))
+}
+
+// `psf <file> <output.wav> [seconds]` renders a psf or minipsf to a wav file. without a
+// duration, the length and fade tags are used
+pub fn run_psf(args: &[String], bios_path: &Path) -> Result<(), String> {
+  let usage = "usage: psf <file> <output.wav> [seconds]";
+
+  let [path, output, ..] = args else {
+    return Err(usage.to_string());
+  };
+
+  let psf = Psf::open(Path::new(path))?;
+
+  let (length, fade) = match args.get(2) {
+    Some(seconds) => (seconds.parse().map_err(|_| usage)?, 0.0),
+    None => (psf.length().unwrap_or(DEFAULT_SONG_LENGTH), psf.fade().unwrap_or(0.0))
+  };
+
+  if let Some(title) = psf.title() {
+    println!("rendering {title}");
+  }
+
+  let bios = fs::read(bios_path).map_err(|e| format!("couldn't read the BIOS {}: {e}", bios_path.display()))?;
+
+  let mut player = PsfPlayer::new(bios, psf);
+
+  let samples = player.render_song(length, fade);
+
+  fs::write(output, wav_file(&samples)).map_err(|e| format!("couldn't write {output}: {e}"))?;
+
+  println!("wrote {:.1} seconds to {output}", length + fade);
+
+  Ok(())
+}
+
+// 16 bit stereo pcm
+fn wav_file(samples: &[i16]) -> Vec<u8> {
+  let data_size = samples.len() as u32 * 2;
+
+  let mut wav = Vec::with_capacity(44 + data_size as usize);
+
+  wav.extend(b"RIFF");
+  wav.extend((36 + data_size).to_le_bytes());
+  wav.extend(b"WAVE");
+
+  wav.extend(b"fmt ");
+  wav.extend(16u32.to_le_bytes());
+  // format (pcm), channels
+  wav.extend(1u16.to_le_bytes());
+  wav.extend(2u16.to_le_bytes());
+  wav.extend(SAMPLE_RATE.to_le_bytes());
+  // byte rate, block align, bits per sample
+  wav.extend((SAMPLE_RATE * 4).to_le_bytes());
+  wav.extend(4u16.to_le_bytes());
+  wav.extend(16u16.to_le_bytes());
+
+  wav.extend(b"data");
+  wav.extend(data_size.to_le_bytes());
+
+  for sample in samples {
+    wav.extend(sample.to_le_bytes());
+  }
+
+  wav
+}
@@ -1,14 +1,165 @@
-use std::{env, fs::{self, File}, path::Path};
+use std::{env, fs, panic::{self, AssertUnwindSafe}, path::{Path, PathBuf}, process, thread, time::Duration};
 
+pub mod cli;
 pub mod sdl_frontend;
 
-use rsx::cpu::CPU;
+use rsx::{cpu::{block_cache::Interpreter, exe::PsxExe, gdb::GdbServer, lockstep, trace::{self, Trace, TraceFormat}, CPU}, cdrom::{disc, ppf::PpfPatch, region::Region, DriveTiming}, game_db::{self, GameDb}};
 use sdl_frontend::SdlFrontend;
 
 extern crate rsx;
 
+const BIOS_PATH: &str = "../SCPH1001.BIN";
+const BIOS_SIZE: usize = 512 * 1024;
+// where the trace is written when the emulator crashes
+const CRASH_TRACE_FILE: &str = "trace-crash.txt";
+// memory cards are stored here, shared by every game unless the game database says otherwise
+const CARD_DIR: &str = "../cards";
+
 pub fn main() {
-  let args: Vec<String> = env::args().collect();
+  let mut args: Vec<String> = env::args().collect();
+
+  // `iso` lists and extracts files from a disc image without starting the emulator
+  if args.get(1).is_some_and(|arg| arg == "iso") {
+    if let Err(e) = cli::run_iso(&args[2..]) {
+      eprintln!("{e}");
+      process::exit(1);
+    }
+
+    return;
+  }
+
+  // `trace-diff` compares two traces recorded with --trace-file
+  if args.get(1).is_some_and(|arg| arg == "trace-diff") {
+    if let Err(e) = cli::run_trace_diff(&args[2..]) {
+      eprintln!("{e}");
+      process::exit(1);
+    }
+
+    return;
+  }
+
+  // `psf` renders a psf soundtrack to a wav file
+  if args.get(1).is_some_and(|arg| arg == "psf") {
+    if let Err(e) = cli::run_psf(&args[2..], Path::new(BIOS_PATH)) {
+      eprintln!("{e}");
+      process::exit(1);
+    }
+
+    return;
+  }
+
+  // everything after -- is passed to a PS-X EXE as its arguments, so it's split off
+  // before looking for emulator flags
+  let exe_args = if let Some(index) = args.iter().position(|arg| arg == "--") {
+    args.drain(index..).skip(1).collect()
+  } else {
+    Vec::new()
+  };
+
+  // --region ntsc-u|ntsc-j|pal overrides the region detected from the disc
+  let region = args.iter().position(|arg| arg == "--region").map(|index| {
+    let region = args.get(index + 1).and_then(|region| Region::parse(region)).expect("region must be one of ntsc-u, ntsc-j or pal");
+
+    args.drain(index..index + 2);
+
+    region
+  });
+
+  // --ppf <path> applies a ppf patch to the disc
+  let ppf = args.iter().position(|arg| arg == "--ppf").map(|index| {
+    let path = args.get(index + 1).expect("please specify a path to a ppf patch").to_string();
+
+    args.drain(index..index + 2);
+
+    path
+  });
+
+  // --fast-cd skips seek and spin up delays for quicker loading
+  let fast_cd = if let Some(index) = args.iter().position(|arg| arg == "--fast-cd") {
+    args.remove(index);
+
+    true
+  } else {
+    false
+  };
+
+  // --direct-boot starts a PS-X EXE right away, without running the BIOS
+  let direct_boot = if let Some(index) = args.iter().position(|arg| arg == "--direct-boot") {
+    args.remove(index);
+
+    true
+  } else {
+    false
+  };
+
+  // --fast-boot skips the BIOS logo and license screens
+  let fast_boot = if let Some(index) = args.iter().position(|arg| arg == "--fast-boot") {
+    args.remove(index);
+
+    true
+  } else {
+    false
+  };
+
+  // --interpreter simple|cached|compare picks the cpu interpreter. compare runs the cached
+  // interpreter alongside the simple one and stops at the first difference between them.
+  // with the dynarec feature, dynarec and compare-dynarec do the same for the recompiler
+  let (interpreter, shadow_interpreter) = match args.iter().position(|arg| arg == "--interpreter") {
+    Some(index) => {
+      let interpreter = match args.get(index + 1).map(|arg| arg.as_str()) {
+        Some("simple") => (Interpreter::Simple, None),
+        Some("cached") => (Interpreter::Cached, None),
+        Some("compare") => (Interpreter::Simple, Some(Interpreter::Cached)),
+        #[cfg(feature = "dynarec")]
+        Some("dynarec") => (Interpreter::Dynarec, None),
+        #[cfg(feature = "dynarec")]
+        Some("compare-dynarec") => (Interpreter::Simple, Some(Interpreter::Dynarec)),
+        _ => panic!("interpreter must be one of simple, cached or compare")
+      };
+
+      args.drain(index..index + 2);
+
+      interpreter
+    }
+    None => (Interpreter::Cached, None)
+  };
+
+  // --gdb [port] lets gdb attach on localhost, on port 3333 by default
+  let gdb_port = args.iter().position(|arg| arg == "--gdb").map(|index| {
+    let port = args.get(index + 1).and_then(|port| port.parse::<u16>().ok());
+
+    args.drain(index..index + 1 + port.is_some() as usize);
+
+    port.unwrap_or(3333)
+  });
+
+  // --trace [count] records the last instructions run (100000 by default), which are written
+  // to trace-crash.txt if the emulator crashes. --trace-file <path> also streams every
+  // instruction to a file, as text for .txt files and in binary for trace-diff otherwise
+  let trace_length = args.iter().position(|arg| arg == "--trace").map(|index| {
+    let length = args.get(index + 1).and_then(|length| length.parse::<usize>().ok());
+
+    args.drain(index..index + 1 + length.is_some() as usize);
+
+    length.unwrap_or(trace::DEFAULT_CAPACITY)
+  });
+
+  let trace_file = args.iter().position(|arg| arg == "--trace-file").map(|index| {
+    let path = PathBuf::from(args.get(index + 1).expect("please specify a path for the trace"));
+
+    args.drain(index..index + 2);
+
+    path
+  });
+
+  // --bypass-region lets imports and unlicensed discs boot
+  let bypass_region = if let Some(index) = args.iter().position(|arg| arg == "--bypass-region") {
+    args.remove(index);
+
+    true
+  } else {
+    false
+  };
 
   if args.len() < 2 {
     panic!("please specify a path to a game or PS exe.");
@@ -20,27 +171,201 @@ pub fn main() {
 
   let file_extension = filepath.extension().unwrap_or_default().to_str().unwrap_or_default();
 
-  let bios_data = fs::read("../SCPH1001.BIN").unwrap();
+  // homebrew that doesn't use the BIOS can direct boot without one
+  let bios_data = match fs::read(BIOS_PATH) {
+    Ok(bios_data) => bios_data,
+    Err(_) if direct_boot => vec![0; BIOS_SIZE],
+    Err(e) => panic!("couldn't read the BIOS: {e}")
+  };
+
+  // multi-disc games are loaded from an m3u playlist, starting with the first disc
+  let playlist = if file_extension == "m3u" {
+    disc::read_m3u(filepath).unwrap()
+  } else {
+    vec![filepath.to_path_buf()]
+  };
+
+  let card_path = Path::new(CARD_DIR).join("memory_card.mcd");
+
+  let create_cpu = |interpreter: Interpreter| {
+    let mut cpu = if file_extension == "exe" {
+      let mut cpu = CPU::new(bios_data.clone(), None, Some(&card_path));
+
+      let exe = PsxExe::parse(&fs::read(filepath).unwrap()).and_then(|exe| exe.with_args(exe_args.clone())).unwrap_or_else(|e| panic!("couldn't load {}: {e}", filepath.display()));
+
+      if direct_boot {
+        cpu.load_exe(&exe);
+      } else {
+        cpu.set_boot_exe(exe);
+      }
+
+      cpu
+    } else {
+      CPU::new(bios_data.clone(), Some(disc::open_disc(&playlist[0]).unwrap()), Some(&card_path))
+    };
+
+    apply_game_settings(&mut cpu, playlist.len(), region.is_some());
+
+    cpu.set_interpreter(interpreter);
+    cpu.set_fast_boot(fast_boot);
+    cpu.set_region_bypass(bypass_region);
 
-  let mut cpu = if file_extension == "exe" {
-    let mut cpu = CPU::new(bios_data, None, None, false);
-    cpu.exe_file = Some(args[1].to_string());
+    // command line flags take priority over the game database
+    if let Some(region) = region {
+      cpu.bus.cdrom.set_region(Some(region));
+    }
+
+    if fast_cd {
+      cpu.bus.cdrom.set_timing(DriveTiming::Fast);
+    }
+
+    if let Some(ppf) = &ppf {
+      let patch = PpfPatch::parse(&fs::read(ppf).unwrap()).unwrap();
+
+      println!("applying ppf patch: {}", patch.description);
+
+      cpu.bus.cdrom.apply_patch(patch).unwrap();
+    }
 
     cpu
-  } else {
-    CPU::new(bios_data, Some(File::open(filepath).unwrap()), None, false)
   };
 
-  let mut frontend = SdlFrontend::new(&sdl_context);
+  let mut cpu = create_cpu(interpreter);
+
+  if trace_length.is_some() || trace_file.is_some() {
+    let mut trace = Trace::new(trace_length.unwrap_or(trace::DEFAULT_CAPACITY));
+
+    if let Some(path) = &trace_file {
+      trace.stream_to(path, TraceFormat::from_path(path)).unwrap_or_else(|e| panic!("couldn't write the trace to {}: {e}", path.display()));
+    }
+
+    cpu.trace = Some(trace);
+  }
+
+  // the simple interpreter is the reference the shadow cpu is compared against
+  let mut shadow_cpu = shadow_interpreter.map(create_cpu);
+
+  let mut gdb = gdb_port.map(|port| {
+    let server = GdbServer::bind(port).unwrap_or_else(|e| panic!("couldn't listen for gdb on port {port}: {e}"));
+
+    println!("waiting for gdb on localhost:{port}");
+
+    server
+  });
+
+  let mut frontend = SdlFrontend::new(&sdl_context, playlist);
 
   loop {
-    cpu.run_frame();
+    if let Some(gdb) = &mut gdb {
+      gdb.poll(&mut cpu);
+
+      if gdb.is_halted() {
+        frontend.handle_events(&mut cpu);
+        thread::sleep(Duration::from_millis(1));
+
+        continue;
+      }
+    }
+
+    if let Some(shadow_cpu) = &mut shadow_cpu {
+      if let Err(e) = lockstep::run_frame_lockstep(&mut cpu, shadow_cpu) {
+        eprintln!("interpreters diverged: {e}");
+        process::exit(1);
+      }
+
+      shadow_cpu.bus.reset_cycles();
+      shadow_cpu.bus.spu.audio_buffer.clear();
+    } else {
+      let stop = panic::catch_unwind(AssertUnwindSafe(|| cpu.run_frame())).unwrap_or_else(|e| {
+        dump_trace(&cpu);
+
+        panic::resume_unwind(e)
+      });
+
+      // stopped partway through the frame
+      if let Some(stop) = stop {
+        if let Some(gdb) = &mut gdb {
+          gdb.stopped(stop);
+        }
+
+        continue;
+      }
+    }
+
     cpu.bus.gpu.cap_fps();
 
     cpu.bus.reset_cycles();
 
     frontend.render(&mut cpu.bus.gpu);
     frontend.handle_events(&mut cpu);
+
+    if let Some(shadow_cpu) = &mut shadow_cpu {
+      copy_inputs(&cpu, shadow_cpu);
+    }
+
     frontend.push_samples(cpu.bus.spu.audio_buffer.drain(..).collect());
   }
-}
\ No newline at end of file
+}
+
+// writes the instructions leading up to a crash, when tracing
+fn dump_trace(cpu: &CPU) {
+  if let Some(trace) = &cpu.trace {
+    match trace.dump(Path::new(CRASH_TRACE_FILE)) {
+      Ok(()) => eprintln!("wrote the last instructions run to {CRASH_TRACE_FILE}"),
+      Err(e) => eprintln!("couldn't write {CRASH_TRACE_FILE}: {e}")
+    }
+  }
+}
+
+// keeps the controller state of the cpu shadowing the main one in sync
+fn copy_inputs(from: &CPU, to: &mut CPU) {
+  let (from, to) = (&from.bus.controllers.joypad, &mut to.bus.controllers.joypad);
+
+  to.low_input = from.low_input;
+  to.high_input = from.high_input;
+  to.lx_axis = from.lx_axis;
+  to.ly_axis = from.ly_axis;
+  to.rx_axis = from.rx_axis;
+  to.ry_axis = from.ry_axis;
+}
+
+// looks the game up in the built in database, plus ../gamedb.txt if it exists,
+// and applies its settings. the database's region is skipped when --region was given
+fn apply_game_settings(cpu: &mut CPU, num_discs: usize, region_given: bool) {
+  let mut db = GameDb::builtin();
+
+  if let Ok(contents) = fs::read_to_string("../gamedb.txt") {
+    // a broken file could have been partly applied
+    if let Err(e) = db.extend(&contents) {
+      println!("couldn't load gamedb.txt, using the built in database: {e}");
+
+      db = GameDb::builtin();
+    }
+  }
+
+  let Some(serial) = cpu.bus.cdrom.disc_mut().and_then(game_db::detect_serial) else {
+    return;
+  };
+
+  let Some(entry) = db.lookup(&serial).cloned() else {
+    println!("game serial: {serial}");
+
+    return;
+  };
+
+  println!("game serial: {serial} ({})", entry.title);
+
+  if entry.discs as usize > num_discs {
+    println!("this game has {} discs, use an .m3u playlist to be able to swap them", entry.discs);
+  }
+
+  for issue in &entry.settings.issues {
+    println!("known issue: {issue}");
+  }
+
+  if let Some(region) = entry.region.filter(|_| !region_given) {
+    cpu.bus.cdrom.set_region(Some(region));
+  }
+
+  entry.settings.apply(cpu, &serial, Some(Path::new(CARD_DIR)));
+}
@@ -1,8 +1,10 @@
-use std::{collections::{HashMap, VecDeque}, ops::DerefMut};
+use std::{collections::{HashMap, VecDeque}, ops::DerefMut, path::{Path, PathBuf}};
 
-use rsx::{gpu::GPU, cpu::CPU, controllers::joypad::{LowInput, HighInput}};
+use rsx::{gpu::GPU, cpu::{trace::{self, Trace}, CPU}, cdrom::disc, controllers::joypad::{LowInput, HighInput}};
 use sdl2::{video::Window, EventPump, event::Event, render::Canvas, pixels::PixelFormatEnum, audio::{AudioCallback, AudioSpecDesired, AudioDevice}, Sdl, keyboard::Keycode, controller::{GameController, Button, Axis}};
 
+const TRACE_FILE: &str = "trace.txt";
+
 pub struct PsxAudioCallback {
   audio_samples: VecDeque<i16>
 }
@@ -48,11 +50,13 @@ pub struct SdlFrontend {
   _controller: Option<GameController>,
   button_map: HashMap<Button, (bool, u8)>,
   key_map: HashMap<Keycode, (bool, u8)>,
-  device: AudioDevice<PsxAudioCallback>
+  device: AudioDevice<PsxAudioCallback>,
+  playlist: Vec<PathBuf>,
+  current_disc: usize
 }
 
 impl SdlFrontend {
-  pub fn new(sdl_context: &Sdl) -> Self {
+  pub fn new(sdl_context: &Sdl, playlist: Vec<PathBuf>) -> Self {
 
     let video = sdl_context.video().unwrap();
 
@@ -150,13 +154,35 @@ impl SdlFrontend {
       _controller,
       button_map,
       key_map,
-      device
+      device,
+      playlist,
+      current_disc: 0
+    }
+  }
+
+  fn next_disc(&mut self, cpu: &mut CPU) {
+    if self.playlist.len() < 2 {
+      return;
+    }
+
+    self.current_disc = (self.current_disc + 1) % self.playlist.len();
+
+    let path = &self.playlist[self.current_disc];
+
+    match disc::open_disc(path) {
+      Ok(disc) => {
+        println!("switching to disc {}: {}", self.current_disc + 1, path.display());
+        cpu.bus.cdrom.swap_disc(disc);
+      }
+      Err(e) => println!("couldn't switch discs: {e}")
     }
   }
 
   pub fn handle_events(&mut self, cpu: &mut CPU) {
     let joypad = &mut cpu.bus.controllers.joypad;
 
+    let mut swap_disc = false;
+
     for event in self.event_pump.poll_iter() {
       match event {
         Event::KeyDown { keycode: Some(k), .. } => {
@@ -171,9 +197,16 @@ impl SdlFrontend {
           // }
 
           match k {
-            Keycode::T => {
-              cpu.debug_on = !cpu.debug_on;
-              println!("toggling cpu debug to {}", cpu.debug_on);
+            // starts tracing the cpu, then writes the trace when pressed again
+            Keycode::T => match cpu.trace.take() {
+              Some(trace) => match trace.dump(Path::new(TRACE_FILE)) {
+                Ok(()) => println!("wrote the last {} instructions to {TRACE_FILE}", trace.entries().count()),
+                Err(e) => println!("couldn't write {TRACE_FILE}: {e}")
+              }
+              None => {
+                cpu.trace = Some(Trace::new(trace::DEFAULT_CAPACITY));
+                println!("tracing the cpu");
+              }
             }
             Keycode::G => {
               cpu.bus.gpu.debug_on = !cpu.bus.gpu.debug_on;
@@ -183,6 +216,7 @@ impl SdlFrontend {
               cpu.gte.debug_on = !cpu.gte.debug_on;
               println!("toggling gte debug to {}", cpu.gte.debug_on);
             }
+            Keycode::F1 => swap_disc = true,
             _ => {
               if let Some(input) = self.key_map.get(&k) {
                 let (is_high_input, input) = *input;
@@ -245,6 +279,10 @@ impl SdlFrontend {
         _ => {},
     };
     }
+
+    if swap_disc {
+      self.next_disc(cpu);
+    }
   }
 
   pub fn push_samples(&mut self, samples: Vec<i16>) {
@@ -1,7 +1,19 @@
-use std::{rc::Rc, cell::Cell, collections::VecDeque, fs::{File, self}, io::{SeekFrom, Read, Seek}};
+use std::{rc::Rc, cell::Cell, collections::VecDeque, mem, ops::RangeInclusive};
 
 use crate::{cpu::interrupt::{interrupt_registers::InterruptRegisters, interrupt_register::Interrupt}, spu::{SPU, voices::{POS_ADPCM_TABLE, NEG_ADPCM_TABLE}}};
 
+use self::{disc::DiscImage, ppf::{PpfDisc, PpfPatch}, region::Region, toc::{Toc, TrackType}};
+
+pub mod chd;
+pub mod cue;
+pub mod disc;
+pub mod ecc;
+pub mod iso9660;
+pub mod ppf;
+pub mod region;
+pub mod subchannel;
+pub mod toc;
+
 const CDROM_CYCLES: i32 = 768;
 pub const SECTORS_PER_SECOND: u64 = 75;
 pub const SECTORS_PER_MINUTE: u64 = 60 * SECTORS_PER_SECOND;
@@ -11,7 +23,21 @@ pub const LEAD_IN_SECTORS: u64 = 2 * SECTORS_PER_SECOND;
 const HEADER_START: usize = 12;
 const SUBHEADER_START: usize = 16;
 
+// sectors skipped per sector played while scanning with Forward/Backward
+const SCAN_SECTORS: usize = 8;
+
+// strings expected by the secret unlock commands (0x50 - 0x54)
+const UNLOCK_STRINGS: [&[u8]; 4] = [b"Licensed by", b"Sony", b"Computer", b"Entertainment"];
+
+// timings are in cdrom cycles (44.1khz)
+const SPIN_UP_CYCLES: i32 = 44100;
+const SPIN_DOWN_CYCLES: i32 = 44100;
+// sectors from the inner to the outer edge of a full 74 minute disc
+const FULL_STROKE_SECTORS: f64 = 74.0 * SECTORS_PER_MINUTE as f64;
+
 const DATA_OFFSET: usize = 24;
+// mode 1 sectors don't have a subheader, so the data starts right after the header
+const MODE1_DATA_OFFSET: usize = 16;
 const ADDR_OFFSET: usize = 12;
 
 // per https://psx-spx.consoledev.net/cdromdrive/#25-point-zigzag-interpolation
@@ -25,7 +51,8 @@ pub const ZIGZAG_INTERPOLATION_TABLE: [[i32; 29]; 7] = [
   [-0x5, 0x11, -0x23, 0x46, -0x17, -0x44, 0x15b, -0x347, 0x80e, -0x1249, 0x3c07, 0x53e0, -0x16fa, 0xafa, -0x548, 0x27b, -0xeb, 0x1a, 0x2b, -0x23, 0x10, -0x8, 0x2, 0, 0, 0, 0, 0, 0],
 ];
 
-
+// the Q subchannel of a sector as read from the disc. every field is stored as
+// the raw (BCD) byte, as copy protected discs deliberately store invalid values
 pub struct SubchannelQ {
   pub track: u8,
   pub index: u8,
@@ -103,27 +130,27 @@ impl CdSubheader {
     (self.sub_mode >> 6) & 0b1 == 1
   }
 
+  // the reserved values of the coding info fields are treated like the hardware
+  // does, by only looking at the low bit of each field
+
   pub fn sample_rate(&self) -> usize {
-    match (self.coding_info >> 2) & 0x3 {
+    match (self.coding_info >> 2) & 0b1 {
       0 => 37800,
-      1 => 18900,
-      n => panic!("reserved value for sample rate given: {n}")
+      _ => 18900
     }
   }
 
   pub fn bits_per_sample(&self) -> usize {
-    match (self.coding_info >> 4) & 0x3  {
+    match (self.coding_info >> 4) & 0b1 {
       0 => 4,
-      1 => 8,
-      n => panic!("reserved value given for bits per sample: {n}")
+      _ => 8
     }
   }
 
   pub fn channels(&self) -> usize {
-    match self.coding_info & 0x3 {
+    match self.coding_info & 0b1 {
       0 => 1,
-      1 => 2,
-      _ => panic!("invalid value specified for channels: {}", self.coding_info)
+      _ => 2
     }
   }
 
@@ -141,7 +168,9 @@ impl CdSubheader {
 pub enum SubResponse {
   Disabled,
   GetID,
-  GetStat
+  GetStat,
+  // a second response computed when the command was executed, sent with the given interrupt
+  Buffered(u8)
 }
 
 #[derive(PartialEq)]
@@ -164,6 +193,56 @@ pub enum DriveMode {
   GetStat
 }
 
+#[derive(PartialEq, Clone, Copy)]
+pub enum MotorState {
+  Off,
+  SpinningUp,
+  On
+}
+
+/// accurate timing models seek distance and motor spin up, fast timing makes
+/// every seek near instant for quicker loading
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DriveTiming {
+  Accurate,
+  Fast
+}
+
+// the cd audio to spu volume matrix. 0x80 is 100%
+#[derive(Clone, Copy)]
+pub struct CdVolume {
+  pub left_to_left: u8,
+  pub left_to_right: u8,
+  pub right_to_right: u8,
+  pub right_to_left: u8
+}
+
+impl Default for CdVolume {
+  fn default() -> Self {
+    Self {
+      left_to_left: 0x80,
+      left_to_right: 0,
+      right_to_right: 0x80,
+      right_to_left: 0
+    }
+  }
+}
+
+impl CdVolume {
+  pub fn apply(&self, left: i16, right: i16) -> (i16, i16) {
+    let mix = |a: i16, a_volume: u8, b: i16, b_volume: u8| {
+      let sample = (a as i32 * a_volume as i32 + b as i32 * b_volume as i32) >> 7;
+
+      sample.clamp(-0x8000, 0x7fff) as i16
+    };
+
+    (
+      mix(left, self.left_to_left, right, self.right_to_left),
+      mix(right, self.right_to_right, left, self.left_to_right)
+    )
+  }
+}
+
 #[derive(PartialEq)]
 pub enum CdReadMode {
   Data,
@@ -202,16 +281,47 @@ pub struct Cdrom {
   processing_seek: bool,
   send_adpcm_sectors: bool,
   report_interrupts: bool,
+  auto_pause: bool,
   xa_filter: bool,
   sector_size: bool,
-  game_bytes: Option<Vec<u8>>,
-  game_file: Option<File>,
+  disc: Option<Box<dyn DiscImage>>,
+  toc: Toc,
+  shell_open: bool,
+  // the shell open stat bit stays set after the lid is closed until the next GetStat
+  shell_was_open: bool,
+  // the first seek after the lid is closed fails until the TOC is read again
+  seek_error: bool,
+  // console region, or None to use the disc's region
+  region: Option<Region>,
+  motor: MotorState,
+  motor_cycles: i32,
+  timing: DriveTiming,
+  muted: bool,
+  adpcm_muted: bool,
+  // volume changes only take effect once the apply bit is written
+  volume: CdVolume,
+  pending_volume: CdVolume,
+  // the raw setmode parameter, returned by GetParam
+  mode: u8,
+  // sectors moved per sector played while fast forwarding or rewinding
+  scan: Option<isize>,
+  // number of secret unlock commands sent in the right order so far
+  unlock_stage: u8,
+  unlocked: bool,
+  // accept unlicensed discs, like a modchip would
+  region_bypass: bool,
+  subresponse_buffer: Vec<u8>,
+  // region from the disc's license sector, None for unlicensed discs
+  disc_region: Option<Region>,
   sector_header: CdHeader,
   sector_subheader: CdSubheader,
   sector_buffer: Vec<u8>,
 
   drive_interrupt_pending: bool,
   pending_stat: u8,
+  pending_interrupt: u8,
+  // error code sent after the stat when the pending interrupt is an INT5
+  pending_error: Option<u8>,
 
   data_buffer: Vec<u8>,
   data_buffer_pointer: usize,
@@ -226,16 +336,20 @@ pub struct Cdrom {
   previous_samples: [[i16; 2]; 2],
   sample_buffer: [Vec<i16>; 2],
 
-  sixstep: usize,
+  // resampler state for each channel
+  sixstep: [usize; 2],
   ringbuf: [[i16; 0x20]; 2],
-  subq: SubchannelQ,
-
-  file_pointer: usize
+  ringbuf_index: [usize; 2],
+  subq: SubchannelQ
 }
 
 impl Cdrom {
-  pub fn new(interrupts: Rc<Cell<InterruptRegisters>>, game_file: Option<File>, game_bytes: Option<Vec<u8>>, is_wasm: bool) -> Self {
-    Self {
+  pub fn new(interrupts: Rc<Cell<InterruptRegisters>>, disc: Option<Box<dyn DiscImage>>) -> Self {
+    let toc = disc.as_ref().map(|disc| disc.toc().clone()).unwrap_or_else(|| Toc::single_track(0, BYTES_PER_SECTOR as usize));
+
+    let motor = if disc.is_some() { MotorState::On } else { MotorState::Off };
+
+    let mut cdrom = Self {
       interrupts,
       index: 0,
       interrupt_enable: 0,
@@ -265,6 +379,7 @@ impl Cdrom {
       processing_seek: false,
       send_adpcm_sectors: false,
       report_interrupts: false,
+      auto_pause: false,
       xa_filter: false,
       sector_size: false,
       sector_header: CdHeader {
@@ -282,6 +397,8 @@ impl Cdrom {
       sector_buffer: vec![0; 0x930],
       drive_interrupt_pending: false,
       pending_stat: 0,
+      pending_interrupt: 0,
+      pending_error: None,
       data_buffer: vec![0; 0x930],
       data_buffer_pointer: 0,
       is_playing: false,
@@ -294,13 +411,166 @@ impl Cdrom {
         Vec::new(),
         Vec::new()
       ],
-      sixstep: 6,
+      sixstep: [6; 2],
       ringbuf: [[0; 0x20]; 2],
+      ringbuf_index: [0; 2],
       subq: SubchannelQ::new(),
-      file_pointer: 0,
-      game_bytes,
-      game_file
+      disc,
+      toc,
+      shell_open: false,
+      shell_was_open: false,
+      seek_error: false,
+      region: None,
+      motor,
+      motor_cycles: 0,
+      timing: DriveTiming::Accurate,
+      muted: false,
+      adpcm_muted: false,
+      volume: CdVolume::default(),
+      pending_volume: CdVolume::default(),
+      mode: 0,
+      scan: None,
+      unlock_stage: 0,
+      unlocked: false,
+      region_bypass: false,
+      subresponse_buffer: Vec::new(),
+      disc_region: None
+    };
+
+    cdrom.detect_license();
+
+    cdrom
+  }
+
+  /// forces the console region. None picks the region of the inserted disc
+  pub fn set_region(&mut self, region: Option<Region>) {
+    self.region = region;
+  }
+
+  pub fn set_timing(&mut self, timing: DriveTiming) {
+    self.timing = timing;
+  }
+
+  /// lets imports and unlicensed discs boot: discs that aren't licensed for the console's
+  /// region are reported as if they were. combine with set_region to match the BIOS
+  pub fn set_region_bypass(&mut self, enabled: bool) {
+    self.region_bypass = enabled;
+  }
+
+  pub fn region(&self) -> Region {
+    self.region.or(self.disc_region).unwrap_or(Region::NtscU)
+  }
+
+  fn detect_license(&mut self) {
+    self.disc_region = None;
+
+    if self.disc.is_none() {
+      return;
+    }
+
+    // the license text is in sector 4 of the first track
+    let mut buf = vec![0; BYTES_PER_SECTOR as usize];
+
+    // sectors that can't be read are treated as unlicensed
+    self.disc_region = self.read_sector(4, &mut buf).ok().and_then(|_| Region::from_license(&buf));
+  }
+
+  /// the inserted disc, ie: for reading its file system with iso9660::IsoFilesystem
+  pub fn disc_mut(&mut self) -> Option<&mut dyn DiscImage> {
+    self.disc.as_deref_mut().map(|disc| disc as &mut dyn DiscImage)
+  }
+
+  pub fn is_lid_open(&self) -> bool {
+    self.shell_open
+  }
+
+  /// opens the drive lid. the motor stops, and any read or play in progress is
+  /// aborted with an error interrupt
+  pub fn open_lid(&mut self) {
+    if self.shell_open {
+      return;
+    }
+
+    self.shell_open = true;
+    self.shell_was_open = true;
+
+    let was_busy = self.is_reading || self.is_playing || self.is_seeking;
+
+    self.is_reading = false;
+    self.is_playing = false;
+    self.is_seeking = false;
+    self.processing_seek = false;
+
+    self.drive_mode = DriveMode::Idle;
+    self.motor = MotorState::Off;
+
+    if was_busy {
+      self.drive_interrupt(0x5);
+    }
+  }
+
+  pub fn close_lid(&mut self) {
+    if !self.shell_open {
+      return;
+    }
+
+    self.shell_open = false;
+    self.seek_error = self.disc.is_some();
+
+    if self.disc.is_some() {
+      self.start_motor();
+    }
+  }
+
+  /// removes the disc from the drive, opening the lid if needed
+  pub fn eject_disc(&mut self) -> Option<Box<dyn DiscImage>> {
+    self.open_lid();
+
+    self.toc = Toc::single_track(0, BYTES_PER_SECTOR as usize);
+    self.disc_region = None;
+
+    self.disc.take()
+  }
+
+  /// puts a disc in the drive, opening the lid if needed. the lid is left open
+  pub fn insert_disc(&mut self, disc: Box<dyn DiscImage>) {
+    self.open_lid();
+
+    self.toc = disc.toc().clone();
+    self.disc = Some(disc);
+
+    self.detect_license();
+  }
+
+  /// applies a ppf patch to the inserted disc. the image itself is left untouched,
+  /// patched bytes are overlaid as sectors are read
+  pub fn apply_patch(&mut self, patch: PpfPatch) -> Result<(), String> {
+    let Some(mut disc) = self.disc.take() else {
+      return Err("no disc inserted".to_string());
+    };
+
+    if let Err(e) = patch.verify(disc.as_mut()) {
+      self.disc = Some(disc);
+
+      return Err(e);
     }
+
+    self.disc = Some(Box::new(PpfDisc::new(disc, patch)));
+
+    self.detect_license();
+
+    Ok(())
+  }
+
+  /// opens the lid, replaces the disc and closes the lid again, returning the old
+  /// disc. games still see the lid as open until their next GetStat
+  pub fn swap_disc(&mut self, disc: Box<dyn DiscImage>) -> Option<Box<dyn DiscImage>> {
+    let previous = self.eject_disc();
+
+    self.insert_disc(disc);
+    self.close_lid();
+
+    previous
   }
 
   pub fn tick_counter(&mut self, cycles: i32, spu: &mut SPU) {
@@ -315,6 +585,7 @@ impl Cdrom {
   }
 
   fn tick(&mut self, cycles: i32, spu: &mut SPU) {
+    self.tick_motor(cycles);
     self.tick_subresponse(cycles);
     self.tick_drive(cycles, spu);
     self.tick_controller(cycles);
@@ -341,17 +612,60 @@ impl Cdrom {
       */
 
     if self.interrupt_flags == 0 {
-      self.controller_response_buffer.push_back(0x2);
-      self.controller_response_buffer.push_back(0x0);
-      self.controller_response_buffer.push_back(0x20);
-      self.controller_response_buffer.push_back(0x0);
-      self.controller_response_buffer.push_back('S' as u8);
-      self.controller_response_buffer.push_back('C' as u8);
-      self.controller_response_buffer.push_back('E' as u8);
-      self.controller_response_buffer.push_back('A' as u8);
+      let (interrupt, response) = self.get_id_response();
+
+      self.controller_response_buffer.extend(response);
+
+      self.controller_mode = ControllerMode::ResponseClear;
+      self.controller_interrupt_flags = interrupt;
+
+      self.controller_cycles += 10;
+
+      self.subresponse = SubResponse::Disabled;
+    }
+
+    self.subresponse_cycles += 1;
+  }
+
+  fn get_id_response(&self) -> (u8, [u8; 8]) {
+    let Some(disc) = &self.disc else {
+      return (0x5, [0x8, 0x40, 0, 0, 0, 0, 0, 0]);
+    };
+
+    let stat = self.get_stat();
+
+    let has_audio = disc.tracks().iter().any(|track| track.track_type == TrackType::Audio);
+
+    let disc_type = match disc.tracks().iter().find(|track| track.track_type != TrackType::Audio) {
+      Some(track) if track.track_type == TrackType::Mode2 => 0x20,
+      Some(_) => 0x0,
+      // audio cds are always denied
+      None => return (0x5, [stat | 0x8, 0x90, 0, 0, 0, 0, 0, 0])
+    };
+
+    let scex = match self.disc_region {
+      Some(region) if region == self.region() => region.scex(),
+      // a modchip makes imports and unlicensed discs look licensed for the console's region
+      _ if self.region_bypass || self.unlocked => self.region().scex(),
+      // imports are denied, but still report their own region
+      disc_region => {
+        let scex = disc_region.map_or(&[0; 4], |region| region.scex());
+        let flags = if has_audio { 0x90 } else { 0x80 };
+
+        return (0x5, [stat | 0x8, flags, disc_type, 0, scex[0], scex[1], scex[2], scex[3]]);
+      }
+    };
+
+    (0x2, [stat, 0, disc_type, 0, scex[0], scex[1], scex[2], scex[3]])
+  }
+
+  fn subresponse_buffered(&mut self, interrupt: u8) {
+    if self.interrupt_flags == 0 {
+      self.controller_response_buffer.extend(self.subresponse_buffer.drain(..));
 
       self.controller_mode = ControllerMode::ResponseClear;
-      self.controller_interrupt_flags = 0x2;
+
+      self.controller_interrupt_flags = interrupt;
 
       self.controller_cycles += 10;
 
@@ -377,6 +691,57 @@ impl Cdrom {
     self.subresponse_cycles += 1;
   }
 
+  fn tick_motor(&mut self, cycles: i32) {
+    if self.motor == MotorState::SpinningUp {
+      self.motor_cycles -= cycles;
+
+      if self.motor_cycles <= 0 {
+        self.motor = MotorState::On;
+      }
+    }
+  }
+
+  /// starts spinning up the motor, returning the cycles until it's at full speed
+  fn start_motor(&mut self) -> i32 {
+    match self.motor {
+      MotorState::On => 0,
+      MotorState::SpinningUp => self.motor_cycles.max(0),
+      MotorState::Off => {
+        self.motor_cycles = if self.timing == DriveTiming::Fast { 10 } else { SPIN_UP_CYCLES };
+        self.motor = MotorState::SpinningUp;
+
+        self.motor_cycles
+      }
+    }
+  }
+
+  /// cycles for the head to move to the setloc target, including any spin up.
+  /// fast timing uses the given fixed number of cycles instead
+  fn seek_cycles(&mut self, fast_cycles: i32) -> i32 {
+    let spin_up = self.start_motor();
+
+    if self.timing == DriveTiming::Fast {
+      return fast_cycles + spin_up;
+    }
+
+    let target = Toc::msf_to_lba(self.mm, self.ss, self.sect).saturating_sub(LEAD_IN_SECTORS as usize);
+
+    let distance = target.abs_diff(self.current_lba());
+
+    let divisor = if self.double_speed { 150 } else { 75 };
+    let sector_cycles = 44100 / divisor;
+
+    // short seeks just read ahead to the target, longer ones move the sled.
+    // roughly 100ms for a short hop, up to about a second across the whole disc
+    let cycles = if distance < 32 {
+      sector_cycles * distance.max(2) as i32
+    } else {
+      4410 + (39690.0 * (distance as f64 / FULL_STROKE_SECTORS).min(1.0).sqrt()) as i32
+    };
+
+    cycles + spin_up
+  }
+
   fn tick_subresponse(&mut self, cycles: i32) {
     self.subresponse_cycles -= cycles;
 
@@ -384,7 +749,8 @@ impl Cdrom {
       match self.subresponse {
         SubResponse::Disabled => self.subresponse_cycles += cycles,
         SubResponse::GetID => self.subresponse_get_id(),
-        SubResponse::GetStat => self.subresponse_get_stat()
+        SubResponse::GetStat => self.subresponse_get_stat(),
+        SubResponse::Buffered(interrupt) => self.subresponse_buffered(interrupt)
       }
     }
   }
@@ -396,16 +762,7 @@ impl Cdrom {
     self.current_ss = self.ss;
     self.current_sect = self.sect;
 
-    self.subq.track = 1;
-    self.subq.index = 1;
-
-    self.subq.mm = self.mm;
-    self.subq.ss = self.ss - 2;
-    self.subq.sect = self.sect;
-
-    self.subq.amm = self.mm;
-    self.subq.ass = self.ss;
-    self.subq.asect = self.sect;
+    self.subq = self.subchannel_q(self.current_lba());
 
     self.is_playing = false;
     self.is_seeking = false;
@@ -432,58 +789,123 @@ impl Cdrom {
     self.drive_mode = self.next_drive_mode;
   }
 
-  fn play_drive(&mut self) {
-    todo!("play_drive not implemented");
-  }
-
-  fn read_drive(&mut self, spu: &mut SPU) {
-    if !self.is_reading {
+  fn play_drive(&mut self, spu: &mut SPU) {
+    if !self.is_playing {
       self.drive_mode = DriveMode::Idle;
       self.drive_cycles += 1;
 
       return;
     }
-    self.push_stat();
 
-    self.file_pointer = self.get_seek_pointer() as usize;
+    let lba = self.current_lba();
+
+    if lba >= self.toc.lead_out {
+      // reached the end of the disc
+      self.is_playing = false;
+      self.drive_mode = DriveMode::Idle;
+
+      self.drive_interrupt(0x4);
+
+      return;
+    }
 
+    let track_end = self.toc.find_track(lba).map(|track| track.end).unwrap_or(self.toc.lead_out);
 
-    let mut buf: Vec<u8> = vec![0; 24];
+    let mut buf: Vec<u8> = vec![0; BYTES_PER_SECTOR as usize];
 
-    if let Some(game_file) = &mut self.game_file {
-      game_file.seek(SeekFrom::Start(self.file_pointer as u64)).unwrap();
-      game_file.read_exact(&mut buf).unwrap();
-    } else if let Some(game_bytes) = &self.game_bytes {
-      let end_length = self.file_pointer + 24;
+    if let Err(e) = self.read_sector(lba, &mut buf) {
+      self.drive_error(lba, e);
 
-      buf = game_bytes[self.file_pointer..end_length].to_vec();
+      return;
     }
 
-    let header = CdHeader::new(&mut buf);
-    let subheader = CdSubheader::new(&mut buf);
+    self.subq = self.subchannel_q(lba);
 
-    self.subq.track = 1;
-    self.subq.index = 1;
+    // CD-DA sectors are 588 stereo samples of 16 bit PCM at 44.1khz
+    let mut peak = 0;
 
-    self.subq.mm = header.mm;
-    self.subq.ss = header.ss - 2;
-    self.subq.sect = header.sect;
+    for sample in buf.chunks_exact(4) {
+      let left = i16::from_le_bytes([sample[0], sample[1]]);
+      let right = i16::from_le_bytes([sample[2], sample[3]]);
 
-    self.subq.amm = header.mm;
-    self.subq.ass = header.ss;
-    self.subq.asect = header.sect;
+      peak = peak.max(left.unsigned_abs()).max(right.unsigned_abs());
 
-    if header.mm != self.current_mm || header.ss != self.current_ss || header.sect != self.current_sect {
-      panic!("mismatched sector info between header and controller");
+      self.push_cd_sample(spu, left, right, false);
     }
 
-    if header.mode != 2 {
-      panic!("unsupported mode found: {}", header.mode);
+    if self.report_interrupts && Self::bcd_to_u8(self.subq.asect).is_multiple_of(10) {
+      self.report_position(peak);
     }
 
-    self.sector_header = header;
-    self.sector_subheader = subheader;
+    self.advance_position();
+
+    if let Some(scan) = self.scan {
+      let lba = (lba as isize + scan).clamp(0, self.toc.lead_out as isize) as usize;
+
+      self.set_position(lba);
+    }
+
+    if self.auto_pause && lba + 1 >= track_end {
+      self.is_playing = false;
+      self.drive_mode = DriveMode::Idle;
+
+      self.drive_interrupt(0x4);
+
+      return;
+    }
+
+    let divisor = if self.double_speed { 150 } else { 75 };
+    self.drive_cycles += 44100 / divisor;
+  }
+
+  fn report_position(&mut self, peak: u16) {
+    // per https://psx-spx.consoledev.net/cdromdrive/#cdrom-reports
+    // reports alternate between absolute time and relative time (with bit 7 of the second set)
+    if self.interrupt_flags != 0 {
+      return;
+    }
+
+    self.interrupt_flags = 0x1;
+
+    self.response_buffer.push_back(self.get_stat());
+    self.response_buffer.push_back(self.subq.track);
+    self.response_buffer.push_back(self.subq.index);
+
+    if Self::bcd_to_u8(self.subq.asect).is_multiple_of(20) {
+      self.response_buffer.push_back(self.subq.amm);
+      self.response_buffer.push_back(self.subq.ass);
+      self.response_buffer.push_back(self.subq.asect);
+    } else {
+      self.response_buffer.push_back(self.subq.mm);
+      self.response_buffer.push_back(self.subq.ss | 0x80);
+      self.response_buffer.push_back(self.subq.sect);
+    }
+
+    self.response_buffer.push_back(peak as u8);
+    self.response_buffer.push_back((peak >> 8) as u8);
+  }
+
+  /// sends a sample to the spu through the mute and volume registers
+  fn push_cd_sample(&self, spu: &mut SPU, left: i16, right: i16, is_adpcm: bool) {
+    let (left, right) = if self.muted || (is_adpcm && self.adpcm_muted) {
+      (0, 0)
+    } else {
+      self.volume.apply(left, right)
+    };
+
+    spu.cd_left_buffer.push_back(left);
+    spu.cd_right_buffer.push_back(right);
+  }
 
+  fn set_position(&mut self, lba: usize) {
+    let (mm, ss, sect) = Toc::lba_to_msf(lba + LEAD_IN_SECTORS as usize);
+
+    self.current_mm = mm;
+    self.current_ss = ss;
+    self.current_sect = sect;
+  }
+
+  fn advance_position(&mut self) {
     self.current_sect += 1;
 
     if self.current_sect >= 75 {
@@ -495,9 +917,85 @@ impl Cdrom {
         self.current_mm += 1;
       }
     }
+  }
+
+  fn drive_interrupt(&mut self, interrupt: u8) {
+    if self.interrupt_flags == 0 {
+      self.interrupt_flags = interrupt;
+      self.response_buffer.push_back(self.get_stat());
+    } else {
+      self.drive_interrupt_pending = true;
+      self.pending_interrupt = interrupt;
+      self.pending_stat = self.get_stat();
+      self.pending_error = None;
+    }
+  }
+
+  // a sector that couldn't be read stops the read or play with a seek error
+  fn drive_error(&mut self, lba: usize, error: String) {
+    println!("couldn't read sector {lba}: {error}");
+
+    self.is_reading = false;
+    self.is_playing = false;
+    self.drive_mode = DriveMode::Idle;
+
+    let stat = self.get_stat() | 0x4 | 0x1;
+
+    if self.interrupt_flags == 0 {
+      self.interrupt_flags = 0x5;
+      self.response_buffer.push_back(stat);
+      self.response_buffer.push_back(0x4);
+    } else {
+      self.drive_interrupt_pending = true;
+      self.pending_interrupt = 0x5;
+      self.pending_stat = stat;
+      self.pending_error = Some(0x4);
+    }
+  }
+
+  fn read_drive(&mut self, spu: &mut SPU) {
+    if !self.is_reading {
+      self.drive_mode = DriveMode::Idle;
+      self.drive_cycles += 1;
+
+      return;
+    }
+    self.push_stat();
+
+    let lba = self.current_lba();
+
+    let mut buf: Vec<u8> = vec![0; BYTES_PER_SECTOR as usize];
+
+    if let Err(e) = self.read_sector(lba, &mut buf) {
+      self.drive_error(lba, e);
+
+      return;
+    }
+
+    let header = CdHeader::new(&mut buf);
+    let subheader = CdSubheader::new(&mut buf);
+
+    self.subq = self.subchannel_q(lba);
+
+    let is_audio_track = self.toc.find_track(lba).map(|track| track.track_type == TrackType::Audio).unwrap_or(false);
+
+    if !is_audio_track && (header.mm != self.current_mm || header.ss != self.current_ss || header.sect != self.current_sect) {
+      panic!("mismatched sector info between header and controller");
+    }
+
+    // only mode 2 sectors have a subheader that can mark them as xa-adpcm
+    let is_mode2 = header.mode == 2;
+
+    self.sector_header = header;
+    self.sector_subheader = subheader;
+
+    self.advance_position();
 
     // TODO: see if subheader.realtime() is needed here
-    let mut mode = if subheader.mode() == CdSubheaderMode::Audio && self.send_adpcm_sectors && subheader.realtime() {
+    let mut mode = if is_audio_track || !is_mode2 {
+      // audio tracks don't have a subheader, so the raw sector is handed over as is
+      CdReadMode::Data
+    } else if subheader.mode() == CdSubheaderMode::Audio && self.send_adpcm_sectors && subheader.realtime() {
       CdReadMode::Audio
     } else {
       CdReadMode::Data
@@ -509,8 +1007,8 @@ impl Cdrom {
 
 
     match mode {
-      CdReadMode::Audio => self.read_audio(spu),
-      CdReadMode::Data => self.read_data(),
+      CdReadMode::Audio => self.read_audio(&buf[DATA_OFFSET..], spu),
+      CdReadMode::Data => self.read_data(buf),
       CdReadMode::Skip => ()
     }
 
@@ -518,76 +1016,65 @@ impl Cdrom {
     self.drive_cycles += 44100 / divisor;
   }
 
-  fn read_audio(&mut self, spu: &mut SPU) {
-    if self.sector_subheader.bits_per_sample() != 4 {
-      todo!("unimplemented bits per sample given")
-    }
-
-    let mut buffer: Vec<u8> = vec![0; 0x914];
-
-    if let Some(game_file) = &mut self.game_file {
-      game_file.read_exact(&mut buffer).unwrap();
-    } else if let Some(game_bytes) = &self.game_bytes {
-      // accomodate for having reading the header data
-      self.file_pointer += 24;
-
-      let end_length = self.file_pointer + 0x914;
-
-      buffer = game_bytes[self.file_pointer..end_length].to_vec();
-    }
-
+  fn read_audio(&mut self, buffer: &[u8], spu: &mut SPU) {
     let channels = self.sector_subheader.channels();
+    let bits_per_sample = self.sector_subheader.bits_per_sample();
 
     // per docs, "Each sector consists of 12h 128-byte portions (=900h bytes)
     // (the remaining 14h bytes of the sectors 914h-byte data region are 00h filled)."
     for i in 0..0x12 {
-      self.decode_blocks(&buffer[i * 128..], channels);
+      self.decode_blocks(&buffer[i * 128..], channels, bits_per_sample);
     }
 
+    // 18.9khz streams are doubled up to 37.8khz before resampling
     let repeat = match self.sector_subheader.sample_rate() {
       18900 => 2,
-      37800 => 1,
-      _ => unreachable!()
+      _ => 1
     };
 
-    for channel in 0..channels {
-      for _ in 0..repeat {
-        for i in 0..self.sample_buffer[channel].len() {
-          self.ringbuf[channel][i & 0x1f] = self.sample_buffer[channel][i];
-
-          self.sixstep -= 1;
-
-          if self.sixstep == 0 {
-            self.sixstep = 6;
-
-            for j in 0..7 {
-              let sample = self.zigzag_interpolate(self.ringbuf[channel], ZIGZAG_INTERPOLATION_TABLE[j], i+1);
-
-              if channels == 1 {
-                spu.cd_left_buffer.push_back(sample);
-                spu.cd_right_buffer.push_back(sample);
-              } else {
-                if channel == 0 {
-                  spu.cd_left_buffer.push_back(sample)
-                } else {
-                  spu.cd_right_buffer.push_back(sample);
-                }
-              }
-            }
-          }
+    let mut output: [Vec<i16>; 2] = [Vec::new(), Vec::new()];
+
+    for (channel, output) in output.iter_mut().enumerate().take(channels) {
+      let samples = mem::take(&mut self.sample_buffer[channel]);
+
+      for sample in samples {
+        for _ in 0..repeat {
+          self.resample(sample, channel, output);
         }
       }
     }
 
-    self.sample_buffer[0].clear();
-    self.sample_buffer[1].clear();
+    // mono streams play the same samples on both sides
+    let right = if channels == 1 { 0 } else { 1 };
+
+    for (left, right) in output[0].iter().zip(output[right].iter()) {
+      self.push_cd_sample(spu, *left, *right, true);
+    }
+  }
+
+  /// converts 37.8khz samples to 44.1khz, outputting 7 samples for every 6
+  fn resample(&mut self, sample: i16, channel: usize, output: &mut Vec<i16>) {
+    let index = self.ringbuf_index[channel];
+
+    self.ringbuf[channel][index & 0x1f] = sample;
+    self.ringbuf_index[channel] = (index + 1) & 0x1f;
+
+    self.sixstep[channel] -= 1;
+
+    if self.sixstep[channel] == 0 {
+      self.sixstep[channel] = 6;
+
+      for table in ZIGZAG_INTERPOLATION_TABLE.iter() {
+        output.push(self.zigzag_interpolate(self.ringbuf[channel], *table, index + 1));
+      }
+    }
   }
 
   fn zigzag_interpolate(&mut self, buffer: [i16; 32], table: [i32; 29], index: usize) -> i16 {
     // https://psx-spx.consoledev.net/cdromdrive/#25-point-zigzag-interpolation
     let mut sum = 0;
     for i in 1..30 {
-      sum += ((buffer[(index - i) & 0x1f] as i32) * table[i - 1]) / 0x8000;
+      sum += ((buffer[(index + 0x20 - i) & 0x1f] as i32) * table[i - 1]) / 0x8000;
     }
 
     if sum < -0x8000 {
@@ -599,15 +1086,19 @@ impl Cdrom {
     sum as i16
   }
 
-  fn decode_blocks(&mut self, buffer: &[u8], channels: usize) {
-    for i in 0..8 {
+  fn decode_blocks(&mut self, buffer: &[u8], channels: usize, bits_per_sample: usize) {
+    // 4 bit portions have 8 blocks of 28 samples, 8 bit portions have 4. stereo
+    // streams alternate between left and right blocks
+    let blocks = if bits_per_sample == 8 { 4 } else { 8 };
+
+    for i in 0..blocks {
       let channel = if channels > 1 { i & 0b1 } else { 0 };
 
-      self.decode_sample_block(buffer, i, channel)
+      self.decode_sample_block(buffer, i, channel, bits_per_sample)
     }
   }
 
-  fn decode_sample_block(&mut self, buffer: &[u8], block: usize, channel: usize) {
+  fn decode_sample_block(&mut self, buffer: &[u8], block: usize, channel: usize, bits_per_sample: usize) {
     // per docs, "The separate 128-byte portions consist of a 16-byte header,
     // followed by twentyeight data words (4x28-bytes),"
     //  00h..03h  Copy of below 4 bytes (at 04h..07h)
@@ -624,15 +1115,23 @@ impl Cdrom {
     let f1 = NEG_ADPCM_TABLE[filter];
 
     for i in 0..28 {
-      let mut sample = buffer[0x10 + (block/2) + (i * 4)];
+      let mut sample = if bits_per_sample == 8 {
+        // each byte of the data word is a sample of a different block
+        let sample = buffer[0x10 + block + (i * 4)];
 
-      if block & 0b1 == 1 {
-        sample >>= 4;
-      }
+        ((sample as u16) << 8) as i16 as i32
+      } else {
+        let mut sample = buffer[0x10 + (block/2) + (i * 4)];
+
+        if block & 0b1 == 1 {
+          sample >>= 4;
+        }
+
+        sample &= 0xf;
 
-      sample &= 0xf;
+        ((sample as u16) << 12) as i16 as i32
+      };
 
-      let mut sample = ((sample as u16) << 12) as i16 as i32;
       sample >>= shift;
 
       let filter = (32 + self.previous_samples[channel][0] as i32 * f0 + self.previous_samples[channel][1] as i32 * f1) / 64;
@@ -651,32 +1150,34 @@ impl Cdrom {
     }
   }
 
-  fn read_data(&mut self) {
-    if let Some(game_file) = &mut self.game_file {
-      game_file.seek(SeekFrom::Start(self.file_pointer as u64)).unwrap();
-      game_file.read_exact(&mut self.sector_buffer).unwrap();
-    } else if let Some(game_bytes) = &self.game_bytes {
-      let end_length = self.file_pointer + self.sector_buffer.len();
-      self.sector_buffer = game_bytes[self.file_pointer..end_length].to_vec();
-    }
+  fn read_data(&mut self, sector: Vec<u8>) {
+    self.sector_buffer = sector;
 
-    if self.interrupt_flags == 0 {
-      self.interrupt_flags = 0x1;
-      self.response_buffer.push_back(self.get_stat());
-    } else {
-      self.drive_interrupt_pending = true;
-      self.pending_stat = self.get_stat();
-    }
+    self.drive_interrupt(0x1);
   }
 
-  fn get_seek_pointer(&self) -> u64 {
-    let mut sector = self.current_ss as u64 * SECTORS_PER_SECOND + self.current_mm as u64 * SECTORS_PER_MINUTE + self.current_sect as u64;
+  fn current_lba(&self) -> usize {
+    let sector = Toc::msf_to_lba(self.current_mm, self.current_ss, self.current_sect);
 
-    if sector >= LEAD_IN_SECTORS {
-      sector -= LEAD_IN_SECTORS;
+    sector.saturating_sub(LEAD_IN_SECTORS as usize)
+  }
+
+  fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), String> {
+    if let Some(disc) = &mut self.disc {
+      disc.read_sector(lba, buf)
+    } else {
+      self.toc.synthesize_sector(lba, buf);
+
+      Ok(())
     }
+  }
 
-    sector * BYTES_PER_SECTOR
+  fn subchannel_q(&self, lba: usize) -> SubchannelQ {
+    if let Some(disc) = &self.disc {
+      disc.subchannel_q(lba)
+    } else {
+      self.toc.subchannel_q(lba)
+    }
   }
 
   fn drive_get_stat(&mut self) {
@@ -701,7 +1202,7 @@ impl Cdrom {
       match self.drive_mode {
         DriveMode::Idle => self.drive_cycles += cycles,
         DriveMode::Seek => self.seek_drive(),
-        DriveMode::Play => self.play_drive(),
+        DriveMode::Play => self.play_drive(spu),
         DriveMode::Read => self.read_drive(spu),
         DriveMode::GetStat => self.drive_get_stat()
       }
@@ -808,52 +1309,90 @@ impl Cdrom {
     }
   }
 
+  /// the number of parameters each command takes, or None for invalid commands
+  fn parameter_count(command: u8) -> Option<RangeInclusive<usize>> {
+    // per https://psx-spx.consoledev.net/cdromdrive/#cdrom-controller-command-summary
+    match command {
+      0x01 | 0x04..=0x0c | 0x10 | 0x11 | 0x13 | 0x15 | 0x16 | 0x1a..=0x1c | 0x1e | 0x0f => Some(0..=0),
+      0x03 => Some(0..=1),
+      0x0e | 0x12 | 0x14 => Some(1..=1),
+      0x0d | 0x1d => Some(2..=2),
+      0x02 => Some(3..=3),
+      0x19 => Some(1..=16),
+      // secret unlock commands 1-7 and SecretLock
+      0x50..=0x57 => Some(0..=16),
+      _ => None
+    }
+  }
+
   fn execute(&mut self, command: u8) {
+    let Some(parameter_count) = Self::parameter_count(command) else {
+      self.controller_interrupt_flags = self.error_response(0x40);
+
+      return;
+    };
+
+    if !parameter_count.contains(&self.controller_param_buffer.len()) {
+      self.controller_interrupt_flags = self.error_response(0x20);
+
+      return;
+    }
+
+    // commands that need the disc fail while the lid is open
+    if self.shell_open && matches!(command, 0x03..=0x06 | 0x10 | 0x11 | 0x12 | 0x13 | 0x14 | 0x15 | 0x16 | 0x1a | 0x1b | 0x1d | 0x1e) {
+      self.controller_interrupt_flags = self.error_response(0x80);
+
+      return;
+    }
+
+    if self.seek_error && matches!(command, 0x03 | 0x06 | 0x15 | 0x16 | 0x1b) {
+      self.seek_error = false;
+      self.controller_interrupt_flags = self.error_response(0x4);
+
+      return;
+    }
+
     let mut interrupt = 0x3;
     match command {
-      0x01 => self.push_stat(),
-      0x02 => self.setloc(),
-      0x06 => self.readn(),
-      0x07 => {
+      0x01 => {
         self.push_stat();
 
-        self.controller_response_buffer.push_back(0x20);
-
-        interrupt = 0x5;
-      },
+        if !self.shell_open {
+          self.shell_was_open = false;
+        }
+      }
+      0x02 => interrupt = self.setloc(),
+      0x03 => self.play(),
+      0x04 => interrupt = self.scan(SCAN_SECTORS as isize),
+      0x05 => interrupt = self.scan(-(SCAN_SECTORS as isize)),
+      0x06 => self.readn(),
+      0x07 => interrupt = self.motor_on(),
+      0x08 => self.stop(),
       0x09 => self.pause(),
       0x0a => self.init(),
-      0x0b | 0x0c => self.push_stat(),
-      0x0d => self.setfilter(),
-      0x0e => self.setmode(),
-      0x10 => self.getloc_l(),
-      0x11 => self.getloc_p(),
-      0x13 => {
-        self.push_stat();
+      0x0b => {
+        self.muted = true;
 
-        self.controller_response_buffer.push_back(1);
-        self.controller_response_buffer.push_back(1);
+        self.push_stat();
       }
-      0x14 => {
+      0x0c => {
+        self.muted = false;
+
         self.push_stat();
-        self.controller_response_buffer.push_back(0);
-        self.controller_response_buffer.push_back(0);
       }
+      0x0d => self.setfilter(),
+      0x0e => self.setmode(),
+      0x0f => self.getparam(),
+      0x10 => interrupt = self.getloc_l(),
+      0x11 => self.getloc_p(),
+      0x12 => interrupt = self.set_session(),
+      0x13 => self.get_tn(),
+      0x14 => interrupt = self.get_td(),
       0x15 | 0x16 => self.seek(),
-      0x19 => {
-        let sub_function = self.controller_param_buffer.pop_front().unwrap();
-        // per https://psx-spx.consoledev.net/cdromdrive/#19h20h-int3yymmddver
-        // 97h,01h,10h,C2h  ;PSX (PU-18) (us/eur)     10 Jan 1997, version vC2 (a)
-        if sub_function == 0x20 {
-          self.controller_response_buffer.push_back(0x97);
-          self.controller_response_buffer.push_back(0x01);
-          self.controller_response_buffer.push_back(0x10);
-          self.controller_response_buffer.push_back(0xc2);
-        } else {
-          panic!("unsupported subfunction given: {:x}", sub_function);
-        }
-      }
+      0x19 => interrupt = self.test(),
       0x1a => {
+        self.seek_error = false;
+
         self.push_stat();
 
         self.subresponse = SubResponse::GetID;
@@ -861,45 +1400,304 @@ impl Cdrom {
         self.subresponse_cycles += 50;
       }
       0x1b => self.reads(),
-      0x1e => {
-        self.push_stat();
+      0x1c => self.reset(),
+      0x1d => interrupt = self.get_q(),
+      0x1e => self.read_toc(),
+      0x50..=0x57 => interrupt = self.secret_unlock(command),
+      _ => unreachable!()
+    }
+
+    self.controller_interrupt_flags = interrupt;
+  }
+
+  fn scan(&mut self, sectors: isize) -> u8 {
+    // only works while playing audio
+    if !self.is_playing {
+      return self.error_response(0x80);
+    }
+
+    self.push_stat();
+
+    self.scan = Some(sectors);
+
+    0x3
+  }
+
+  fn getparam(&mut self) {
+    self.push_stat();
+
+    self.controller_response_buffer.push_back(self.mode);
+    self.controller_response_buffer.push_back(0);
+    self.controller_response_buffer.push_back(self.filter_file);
+    self.controller_response_buffer.push_back(self.filter_channel);
+  }
+
+  fn set_session(&mut self) -> u8 {
+    let session = self.controller_param_buffer.pop_front().unwrap();
+
+    if session == 0 {
+      return self.error_response(0x10);
+    }
+
+    self.push_stat();
+
+    self.is_playing = false;
+    self.is_reading = false;
+    self.is_seeking = false;
+
+    // disc images only ever have a single session
+    if session == 1 {
+      self.subresponse = SubResponse::GetStat;
+    } else {
+      self.subresponse_buffer = vec![self.get_stat() | 0x4 | 0x1, 0x40];
+      self.subresponse = SubResponse::Buffered(0x5);
+    }
+
+    self.subresponse_cycles += self.seek_cycles(28);
+
+    0x3
+  }
+
+  fn reset(&mut self) {
+    self.push_stat();
+
+    self.mode = 0;
+    self.double_speed = false;
+    self.send_adpcm_sectors = false;
+    self.sector_size = false;
+    self.xa_filter = false;
+    self.report_interrupts = false;
+    self.auto_pause = false;
+
+    self.is_playing = false;
+    self.is_reading = false;
+    self.is_seeking = false;
+    self.processing_seek = false;
+    self.scan = None;
+
+    self.drive_mode = DriveMode::Idle;
+    self.subresponse = SubResponse::Disabled;
+  }
+
+  fn get_q(&mut self) -> u8 {
+    // returns the lead-in TOC entry for the given point, ie: a track's start
+    // time, or A0h-A2h for the first/last track numbers and the lead out
+    let _adr = self.controller_param_buffer.pop_front().unwrap();
+    let point = self.controller_param_buffer.pop_front().unwrap();
+
+    let data_control = 0x41;
+    let audio_control = 0x01;
+
+    let disc_type = if self.toc.tracks.iter().any(|track| track.track_type == TrackType::Mode2) { 0x20 } else { 0x00 };
+
+    let entry = match point {
+      0xa0 => {
+        let first = self.toc.tracks.first().map(|track| track.track_type);
 
-        self.subresponse = SubResponse::GetStat;
-        self.subresponse_cycles += 44100;
+        let control = if first == Some(TrackType::Audio) { audio_control } else { data_control };
+
+        Some((control, Self::u8_to_bcd(self.toc.first_track()), disc_type, 0))
+      }
+      0xa1 => Some((data_control, Self::u8_to_bcd(self.toc.last_track()), 0, 0)),
+      0xa2 => {
+        let (mm, ss, sect) = Toc::lba_to_msf(self.toc.lead_out + LEAD_IN_SECTORS as usize);
+
+        Some((data_control, Self::u8_to_bcd(mm), Self::u8_to_bcd(ss), Self::u8_to_bcd(sect)))
       }
-      _ => todo!("command not implemented yet: {:x}", command)
+      point => self.toc.get_track(Self::bcd_to_u8(point)).map(|track| {
+        let control = if track.track_type == TrackType::Audio { audio_control } else { data_control };
+
+        let (mm, ss, sect) = Toc::lba_to_msf(track.start + LEAD_IN_SECTORS as usize);
+
+        (control, Self::u8_to_bcd(mm), Self::u8_to_bcd(ss), Self::u8_to_bcd(sect))
+      })
+    };
+
+    let Some((control, pmin, psec, pframe)) = entry else {
+      return self.error_response(0x10);
+    };
+
+    self.push_stat();
+
+    self.subresponse_buffer = vec![control, 0, point, 0, 0, 0, 0, pmin, psec, pframe];
+    self.subresponse = SubResponse::Buffered(0x2);
+    self.subresponse_cycles += self.seek_cycles(28);
+
+    0x3
+  }
+
+  fn read_toc(&mut self) {
+    self.seek_error = false;
+
+    self.push_stat();
+
+    if let Some(disc) = &self.disc {
+      self.toc = disc.toc().clone();
     }
 
-    self.controller_interrupt_flags = interrupt;
+    self.is_playing = false;
+    self.is_reading = false;
+    self.is_seeking = false;
+
+    self.subresponse = SubResponse::GetStat;
+    self.subresponse_cycles += 44100 + self.start_motor();
+  }
+
+  fn secret_unlock(&mut self, command: u8) -> u8 {
+    // per https://psx-spx.consoledev.net/cdromdrive/#secret-unlock-commands
+    // each command checks its string, and the drive unlocks after all 7 were
+    // sent in order. every command responds with an "invalid command" error
+    // either way. japanese drives don't support unlocking
+    let params: Vec<u8> = self.controller_param_buffer.drain(..).collect();
+
+    let stage = (command - 0x50) as usize;
+
+    let valid = match stage {
+      0..=3 => params == UNLOCK_STRINGS[stage],
+      4 => params == b"of America" || params == b"(Europe)" || params == b"World wide",
+      5 | 6 => params.is_empty(),
+      _ => false
+    };
+
+    if command == 0x57 {
+      self.unlocked = false;
+      self.unlock_stage = 0;
+    } else if valid && self.unlock_stage as usize == stage && self.region() != Region::NtscJ {
+      self.unlock_stage += 1;
+
+      if self.unlock_stage == 7 {
+        self.unlocked = true;
+      }
+    } else {
+      self.unlock_stage = 0;
+    }
+
+    self.error_response(0x40)
+  }
+
+  fn test(&mut self) -> u8 {
+    // per https://psx-spx.consoledev.net/cdromdrive/#19h-test-command
+    let Some(sub_function) = self.controller_param_buffer.pop_front() else {
+      return self.error_response(0x20);
+    };
+
+    match sub_function {
+      // motor and lens control, nothing to emulate for these
+      0x00..=0x04 | 0x10..=0x1a | 0x30 | 0x31 | 0x50 | 0x72 => self.push_stat(),
+      0x05 => {
+        // total and successfully read SCEx strings since 0x04. the strings aren't
+        // emulated, so this only reports whether the disc is licensed (or a modchip
+        // would inject them)
+        self.controller_response_buffer.push_back(self.disc.is_some() as u8);
+        self.controller_response_buffer.push_back((self.disc.is_some() && (self.disc_region.is_some() || self.region_bypass)) as u8);
+      }
+      0x06..=0x08 => {
+        // adjust balance/gain, returns the old value
+        self.controller_response_buffer.push_back(0);
+      }
+      0x20 => {
+        // 97h,01h,10h,C2h  ;PSX (PU-18) (us/eur)     10 Jan 1997, version vC2 (a)
+        self.controller_response_buffer.extend([0x97, 0x01, 0x10, 0xc2]);
+      }
+      0x21 => {
+        // drive switches: bit 0 is the lens at its innermost position, bit 1 the door
+        self.controller_response_buffer.push_back(((self.shell_open as u8) << 1) | 0b1);
+      }
+      0x22 => self.controller_response_buffer.extend(self.region().id_string()),
+      0x23 => self.controller_response_buffer.extend(b"CXA1782BR"),
+      0x24 => self.controller_response_buffer.extend(b"CXD2545Q"),
+      0x25 => self.controller_response_buffer.extend(b"CXD1815Q"),
+      0x40..=0x4f => self.controller_response_buffer.extend([0, 0]),
+      // sub cpu ram and decoder register reads
+      0x60 | 0x71 => self.controller_response_buffer.push_back(0),
+      _ => return self.error_response(0x10)
+    }
+
+    0x3
+  }
+
+  fn get_tn(&mut self) {
+    self.push_stat();
+
+    self.controller_response_buffer.push_back(Self::u8_to_bcd(self.toc.first_track()));
+    self.controller_response_buffer.push_back(Self::u8_to_bcd(self.toc.last_track()));
+  }
+
+  fn get_td(&mut self) -> u8 {
+    let track = Self::bcd_to_u8(self.controller_param_buffer.pop_front().unwrap_or(0));
+
+    // track 0 returns the start of the lead out area
+    let lba = if track == 0 {
+      Some(self.toc.lead_out)
+    } else {
+      self.toc.get_track(track).map(|track| track.start)
+    };
+
+    if let Some(lba) = lba {
+      let (mm, ss, _) = Toc::lba_to_msf(lba + LEAD_IN_SECTORS as usize);
+
+      self.push_stat();
+
+      self.controller_response_buffer.push_back(Self::u8_to_bcd(mm));
+      self.controller_response_buffer.push_back(Self::u8_to_bcd(ss));
+
+      0x3
+    } else {
+      // invalid parameter
+      self.error_response(0x10)
+    }
+  }
+
+  /// pushes an INT5 error response. seek errors (0x4) also set the seek error stat bit
+  fn error_response(&mut self, error: u8) -> u8 {
+    let mut stat = self.get_stat() | 0x1;
+
+    if error == 0x4 {
+      stat |= 0x4;
+    }
+
+    self.controller_response_buffer.push_back(stat);
+    self.controller_response_buffer.push_back(error);
+
+    0x5
   }
 
   fn getloc_p(&mut self) {
     self.controller_response_buffer.push_back(self.subq.track);
     self.controller_response_buffer.push_back(self.subq.index);
 
-    self.controller_response_buffer.push_back(Self::u8_to_bcd(self.subq.mm));
-    self.controller_response_buffer.push_back(Self::u8_to_bcd(self.subq.ss));
-    self.controller_response_buffer.push_back(Self::u8_to_bcd(self.subq.sect));
+    self.controller_response_buffer.push_back(self.subq.mm);
+    self.controller_response_buffer.push_back(self.subq.ss);
+    self.controller_response_buffer.push_back(self.subq.sect);
 
-    self.controller_response_buffer.push_back(Self::u8_to_bcd(self.subq.amm));
-    self.controller_response_buffer.push_back(Self::u8_to_bcd(self.subq.ass));
-    self.controller_response_buffer.push_back(Self::u8_to_bcd(self.subq.asect));
+    self.controller_response_buffer.push_back(self.subq.amm);
+    self.controller_response_buffer.push_back(self.subq.ass);
+    self.controller_response_buffer.push_back(self.subq.asect);
 
   }
 
-  fn getloc_l(&mut self) {
+  fn getloc_l(&mut self) -> u8 {
+    // the header is only known after reading a data sector
+    if self.is_playing || self.is_seeking {
+      return self.error_response(0x80);
+    }
+
     self.controller_response_buffer.push_back(Self::u8_to_bcd(self.sector_header.mm));
     self.controller_response_buffer.push_back(Self::u8_to_bcd(self.sector_header.ss));
-    self.controller_response_buffer.push_back(self.sector_header.sect);
+    self.controller_response_buffer.push_back(Self::u8_to_bcd(self.sector_header.sect));
 
     self.controller_response_buffer.push_back(self.sector_header.mode);
     self.controller_response_buffer.push_back(self.sector_subheader.file);
     self.controller_response_buffer.push_back(self.sector_subheader.channel);
     self.controller_response_buffer.push_back(self.sector_subheader.sub_mode);
     self.controller_response_buffer.push_back(self.sector_subheader.coding_info);
+
+    0x3
   }
 
   fn setfilter(&mut self) {
+    self.push_stat();
+
     let file = self.controller_param_buffer.pop_front().unwrap();
     let filter = self.controller_param_buffer.pop_front().unwrap();
 
@@ -910,21 +1708,62 @@ impl Cdrom {
   fn init(&mut self) {
     self.push_stat();
 
+    self.mode = 0;
     self.double_speed = false;
     self.sector_size = false;
 
     self.is_playing = false;
     self.is_reading = false;
     self.is_seeking = false;
+    self.scan = None;
+
+    self.subresponse = SubResponse::GetStat;
+    self.subresponse_cycles += 10 + self.start_motor();
+
+  }
+
+  fn motor_on(&mut self) -> u8 {
+    if self.motor != MotorState::Off {
+      return self.error_response(0x20);
+    }
+
+    self.push_stat();
 
     self.subresponse = SubResponse::GetStat;
-    self.subresponse_cycles += 10;
+    self.subresponse_cycles += 10 + self.start_motor();
+
+    0x3
+  }
+
+  fn stop(&mut self) {
+    self.push_stat();
+
+    // the second response comes once the motor has stopped
+    self.subresponse_cycles += if self.motor != MotorState::Off && self.timing == DriveTiming::Accurate {
+      SPIN_DOWN_CYCLES
+    } else {
+      10
+    };
+
+    self.is_playing = false;
+    self.is_reading = false;
+    self.is_seeking = false;
+    self.processing_seek = false;
+    self.scan = None;
 
+    self.drive_mode = DriveMode::Idle;
+    self.motor = MotorState::Off;
+
+    self.subresponse = SubResponse::GetStat;
   }
 
   fn setmode(&mut self) {
+    self.push_stat();
+
     let param = self.controller_param_buffer.pop_front().unwrap();
 
+    self.mode = param;
+
     self.double_speed = (param >> 7) & 0b1 == 1;
     self.send_adpcm_sectors = (param >> 6) & 0b1 == 1;
     self.sector_size = (param >> 5) & 0b1 == 1;
@@ -933,6 +1772,7 @@ impl Cdrom {
 
     self.xa_filter = (param >> 3) & 0b1 == 1;
     self.report_interrupts = (param >> 2) & 0b1 == 1;
+    self.auto_pause = (param >> 1) & 0b1 == 1;
 
   }
 
@@ -952,10 +1792,53 @@ impl Cdrom {
     self.is_playing = false;
     self.is_reading = false;
     self.is_seeking = false;
+    self.scan = None;
 
     self.subresponse = SubResponse::GetStat;
   }
 
+  fn play(&mut self) {
+    self.scan = None;
+
+    // an optional parameter selects the track to start playing from
+    if let Some(track) = self.controller_param_buffer.pop_front() {
+      let track = Self::bcd_to_u8(track);
+
+      if let Some(track) = self.toc.get_track(track) {
+        let (mm, ss, sect) = Toc::lba_to_msf(track.start + LEAD_IN_SECTORS as usize);
+
+        self.mm = mm;
+        self.ss = ss;
+        self.sect = sect;
+
+        self.processing_seek = true;
+      }
+    }
+
+    if self.processing_seek {
+      self.drive_mode = DriveMode::Seek;
+      self.next_drive_mode = DriveMode::Play;
+
+      self.is_seeking = true;
+      self.is_reading = false;
+      self.is_playing = false;
+
+      self.drive_cycles += self.seek_cycles(if self.double_speed { 14 } else { 28 });
+    } else {
+      self.drive_mode = DriveMode::Play;
+
+      self.is_playing = true;
+      self.is_reading = false;
+      self.is_seeking = false;
+
+      let divisor = if self.double_speed { 150 } else { 75 };
+
+      self.drive_cycles += 44100 / divisor + self.start_motor();
+    }
+
+    self.push_stat();
+  }
+
   fn readn(&mut self) {
     self.read_command(true);
   }
@@ -973,11 +1856,13 @@ impl Cdrom {
       self.is_reading = false;
       self.is_playing = false;
 
-      self.drive_cycles += if self.double_speed {
+      let fast_cycles = if self.double_speed {
         if is_readn { 140 } else { 14 }
       } else {
         if is_readn { 280 } else { 28 }
       };
+
+      self.drive_cycles += self.seek_cycles(fast_cycles);
     } else {
       self.drive_mode = DriveMode::Read;
 
@@ -991,7 +1876,7 @@ impl Cdrom {
         75
       };
 
-      self.drive_cycles += 44100 / divisor;
+      self.drive_cycles += 44100 / divisor + self.start_motor();
     }
 
     self.push_stat();
@@ -1002,14 +1887,26 @@ impl Cdrom {
     self.controller_response_buffer.push_back(stat);
   }
 
-  fn setloc(&mut self) {
+  fn setloc(&mut self) -> u8 {
+    let mm = self.controller_param_buffer.pop_front().unwrap();
+    let ss = self.controller_param_buffer.pop_front().unwrap();
+    let sect = self.controller_param_buffer.pop_front().unwrap();
+
+    let is_bcd = |value: u8| value & 0xf <= 9 && value >> 4 <= 9;
+
+    if !is_bcd(mm) || !is_bcd(ss) || !is_bcd(sect) || Self::bcd_to_u8(ss) >= 60 || Self::bcd_to_u8(sect) >= 75 {
+      return self.error_response(0x10);
+    }
+
     self.push_stat();
 
-    self.mm = Self::bcd_to_u8(self.controller_param_buffer.pop_front().unwrap());
-    self.ss = Self::bcd_to_u8(self.controller_param_buffer.pop_front().unwrap());
-    self.sect = Self::bcd_to_u8(self.controller_param_buffer.pop_front().unwrap());
+    self.mm = Self::bcd_to_u8(mm);
+    self.ss = Self::bcd_to_u8(ss);
+    self.sect = Self::bcd_to_u8(sect);
 
     self.processing_seek = true;
+
+    0x3
   }
 
   fn seek(&mut self) {
@@ -1018,11 +1915,7 @@ impl Cdrom {
     self.drive_mode = DriveMode::Seek;
     self.next_drive_mode = DriveMode::GetStat;
 
-    self.drive_cycles += if self.double_speed {
-      14
-    } else {
-      28
-    };
+    self.drive_cycles += self.seek_cycles(if self.double_speed { 14 } else { 28 });
 
     self.is_seeking = true;
     self.is_playing = false;
@@ -1032,6 +1925,8 @@ impl Cdrom {
   fn read_data_buffer(&mut self) -> u8 {
     let offset = if self.sector_size {
       ADDR_OFFSET
+    } else if self.data_buffer[HEADER_START + 3] == 1 {
+      MODE1_DATA_OFFSET
     } else {
       DATA_OFFSET
     };
@@ -1058,8 +1953,9 @@ impl Cdrom {
   }
 
   fn get_stat(&self) -> u8 {
-    // bit 1 is for the "motor on" status, should always be 1 in our case
-    let mut val = 0b10;
+    // bit 1 is for the "motor on" status, which stays off while spinning up
+    let mut val = ((self.motor == MotorState::On) as u8) << 1;
+    val |= ((self.shell_open || self.shell_was_open) as u8) << 4;
     val |= (self.is_playing as u8) << 7;
     val |= (self.is_seeking as u8) << 6;
     val |= (self.is_reading as u8) << 5;
@@ -1108,7 +2004,7 @@ impl Cdrom {
       1 => {
         match self.index {
           0 => self.command = Some(value),
-          3 => (), // cd audio to spu, unimplemented
+          3 => self.pending_volume.right_to_right = value,
           _ => panic!("offset 1 with index {} not implemented", self.index)
         }
       }
@@ -1116,8 +2012,8 @@ impl Cdrom {
         match self.index {
           0 => self.param_buffer.push_back(value),
           1 => self.interrupt_enable = value & 0x1f,
-          2 => (), // left cd audio to spu, not implemented
-          3 => (), // right cd audio to spu, not implemented
+          2 => self.pending_volume.left_to_left = value,
+          3 => self.pending_volume.right_to_left = value,
           _ => panic!("offset 2 with index {} not implemented yet", {self.index})
         }
       }
@@ -1136,9 +2032,10 @@ impl Cdrom {
             self.interrupt_flags &= !(value & 0x1f);
 
             if self.interrupt_flags == 0 && self.drive_interrupt_pending {
-              self.interrupt_flags = 1;
+              self.interrupt_flags = self.pending_interrupt;
               self.drive_interrupt_pending = false;
               self.response_buffer.push_back(self.pending_stat);
+              self.response_buffer.extend(self.pending_error.take());
             }
 
             self.response_buffer.clear();
@@ -1147,7 +2044,14 @@ impl Cdrom {
               self.param_buffer.clear();
             }
           }
-          2 | 3 => (), // more CD audio stuff
+          2 => self.pending_volume.left_to_right = value,
+          3 => {
+            self.adpcm_muted = value & 0b1 == 1;
+
+            if (value >> 5) & 0b1 == 1 {
+              self.volume = self.pending_volume;
+            }
+          }
           _ => panic!("offset 3 with index {} not implemented yet", self.index)
         }
       }
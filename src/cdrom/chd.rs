@@ -0,0 +1,930 @@
+use std::{io::{Cursor, Read, Seek, SeekFrom}, mem};
+
+use claxon::frame::FrameReader;
+use flate2::read::DeflateDecoder;
+use lzma_rs::decompress::raw::{LzmaDecoder, LzmaParams, LzmaProperties};
+
+use super::{disc::DiscImage, ecc::{self, SYNC_PATTERN}, toc::{Toc, Track, TrackType, COOKED_SECTOR_SIZE, MODE2_SECTOR_SIZE}, BYTES_PER_SECTOR};
+
+// per https://github.com/mamedev/mame/blob/master/src/lib/util/chd.h
+const CHD_MAGIC: &[u8; 8] = b"MComprHD";
+const V5_HEADER_SIZE: usize = 124;
+
+const CD_FRAME_SIZE: usize = 2448;
+const CD_SECTOR_DATA: usize = 2352;
+const CD_SUBCODE_DATA: usize = 96;
+// chdman pads every track out to a multiple of 4 frames
+const CD_TRACK_PADDING: usize = 4;
+
+const CODEC_NONE: u32 = 0;
+const CODEC_ZLIB: u32 = u32::from_be_bytes(*b"zlib");
+const CODEC_LZMA: u32 = u32::from_be_bytes(*b"lzma");
+const CODEC_CD_ZLIB: u32 = u32::from_be_bytes(*b"cdzl");
+const CODEC_CD_LZMA: u32 = u32::from_be_bytes(*b"cdlz");
+const CODEC_CD_FLAC: u32 = u32::from_be_bytes(*b"cdfl");
+
+const TRACK_METADATA_TAG: u32 = u32::from_be_bytes(*b"CHTR");
+const TRACK_METADATA2_TAG: u32 = u32::from_be_bytes(*b"CHT2");
+
+const COMPRESSION_TYPE_0: u8 = 0;
+const COMPRESSION_TYPE_3: u8 = 3;
+const COMPRESSION_NONE: u8 = 4;
+const COMPRESSION_SELF: u8 = 5;
+const COMPRESSION_PARENT: u8 = 6;
+const COMPRESSION_RLE_SMALL: u8 = 7;
+const COMPRESSION_RLE_LARGE: u8 = 8;
+const COMPRESSION_SELF_0: u8 = 9;
+const COMPRESSION_SELF_1: u8 = 10;
+const COMPRESSION_PARENT_SELF: u8 = 11;
+const COMPRESSION_PARENT_0: u8 = 12;
+const COMPRESSION_PARENT_1: u8 = 13;
+
+pub trait ChdReader: Read + Seek {}
+
+impl<T: Read + Seek> ChdReader for T {}
+
+struct BitReader<'a> {
+  data: &'a [u8],
+  position: usize
+}
+
+impl<'a> BitReader<'a> {
+  pub fn new(data: &'a [u8]) -> Self {
+    Self {
+      data,
+      position: 0
+    }
+  }
+
+  pub fn peek(&self, bits: u32) -> u32 {
+    let mut value = 0;
+
+    for i in 0..bits as usize {
+      let bit_position = self.position + i;
+      // reading past the end of the stream returns zeroes
+      let byte = self.data.get(bit_position / 8).copied().unwrap_or(0);
+
+      value = (value << 1) | ((byte >> (7 - bit_position % 8)) & 0b1) as u32;
+    }
+
+    value
+  }
+
+  pub fn read(&mut self, bits: u32) -> u32 {
+    let value = self.peek(bits);
+
+    self.position += bits as usize;
+
+    value
+  }
+}
+
+// canonical huffman decoder used by the compressed hunk map (16 codes, 8 bits max)
+struct HuffmanDecoder {
+  max_bits: u32,
+  lookup: Vec<(u8, u8)>
+}
+
+impl HuffmanDecoder {
+  pub fn import_tree_rle(reader: &mut BitReader, num_codes: usize, max_bits: u32) -> Result<Self, String> {
+    let num_bits = if max_bits >= 16 { 5 } else if max_bits >= 8 { 4 } else { 3 };
+
+    let mut code_lengths: Vec<u8> = Vec::with_capacity(num_codes);
+
+    while code_lengths.len() < num_codes {
+      let length = reader.read(num_bits) as u8;
+
+      if length != 1 {
+        code_lengths.push(length);
+      } else {
+        let length = reader.read(num_bits) as u8;
+
+        if length == 1 {
+          code_lengths.push(length);
+        } else {
+          let repeat = reader.read(num_bits) + 3;
+
+          for _ in 0..repeat {
+            code_lengths.push(length);
+          }
+        }
+      }
+    }
+
+    if code_lengths.len() != num_codes {
+      return Err("invalid huffman tree in hunk map".to_string());
+    }
+
+    // assign canonical codes, starting with the longest ones
+    let mut histogram = [0u32; 33];
+
+    for length in code_lengths.iter() {
+      if *length as u32 > max_bits {
+        return Err("invalid huffman code length in hunk map".to_string());
+      }
+      histogram[*length as usize] += 1;
+    }
+
+    let mut start = 0;
+
+    for length in (1..33).rev() {
+      let next_start = (start + histogram[length]) >> 1;
+
+      if length != 1 && next_start * 2 != start + histogram[length] {
+        return Err("inconsistent huffman tree in hunk map".to_string());
+      }
+
+      histogram[length] = start;
+      start = next_start;
+    }
+
+    let mut lookup = vec![(0, 0); 1 << max_bits];
+
+    for (symbol, length) in code_lengths.iter().enumerate() {
+      let length = *length as u32;
+
+      if length == 0 {
+        continue;
+      }
+
+      let code = histogram[length as usize];
+      histogram[length as usize] += 1;
+
+      let shift = max_bits - length;
+
+      for i in 0..(1 << shift) {
+        lookup[((code << shift) | i) as usize] = (symbol as u8, length as u8);
+      }
+    }
+
+    Ok(Self {
+      max_bits,
+      lookup
+    })
+  }
+
+  pub fn decode_one(&self, reader: &mut BitReader) -> u8 {
+    let (symbol, length) = self.lookup[reader.peek(self.max_bits) as usize];
+
+    reader.position += length as usize;
+
+    symbol
+  }
+}
+
+#[derive(Clone, Copy)]
+struct MapEntry {
+  compression: u8,
+  length: u32,
+  offset: u64
+}
+
+pub struct ChdTrack {
+  pub number: u8,
+  pub track_type: TrackType,
+  // bytes of sector data at the start of every frame
+  pub sector_size: usize,
+  pub frames: usize,
+  pub pregap: usize,
+  pub pregap_in_file: bool,
+  // index of the track's first frame inside the chd
+  pub chd_frame: usize
+}
+
+pub struct Chd {
+  reader: Box<dyn ChdReader>,
+  codecs: [u32; 4],
+  hunk_bytes: usize,
+  map: Vec<MapEntry>,
+  hunk: Vec<u8>,
+  cached_hunk: Option<usize>,
+  pub tracks: Vec<ChdTrack>,
+  toc: Toc
+}
+
+impl Chd {
+  pub fn is_chd(bytes: &[u8]) -> bool {
+    bytes.len() >= CHD_MAGIC.len() && &bytes[..CHD_MAGIC.len()] == CHD_MAGIC
+  }
+
+  pub fn open(mut reader: Box<dyn ChdReader>) -> Result<Self, String> {
+    let mut header = [0; V5_HEADER_SIZE];
+
+    reader.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+    reader.read_exact(&mut header).map_err(|_| "file is too small to be a chd".to_string())?;
+
+    if !Self::is_chd(&header) {
+      return Err("invalid chd header".to_string());
+    }
+
+    let version = Self::read_u32(&header, 12);
+
+    if version != 5 {
+      return Err(format!("unsupported chd version {version}, only v5 is supported"));
+    }
+
+    let mut codecs = [0; 4];
+
+    for (i, codec) in codecs.iter_mut().enumerate() {
+      *codec = Self::read_u32(&header, 16 + i * 4);
+    }
+
+    let logical_bytes = Self::read_u64(&header, 32);
+    let map_offset = Self::read_u64(&header, 40);
+    let meta_offset = Self::read_u64(&header, 48);
+    let hunk_bytes = Self::read_u32(&header, 56) as usize;
+    let unit_bytes = Self::read_u32(&header, 60) as usize;
+
+    if hunk_bytes == 0 || !hunk_bytes.is_multiple_of(CD_FRAME_SIZE) {
+      return Err(format!("invalid hunk size {hunk_bytes} for a cd image"));
+    }
+
+    let hunk_count = logical_bytes.div_ceil(hunk_bytes as u64) as usize;
+
+    let map = if codecs[0] == CODEC_NONE {
+      Self::read_uncompressed_map(&mut reader, map_offset, hunk_count, hunk_bytes)?
+    } else {
+      Self::read_compressed_map(&mut reader, map_offset, hunk_count, hunk_bytes, unit_bytes)?
+    };
+
+    let tracks = Self::read_tracks(&mut reader, meta_offset)?;
+
+    let toc = Self::build_toc(&tracks);
+
+    Ok(Self {
+      reader,
+      codecs,
+      hunk_bytes,
+      map,
+      hunk: vec![0; hunk_bytes],
+      cached_hunk: None,
+      tracks,
+      toc
+    })
+  }
+
+  fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+  }
+
+  fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+  }
+
+  fn read_u48(buf: &[u8], offset: usize) -> u64 {
+    (Self::read_u16(buf, offset) as u64) << 32 | Self::read_u32(buf, offset + 2) as u64
+  }
+
+  fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    (Self::read_u32(buf, offset) as u64) << 32 | Self::read_u32(buf, offset + 4) as u64
+  }
+
+  fn read_at(reader: &mut Box<dyn ChdReader>, offset: u64, buf: &mut [u8]) -> Result<(), String> {
+    reader.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+    reader.read_exact(buf).map_err(|e| format!("couldn't read chd at offset {offset:x}: {e}"))
+  }
+
+  fn read_uncompressed_map(reader: &mut Box<dyn ChdReader>, map_offset: u64, hunk_count: usize, hunk_bytes: usize) -> Result<Vec<MapEntry>, String> {
+    let mut raw_map = vec![0; hunk_count * 4];
+
+    Self::read_at(reader, map_offset, &mut raw_map)?;
+
+    Ok((0..hunk_count).map(|hunk| {
+      MapEntry {
+        compression: COMPRESSION_NONE,
+        length: hunk_bytes as u32,
+        offset: Self::read_u32(&raw_map, hunk * 4) as u64 * hunk_bytes as u64
+      }
+    }).collect())
+  }
+
+  fn read_compressed_map(
+    reader: &mut Box<dyn ChdReader>,
+    map_offset: u64,
+    hunk_count: usize,
+    hunk_bytes: usize,
+    unit_bytes: usize
+  ) -> Result<Vec<MapEntry>, String> {
+    let mut map_header = [0; 16];
+
+    Self::read_at(reader, map_offset, &mut map_header)?;
+
+    let map_bytes = Self::read_u32(&map_header, 0) as usize;
+    let first_offset = Self::read_u48(&map_header, 4);
+    let length_bits = map_header[12] as u32;
+    let hunk_bits = map_header[13] as u32;
+    let parent_bits = map_header[14] as u32;
+
+    let mut compressed = vec![0; map_bytes];
+
+    Self::read_at(reader, map_offset + 16, &mut compressed)?;
+
+    let mut bits = BitReader::new(&compressed);
+
+    let decoder = HuffmanDecoder::import_tree_rle(&mut bits, 16, 8)?;
+
+    // first pass decodes the compression type of every hunk
+    let mut compression_types = vec![0; hunk_count];
+
+    let mut last_compression = 0;
+    let mut repeat_count = 0;
+
+    for compression in compression_types.iter_mut() {
+      if repeat_count > 0 {
+        *compression = last_compression;
+        repeat_count -= 1;
+      } else {
+        let value = decoder.decode_one(&mut bits);
+
+        if value == COMPRESSION_RLE_SMALL {
+          *compression = last_compression;
+          repeat_count = 2 + decoder.decode_one(&mut bits) as usize;
+        } else if value == COMPRESSION_RLE_LARGE {
+          *compression = last_compression;
+          repeat_count = 2 + 16 + ((decoder.decode_one(&mut bits) as usize) << 4);
+          repeat_count += decoder.decode_one(&mut bits) as usize;
+        } else {
+          *compression = value;
+          last_compression = value;
+        }
+      }
+    }
+
+    // second pass reads the offsets and lengths
+    let mut map = Vec::with_capacity(hunk_count);
+
+    let mut current_offset = first_offset;
+    let mut last_self = 0;
+    let mut last_parent = 0;
+
+    for (hunk, compression) in compression_types.into_iter().enumerate() {
+      let mut entry = MapEntry {
+        compression,
+        length: 0,
+        offset: current_offset
+      };
+
+      match compression {
+        COMPRESSION_TYPE_0..=COMPRESSION_TYPE_3 => {
+          entry.length = bits.read(length_bits);
+          current_offset += entry.length as u64;
+          // crc
+          bits.read(16);
+        }
+        COMPRESSION_NONE => {
+          entry.length = hunk_bytes as u32;
+          current_offset += hunk_bytes as u64;
+          bits.read(16);
+        }
+        COMPRESSION_SELF => {
+          last_self = bits.read(hunk_bits) as u64;
+          entry.offset = last_self;
+        }
+        COMPRESSION_PARENT => {
+          last_parent = bits.read(parent_bits) as u64;
+          entry.offset = last_parent;
+        }
+        COMPRESSION_SELF_0 | COMPRESSION_SELF_1 => {
+          if compression == COMPRESSION_SELF_1 {
+            last_self += 1;
+          }
+          entry.compression = COMPRESSION_SELF;
+          entry.offset = last_self;
+        }
+        COMPRESSION_PARENT_SELF => {
+          last_parent = (hunk as u64 * hunk_bytes as u64) / unit_bytes as u64;
+          entry.compression = COMPRESSION_PARENT;
+          entry.offset = last_parent;
+        }
+        COMPRESSION_PARENT_0 | COMPRESSION_PARENT_1 => {
+          if compression == COMPRESSION_PARENT_1 {
+            last_parent += (hunk_bytes / unit_bytes) as u64;
+          }
+          entry.compression = COMPRESSION_PARENT;
+          entry.offset = last_parent;
+        }
+        _ => return Err(format!("invalid compression type {compression} in hunk map"))
+      }
+
+      map.push(entry);
+    }
+
+    Ok(map)
+  }
+
+  fn read_tracks(reader: &mut Box<dyn ChdReader>, meta_offset: u64) -> Result<Vec<ChdTrack>, String> {
+    let mut tracks: Vec<ChdTrack> = Vec::new();
+
+    let mut offset = meta_offset;
+
+    let mut chd_frame = 0;
+
+    while offset != 0 {
+      let mut entry_header = [0; 16];
+
+      Self::read_at(reader, offset, &mut entry_header)?;
+
+      let tag = Self::read_u32(&entry_header, 0);
+      let length = (Self::read_u32(&entry_header, 4) & 0xff_ffff) as usize;
+      let next = Self::read_u64(&entry_header, 8);
+
+      if tag == TRACK_METADATA_TAG || tag == TRACK_METADATA2_TAG {
+        let mut data = vec![0; length];
+
+        Self::read_at(reader, offset + 16, &mut data)?;
+
+        let metadata = String::from_utf8_lossy(&data);
+
+        let track = Self::parse_track_metadata(metadata.trim_end_matches('\0'), chd_frame)?;
+
+        chd_frame += track.frames.div_ceil(CD_TRACK_PADDING) * CD_TRACK_PADDING;
+
+        tracks.push(track);
+      }
+
+      offset = next;
+    }
+
+    if tracks.is_empty() {
+      return Err("chd doesn't contain any cd track metadata".to_string());
+    }
+
+    Ok(tracks)
+  }
+
+  fn parse_track_metadata(metadata: &str, chd_frame: usize) -> Result<ChdTrack, String> {
+    // ie: "TRACK:1 TYPE:MODE2_RAW SUBTYPE:NONE FRAMES:1234 PREGAP:0 PGTYPE:MODE2_RAW PGSUB:RW POSTGAP:0"
+    let mut track = ChdTrack {
+      number: 0,
+      track_type: TrackType::Mode2,
+      sector_size: BYTES_PER_SECTOR as usize,
+      frames: 0,
+      pregap: 0,
+      pregap_in_file: false,
+      chd_frame
+    };
+
+    for field in metadata.split_whitespace() {
+      let (key, value) = field.split_once(':').unwrap_or((field, ""));
+
+      let parse_number = || value.parse::<usize>().map_err(|_| format!("invalid track metadata: {metadata}"));
+
+      match key {
+        "TRACK" => track.number = parse_number()? as u8,
+        "TYPE" => {
+          let raw = BYTES_PER_SECTOR as usize;
+
+          (track.track_type, track.sector_size) = match value {
+            "AUDIO" => (TrackType::Audio, raw),
+            "MODE1_RAW" | "MODE1/2352" => (TrackType::Mode1, raw),
+            "MODE1" | "MODE1/2048" => (TrackType::Mode1, COOKED_SECTOR_SIZE),
+            "MODE2_RAW" | "MODE2/2352" | "CDI/2352" => (TrackType::Mode2, raw),
+            "MODE2" | "MODE2_FORM_MIX" | "MODE2/2336" => (TrackType::Mode2, MODE2_SECTOR_SIZE),
+            "MODE2_FORM1" | "MODE2/2048" => (TrackType::Mode2, COOKED_SECTOR_SIZE),
+            _ => return Err(format!("unsupported chd track type {value}"))
+          };
+        }
+        "FRAMES" => track.frames = parse_number()?,
+        "PREGAP" => track.pregap = parse_number()?,
+        // a 'V' prefix means the pregap is stored in the image
+        "PGTYPE" => track.pregap_in_file = value.starts_with('V'),
+        _ => ()
+      }
+    }
+
+    Ok(track)
+  }
+
+  fn build_toc(chd_tracks: &[ChdTrack]) -> Toc {
+    let mut tracks = Vec::new();
+
+    let mut lba = 0;
+
+    for (index, chd_track) in chd_tracks.iter().enumerate() {
+      // the first track's pregap is the lead in area, which isn't addressable
+      let pregap = if index == 0 && !chd_track.pregap_in_file { 0 } else { chd_track.pregap };
+
+      let track = if chd_track.pregap_in_file {
+        Track {
+          number: chd_track.number,
+          track_type: chd_track.track_type,
+          sector_size: chd_track.sector_size,
+          file_index: index,
+          file_lba: lba,
+          data_start: lba,
+          pregap_start: lba,
+          start: lba + pregap,
+          end: lba + chd_track.frames
+        }
+      } else {
+        Track {
+          number: chd_track.number,
+          track_type: chd_track.track_type,
+          sector_size: chd_track.sector_size,
+          file_index: index,
+          file_lba: lba + pregap,
+          data_start: lba + pregap,
+          pregap_start: lba,
+          start: lba + pregap,
+          end: lba + pregap + chd_track.frames
+        }
+      };
+
+      lba = track.end;
+
+      tracks.push(track);
+    }
+
+    Toc {
+      tracks,
+      lead_out: lba
+    }
+  }
+
+  /// reads a sector of a track, where offset is the byte offset relative to the
+  /// track's first frame (in sectors of the track's sector size)
+  pub fn read(&mut self, track_index: usize, offset: u64, buf: &mut [u8]) -> Result<(), String> {
+    let track = &self.tracks[track_index];
+
+    let is_audio = track.track_type == TrackType::Audio;
+    let sector_size = track.sector_size as u64;
+
+    let frame = track.chd_frame + (offset / sector_size) as usize;
+    let frame_offset = (offset % sector_size) as usize;
+
+    let frames_per_hunk = self.hunk_bytes / CD_FRAME_SIZE;
+
+    let hunk = frame / frames_per_hunk;
+
+    if self.cached_hunk != Some(hunk) {
+      let mut data = vec![0; self.hunk_bytes];
+
+      self.read_hunk(hunk, &mut data)?;
+
+      self.hunk = data;
+      self.cached_hunk = Some(hunk);
+    }
+
+    let start = (frame % frames_per_hunk) * CD_FRAME_SIZE + frame_offset;
+    let length = buf.len().min(CD_SECTOR_DATA - frame_offset);
+
+    buf[..length].copy_from_slice(&self.hunk[start..start + length]);
+
+    // audio is stored big endian in chd files
+    if is_audio {
+      for sample in buf[..length].chunks_exact_mut(2) {
+        sample.swap(0, 1);
+      }
+    }
+
+    Ok(())
+  }
+
+  fn read_hunk(&mut self, hunk: usize, dest: &mut [u8]) -> Result<(), String> {
+    let entry = *self.map.get(hunk).ok_or_else(|| format!("hunk {hunk} out of range"))?;
+
+    match entry.compression {
+      COMPRESSION_TYPE_0..=COMPRESSION_TYPE_3 => {
+        let mut compressed = vec![0; entry.length as usize];
+
+        Self::read_at(&mut self.reader, entry.offset, &mut compressed)?;
+
+        let codec = self.codecs[entry.compression as usize];
+
+        Self::decompress(codec, &compressed, dest)
+      }
+      COMPRESSION_NONE => {
+        if entry.offset == 0 {
+          dest.fill(0);
+
+          return Ok(());
+        }
+
+        Self::read_at(&mut self.reader, entry.offset, dest)
+      }
+      // only earlier hunks can be referenced, so a corrupt map can't loop forever
+      COMPRESSION_SELF if entry.offset < hunk as u64 => self.read_hunk(entry.offset as usize, dest),
+      COMPRESSION_SELF => Err(format!("hunk {hunk} references hunk {}", entry.offset)),
+      COMPRESSION_PARENT => Err("chd files with a parent are not supported".to_string()),
+      _ => Err(format!("invalid compression type {}", entry.compression))
+    }
+  }
+
+  fn decompress(codec: u32, src: &[u8], dest: &mut [u8]) -> Result<(), String> {
+    match codec {
+      CODEC_ZLIB => Self::inflate(src, dest),
+      CODEC_LZMA => Self::decompress_lzma(src, dest),
+      CODEC_CD_ZLIB | CODEC_CD_LZMA => Self::decompress_cd(codec, src, dest),
+      CODEC_CD_FLAC => Self::decompress_cd_flac(src, dest),
+      _ => Err(format!("unsupported chd codec {}", String::from_utf8_lossy(&codec.to_be_bytes())))
+    }
+  }
+
+  fn inflate(src: &[u8], dest: &mut [u8]) -> Result<(), String> {
+    DeflateDecoder::new(src).read_exact(dest).map_err(|e| format!("zlib error: {e}"))
+  }
+
+  fn decompress_lzma(src: &[u8], dest: &mut [u8]) -> Result<(), String> {
+    // the properties aren't stored in the chd, they're derived from the
+    // hunk size the same way the compressor derives them
+    let mut dict_size = 1 << 26;
+
+    let reduce_size = dest.len() as u32;
+
+    if dict_size > reduce_size {
+      for i in 11..=30 {
+        if reduce_size <= (2 << i) {
+          dict_size = 2 << i;
+          break;
+        }
+        if reduce_size <= (3 << i) {
+          dict_size = 3 << i;
+          break;
+        }
+      }
+    }
+
+    let properties = LzmaProperties {
+      lc: 3,
+      lp: 0,
+      pb: 2
+    };
+
+    let params = LzmaParams::new(properties, dict_size, Some(dest.len() as u64));
+
+    let mut decoder = LzmaDecoder::new(params, None).map_err(|e| format!("lzma error: {e}"))?;
+
+    let mut output = Vec::with_capacity(dest.len());
+
+    decoder.decompress(&mut Cursor::new(src), &mut output).map_err(|e| format!("lzma error: {e}"))?;
+
+    if output.len() != dest.len() {
+      return Err("lzma error: hunk decompressed to the wrong size".to_string());
+    }
+
+    dest.copy_from_slice(&output);
+
+    Ok(())
+  }
+
+  fn decompress_cd(codec: u32, src: &[u8], dest: &mut [u8]) -> Result<(), String> {
+    let frames = dest.len() / CD_FRAME_SIZE;
+
+    // the header is a bitmap of sectors that need their sync and ecc regenerated,
+    // followed by the length of the sector data
+    let complen_bytes = if dest.len() < 65536 { 2 } else { 3 };
+    let ecc_bytes = frames.div_ceil(8);
+    let header_bytes = ecc_bytes + complen_bytes;
+
+    if src.len() < header_bytes {
+      return Err("cd hunk is too small".to_string());
+    }
+
+    let mut complen_base = Self::read_u16(src, ecc_bytes) as usize;
+
+    if complen_bytes > 2 {
+      complen_base = (complen_base << 8) | src[ecc_bytes + 2] as usize;
+    }
+
+    let base_end = header_bytes + complen_base;
+
+    if base_end > src.len() {
+      return Err("cd hunk has an invalid length".to_string());
+    }
+
+    let mut sector_data = vec![0; frames * CD_SECTOR_DATA];
+    let mut subcode_data = vec![0; frames * CD_SUBCODE_DATA];
+
+    if codec == CODEC_CD_LZMA {
+      Self::decompress_lzma(&src[header_bytes..base_end], &mut sector_data)?;
+    } else {
+      Self::inflate(&src[header_bytes..base_end], &mut sector_data)?;
+    }
+
+    Self::inflate(&src[base_end..], &mut subcode_data)?;
+
+    for frame in 0..frames {
+      let sector = &mut dest[frame * CD_FRAME_SIZE..(frame + 1) * CD_FRAME_SIZE];
+
+      sector[..CD_SECTOR_DATA].copy_from_slice(&sector_data[frame * CD_SECTOR_DATA..(frame + 1) * CD_SECTOR_DATA]);
+      sector[CD_SECTOR_DATA..].copy_from_slice(&subcode_data[frame * CD_SUBCODE_DATA..(frame + 1) * CD_SUBCODE_DATA]);
+
+      if (src[frame / 8] >> (frame % 8)) & 0b1 == 1 {
+        sector[..SYNC_PATTERN.len()].copy_from_slice(&SYNC_PATTERN);
+        ecc::generate(sector);
+      }
+    }
+
+    Ok(())
+  }
+
+  fn decompress_cd_flac(src: &[u8], dest: &mut [u8]) -> Result<(), String> {
+    let frames = dest.len() / CD_FRAME_SIZE;
+
+    let mut sector_data = vec![0; frames * CD_SECTOR_DATA];
+    let mut subcode_data = vec![0; frames * CD_SUBCODE_DATA];
+
+    // the audio is stored as raw flac frames (no stream header), 16 bit stereo
+    let mut reader = FrameReader::new(Cursor::new(src));
+
+    let mut position = 0;
+    let mut buffer = Vec::new();
+
+    while position < sector_data.len() {
+      let block = match reader.read_next_or_eof(buffer) {
+        Ok(Some(block)) => block,
+        Ok(None) => return Err("flac error: unexpected end of stream".to_string()),
+        Err(e) => return Err(format!("flac error: {e}"))
+      };
+
+      for (left, right) in block.stereo_samples() {
+        if position >= sector_data.len() {
+          break;
+        }
+
+        sector_data[position..position + 2].copy_from_slice(&(left as i16).to_be_bytes());
+        sector_data[position + 2..position + 4].copy_from_slice(&(right as i16).to_be_bytes());
+
+        position += 4;
+      }
+
+      buffer = block.into_buffer();
+    }
+
+    let offset = reader.into_inner().position() as usize;
+
+    Self::inflate(&src[offset..], &mut subcode_data)?;
+
+    for frame in 0..frames {
+      let sector = &mut dest[frame * CD_FRAME_SIZE..(frame + 1) * CD_FRAME_SIZE];
+
+      sector[..CD_SECTOR_DATA].copy_from_slice(&sector_data[frame * CD_SECTOR_DATA..(frame + 1) * CD_SECTOR_DATA]);
+      sector[CD_SECTOR_DATA..].copy_from_slice(&subcode_data[frame * CD_SUBCODE_DATA..(frame + 1) * CD_SUBCODE_DATA]);
+    }
+
+    Ok(())
+  }
+}
+
+impl DiscImage for Chd {
+  fn toc(&self) -> &Toc {
+    &self.toc
+  }
+
+  fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), String> {
+    // the toc is moved out while reading so the closure can borrow self mutably
+    let toc = mem::take(&mut self.toc);
+
+    let result = toc.read_sector(lba, buf, |track_index, offset, buf| self.read(track_index, offset, buf));
+
+    self.toc = toc;
+
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FRAMES: usize = 6;
+
+  fn metadata(next: u64, text: &str) -> Vec<u8> {
+    let mut entry = TRACK_METADATA2_TAG.to_be_bytes().to_vec();
+
+    entry.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+    entry.extend_from_slice(&next.to_be_bytes());
+    entry.extend_from_slice(text.as_bytes());
+    entry.push(0);
+
+    entry
+  }
+
+  // an uncompressed chd with one frame per hunk: a two frame data track, padding
+  // up to a multiple of 4 frames, then a two frame audio track
+  fn chd() -> Vec<u8> {
+    let hunk_bytes = CD_FRAME_SIZE;
+
+    let mut bytes = vec![0; hunk_bytes * (1 + FRAMES)];
+
+    bytes[..8].copy_from_slice(CHD_MAGIC);
+    bytes[8..12].copy_from_slice(&(V5_HEADER_SIZE as u32).to_be_bytes());
+    bytes[12..16].copy_from_slice(&5u32.to_be_bytes());
+    bytes[32..40].copy_from_slice(&((FRAMES * hunk_bytes) as u64).to_be_bytes());
+    bytes[40..48].copy_from_slice(&(V5_HEADER_SIZE as u64).to_be_bytes());
+    bytes[48..56].copy_from_slice(&0x100u64.to_be_bytes());
+    bytes[56..60].copy_from_slice(&(hunk_bytes as u32).to_be_bytes());
+    bytes[60..64].copy_from_slice(&(CD_FRAME_SIZE as u32).to_be_bytes());
+
+    // the hunks are stored after the header, map and metadata
+    for hunk in 0..FRAMES {
+      let offset = V5_HEADER_SIZE + hunk * 4;
+
+      bytes[offset..offset + 4].copy_from_slice(&(hunk as u32 + 1).to_be_bytes());
+    }
+
+    let track1 = metadata(0x200, "TRACK:1 TYPE:MODE2_RAW SUBTYPE:NONE FRAMES:2 PREGAP:0 PGTYPE:MODE1 PGSUB:RW POSTGAP:0");
+    let track2 = metadata(0, "TRACK:2 TYPE:AUDIO SUBTYPE:NONE FRAMES:2 PREGAP:150 PGTYPE:MODE1 PGSUB:RW POSTGAP:0");
+
+    bytes[0x100..0x100 + track1.len()].copy_from_slice(&track1);
+    bytes[0x200..0x200 + track2.len()].copy_from_slice(&track2);
+
+    for frame in 0..FRAMES {
+      let offset = hunk_bytes * (1 + frame);
+
+      bytes[offset..offset + CD_SECTOR_DATA].fill(frame as u8);
+      bytes[offset] = 0xa0 | frame as u8;
+    }
+
+    bytes
+  }
+
+  fn open() -> Chd {
+    Chd::open(Box::new(Cursor::new(chd()))).unwrap()
+  }
+
+  #[test]
+  fn header() {
+    assert!(Chd::is_chd(&chd()));
+    assert!(!Chd::is_chd(b"MComp"));
+
+    let mut bytes = chd();
+    bytes[15] = 4;
+
+    assert!(Chd::open(Box::new(Cursor::new(bytes))).is_err());
+
+    let mut bytes = chd();
+    bytes[59] = 0;
+
+    assert!(Chd::open(Box::new(Cursor::new(bytes))).is_err());
+  }
+
+  #[test]
+  fn tracks() {
+    let chd = open();
+
+    assert_eq!(chd.tracks.len(), 2);
+    assert_eq!(chd.tracks[1].chd_frame, 4);
+    assert_eq!(chd.tracks[1].track_type, TrackType::Audio);
+
+    let toc = chd.toc();
+
+    assert_eq!((toc.tracks[0].start, toc.tracks[0].end), (0, 2));
+    // the audio track's pregap isn't stored in the file
+    assert_eq!((toc.tracks[1].pregap_start, toc.tracks[1].start, toc.tracks[1].end), (2, 152, 154));
+    assert_eq!(toc.lead_out, 154);
+  }
+
+  #[test]
+  fn read_sectors() {
+    let mut chd = open();
+    let mut buf = vec![0xff; BYTES_PER_SECTOR as usize];
+
+    chd.read_sector(1, &mut buf).unwrap();
+
+    assert_eq!(buf[..2], [0xa1, 1]);
+
+    // audio samples are swapped to little endian
+    chd.read_sector(153, &mut buf).unwrap();
+
+    assert_eq!(buf[..2], [5, 0xa5]);
+
+    chd.read_sector(100, &mut buf).unwrap();
+
+    assert!(buf.iter().all(|b| *b == 0));
+  }
+
+  #[test]
+  fn self_references() {
+    let mut chd = open();
+    let mut buf = vec![0; CD_FRAME_SIZE];
+
+    chd.map[3] = MapEntry { compression: COMPRESSION_SELF, length: 0, offset: 1 };
+    chd.read_hunk(3, &mut buf).unwrap();
+
+    assert_eq!(buf[..2], [0xa1, 1]);
+
+    // a hunk can't refer to itself or a later hunk
+    chd.map[3].offset = 3;
+    assert!(chd.read_hunk(3, &mut buf).is_err());
+
+    chd.map[3].offset = 5;
+    assert!(chd.read_hunk(3, &mut buf).is_err());
+  }
+
+  #[test]
+  fn track_metadata() {
+    let track = Chd::parse_track_metadata("TRACK:3 TYPE:MODE1 SUBTYPE:NONE FRAMES:300 PREGAP:150 PGTYPE:VMODE1 PGSUB:RW POSTGAP:0", 8).unwrap();
+
+    assert_eq!(track.number, 3);
+    assert_eq!(track.track_type, TrackType::Mode1);
+    assert_eq!(track.sector_size, COOKED_SECTOR_SIZE);
+    assert_eq!(track.frames, 300);
+    assert_eq!(track.pregap, 150);
+    assert!(track.pregap_in_file);
+    assert_eq!(track.chd_frame, 8);
+
+    assert!(Chd::parse_track_metadata("TRACK:1 TYPE:MODE3", 0).is_err());
+    assert!(Chd::parse_track_metadata("TRACK:1 TYPE:AUDIO FRAMES:x", 0).is_err());
+  }
+}
@@ -0,0 +1,265 @@
+use super::{toc::{Toc, Track, TrackType, COOKED_SECTOR_SIZE, MODE2_SECTOR_SIZE}, BYTES_PER_SECTOR};
+
+pub struct CueFile {
+  pub filename: String,
+  pub tracks: Vec<CueTrack>
+}
+
+pub struct CueTrack {
+  pub number: u8,
+  pub track_type: TrackType,
+  pub sector_size: usize,
+  pub pregap: usize,
+  pub index0: Option<usize>,
+  pub index1: Option<usize>
+}
+
+pub struct CueSheet {
+  pub files: Vec<CueFile>
+}
+
+impl CueSheet {
+  pub fn parse(contents: &str) -> Result<Self, String> {
+    let mut files: Vec<CueFile> = Vec::new();
+
+    for (line_number, line) in contents.lines().enumerate() {
+      let line = line.trim();
+
+      if line.is_empty() {
+        continue;
+      }
+
+      let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+      let args = args.trim();
+
+      let error = |message: &str| format!("line {}: {message}", line_number + 1);
+
+      match command.to_uppercase().as_str() {
+        "FILE" => {
+          let filename = if let Some(rest) = args.strip_prefix('"') {
+            let end = rest.find('"').ok_or_else(|| error("unterminated file name"))?;
+
+            rest[..end].to_string()
+          } else {
+            args.split_whitespace().next().ok_or_else(|| error("missing file name"))?.to_string()
+          };
+
+          files.push(CueFile {
+            filename,
+            tracks: Vec::new()
+          });
+        }
+        "TRACK" => {
+          let file = files.last_mut().ok_or_else(|| error("TRACK found before FILE"))?;
+
+          let mut parts = args.split_whitespace();
+
+          let number = parts
+            .next()
+            .and_then(|number| number.parse::<u8>().ok())
+            .ok_or_else(|| error("invalid track number"))?;
+
+          let raw = BYTES_PER_SECTOR as usize;
+
+          let (track_type, sector_size) = match parts.next().map(|t| t.to_uppercase()).as_deref() {
+            Some("AUDIO") => (TrackType::Audio, raw),
+            Some("MODE1/2352") => (TrackType::Mode1, raw),
+            Some("MODE1/2048") => (TrackType::Mode1, COOKED_SECTOR_SIZE),
+            Some("MODE2/2352") => (TrackType::Mode2, raw),
+            Some("MODE2/2336") => (TrackType::Mode2, MODE2_SECTOR_SIZE),
+            Some("MODE2/2048") => (TrackType::Mode2, COOKED_SECTOR_SIZE),
+            Some(t) => return Err(error(&format!("unsupported track type {t}"))),
+            None => return Err(error("missing track type"))
+          };
+
+          file.tracks.push(CueTrack {
+            number,
+            track_type,
+            sector_size,
+            pregap: 0,
+            index0: None,
+            index1: None
+          });
+        }
+        "INDEX" => {
+          let track = files
+            .last_mut()
+            .and_then(|file| file.tracks.last_mut())
+            .ok_or_else(|| error("INDEX found before TRACK"))?;
+
+          let mut parts = args.split_whitespace();
+
+          let index = parts
+            .next()
+            .and_then(|index| index.parse::<u8>().ok())
+            .ok_or_else(|| error("invalid index number"))?;
+
+          let position = parts
+            .next()
+            .and_then(Self::parse_msf)
+            .ok_or_else(|| error("invalid index position"))?;
+
+          match index {
+            0 => track.index0 = Some(position),
+            1 => track.index1 = Some(position),
+            // sub indexes don't affect the layout of the disc
+            _ => ()
+          }
+        }
+        "PREGAP" => {
+          let track = files
+            .last_mut()
+            .and_then(|file| file.tracks.last_mut())
+            .ok_or_else(|| error("PREGAP found before TRACK"))?;
+
+          track.pregap = Self::parse_msf(args).ok_or_else(|| error("invalid pregap length"))?;
+        }
+        // metadata that doesn't matter for emulation
+        "REM" | "CATALOG" | "CDTEXTFILE" | "TITLE" | "PERFORMER" | "SONGWRITER" | "FLAGS" | "ISRC" | "POSTGAP" => (),
+        _ => return Err(error(&format!("unknown command {command}")))
+      }
+    }
+
+    if files.iter().all(|file| file.tracks.is_empty()) {
+      return Err("cue sheet doesn't contain any tracks".to_string());
+    }
+
+    Ok(Self {
+      files
+    })
+  }
+
+  fn parse_msf(msf: &str) -> Option<usize> {
+    let mut parts = msf.trim().split(':').map(|part| part.parse::<u8>().ok());
+
+    let mm = parts.next()??;
+    let ss = parts.next()??;
+    let sect = parts.next()??;
+
+    if parts.next().is_some() {
+      return None;
+    }
+
+    Some(Toc::msf_to_lba(mm, ss, sect))
+  }
+
+  /// builds the disc layout given the size (in sectors) of every file
+  pub fn build_toc(&self, file_sectors: &[usize]) -> Result<Toc, String> {
+    let mut tracks: Vec<Track> = Vec::new();
+
+    let mut disc_lba = 0;
+
+    for (file_index, file) in self.files.iter().enumerate() {
+      let num_sectors = file_sectors[file_index];
+
+      let mut shift = 0;
+
+      for cue_track in file.tracks.iter() {
+        let index1 = cue_track.index1.ok_or_else(|| format!("track {} is missing INDEX 01", cue_track.number))?;
+
+        shift += cue_track.pregap;
+
+        let file_lba = disc_lba + shift;
+        let start = file_lba + index1;
+
+        let (pregap_start, data_start) = if let Some(index0) = cue_track.index0 {
+          (file_lba + index0, file_lba + index0)
+        } else {
+          (start - cue_track.pregap, start)
+        };
+
+        if let Some(previous) = tracks.last_mut() {
+          previous.end = pregap_start;
+        }
+
+        tracks.push(Track {
+          number: cue_track.number,
+          track_type: cue_track.track_type,
+          sector_size: cue_track.sector_size,
+          file_index,
+          file_lba,
+          data_start,
+          pregap_start,
+          start,
+          end: file_lba + num_sectors
+        });
+      }
+
+      disc_lba += shift + num_sectors;
+    }
+
+    Ok(Toc {
+      tracks,
+      lead_out: disc_lba
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn single_file() {
+    let cue = CueSheet::parse(
+      "REM a comment\r\nFILE \"My Game (USA).bin\" BINARY\r\n  TRACK 01 MODE2/2352\r\n    INDEX 01 00:00:00\r\n  TRACK 02 AUDIO\r\n    FLAGS DCP\r\n    INDEX 00 00:10:00\r\n    INDEX 01 00:12:00\r\n"
+    ).unwrap();
+
+    assert_eq!(cue.files.len(), 1);
+    assert_eq!(cue.files[0].filename, "My Game (USA).bin");
+
+    let tracks = &cue.files[0].tracks;
+
+    assert_eq!(tracks[0].track_type, TrackType::Mode2);
+    assert_eq!(tracks[1].track_type, TrackType::Audio);
+    assert_eq!(tracks[1].index0, Some(750));
+    assert_eq!(tracks[1].index1, Some(900));
+
+    let toc = cue.build_toc(&[1000]).unwrap();
+
+    assert_eq!(toc.lead_out, 1000);
+
+    let (data, audio) = (&toc.tracks[0], &toc.tracks[1]);
+
+    assert_eq!((data.start, data.end), (0, 750));
+    assert_eq!((audio.pregap_start, audio.data_start, audio.start, audio.end), (750, 750, 900, 1000));
+    assert_eq!(audio.file_lba, 0);
+  }
+
+  #[test]
+  fn file_per_track_with_pregap() {
+    let cue = CueSheet::parse(
+      "FILE track1.bin BINARY\nTRACK 01 MODE1/2048\nINDEX 01 00:00:00\nFILE \"track2.bin\" BINARY\nTRACK 02 AUDIO\nPREGAP 00:02:00\nINDEX 01 00:00:00\n"
+    ).unwrap();
+
+    assert_eq!(cue.files[0].filename, "track1.bin");
+    assert_eq!(cue.files[0].tracks[0].sector_size, COOKED_SECTOR_SIZE);
+
+    let toc = cue.build_toc(&[500, 300]).unwrap();
+
+    assert_eq!(toc.lead_out, 950);
+
+    let (data, audio) = (&toc.tracks[0], &toc.tracks[1]);
+
+    assert_eq!((data.start, data.end), (0, 500));
+
+    // the pregap isn't in the file, so it has to be synthesized
+    assert_eq!(audio.file_index, 1);
+    assert_eq!((audio.pregap_start, audio.data_start, audio.start, audio.end), (500, 650, 650, 950));
+    assert_eq!(audio.file_lba, 650);
+  }
+
+  #[test]
+  fn errors() {
+    assert!(CueSheet::parse("TRACK 01 AUDIO\n").is_err());
+    assert!(CueSheet::parse("FILE a.bin BINARY\nTRACK 01 MODE3/2352\n").is_err());
+    assert!(CueSheet::parse("FILE a.bin BINARY\nTRACK 01 AUDIO\nINDEX 01 00:00\n").is_err());
+    assert!(CueSheet::parse("FILE a.bin BINARY\nTRACK 01 AUDIO\nINDEX 01 00:00:00\nSESSION 2\n").is_err());
+    assert!(CueSheet::parse("FILE \"a.bin BINARY\n").is_err());
+    assert!(CueSheet::parse("FILE a.bin BINARY\n").is_err());
+
+    let cue = CueSheet::parse("FILE a.bin BINARY\nTRACK 01 AUDIO\nINDEX 00 00:00:00\n").unwrap();
+
+    assert!(cue.build_toc(&[100]).is_err());
+  }
+}
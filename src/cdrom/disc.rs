@@ -0,0 +1,202 @@
+use std::{fs::{self, File}, io::{Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use super::{chd::Chd, cue::CueSheet, ecc::SYNC_PATTERN, subchannel::{PatchedDisc, SubchannelPatch}, toc::{Toc, Track, COOKED_SECTOR_SIZE}, SubchannelQ, BYTES_PER_SECTOR};
+
+/// a source of raw 2352 byte CD sectors. implement this to feed the drive from
+/// anything other than the built in image formats (archives, network streams, etc)
+pub trait DiscImage {
+  fn toc(&self) -> &Toc;
+
+  /// reads the raw sector (sync, header and data) at the given LBA, where LBA 0
+  /// is at 00:02:00
+  fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), String>;
+
+  fn tracks(&self) -> &[Track] {
+    &self.toc().tracks
+  }
+
+  fn subchannel_q(&self, lba: usize) -> SubchannelQ {
+    self.toc().subchannel_q(lba)
+  }
+}
+
+/// opens a disc image, picking the format from the file extension. a .sbi or
+/// .lsd file next to the image is applied to the disc's subchannel data
+pub fn open_disc(path: &Path) -> Result<Box<dyn DiscImage>, String> {
+  let extension = path.extension().unwrap_or_default().to_str().unwrap_or_default().to_lowercase();
+
+  let open_file = || File::open(path).map_err(|e| format!("couldn't open {}: {e}", path.display()));
+
+  let disc: Box<dyn DiscImage> = match extension.as_str() {
+    "cue" => Box::new(CueImage::open(path)?),
+    "chd" => Box::new(Chd::open(Box::new(open_file()?))?),
+    _ => Box::new(BinImage::new(open_file()?)?)
+  };
+
+  // libcrypt protected games ship their subchannel data in a separate file
+  match SubchannelPatch::find(path)? {
+    Some(patch) => Ok(Box::new(PatchedDisc::new(disc, patch))),
+    None => Ok(disc)
+  }
+}
+
+/// reads an m3u playlist of disc images for multi-disc games. paths are
+/// relative to the playlist, and lines starting with # are comments
+pub fn read_m3u(path: &Path) -> Result<Vec<PathBuf>, String> {
+  let contents = fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+
+  let directory = path.parent().unwrap_or(Path::new(""));
+
+  let discs: Vec<PathBuf> = contents
+    .lines()
+    .map(|line| line.trim())
+    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    .map(|line| directory.join(line))
+    .collect();
+
+  if discs.is_empty() {
+    return Err(format!("{} doesn't list any discs", path.display()));
+  }
+
+  Ok(discs)
+}
+
+/// guesses whether an image stores raw 2352 byte sectors or cooked 2048 byte
+/// ones. raw images start with a sync pattern, while the first sectors of a
+/// cooked iso are the (usually empty) system area
+pub fn detect_sector_size(start: &[u8], length: u64) -> usize {
+  if !start.starts_with(&SYNC_PATTERN) && length.is_multiple_of(COOKED_SECTOR_SIZE as u64) {
+    COOKED_SECTOR_SIZE
+  } else {
+    BYTES_PER_SECTOR as usize
+  }
+}
+
+pub struct BinImage {
+  toc: Toc,
+  file: File
+}
+
+impl BinImage {
+  pub fn new(mut file: File) -> Result<Self, String> {
+    let length = file.metadata().map_err(|e| e.to_string())?.len();
+
+    let mut start = [0; SYNC_PATTERN.len()];
+
+    // images smaller than a sync pattern are empty either way
+    let _ = file.read_exact(&mut start);
+
+    let sector_size = detect_sector_size(&start, length);
+
+    Ok(Self {
+      toc: Toc::single_track((length / sector_size as u64) as usize, sector_size),
+      file
+    })
+  }
+}
+
+impl DiscImage for BinImage {
+  fn toc(&self) -> &Toc {
+    &self.toc
+  }
+
+  fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), String> {
+    let file = &mut self.file;
+
+    self.toc.read_sector(lba, buf, |_, offset, buf| {
+      file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+      file.read_exact(buf).map_err(|e| e.to_string())
+    })
+  }
+}
+
+pub struct MemoryImage {
+  toc: Toc,
+  bytes: Vec<u8>
+}
+
+impl MemoryImage {
+  pub fn new(bytes: Vec<u8>) -> Self {
+    let sector_size = detect_sector_size(&bytes, bytes.len() as u64);
+
+    Self {
+      toc: Toc::single_track(bytes.len() / sector_size, sector_size),
+      bytes
+    }
+  }
+}
+
+impl DiscImage for MemoryImage {
+  fn toc(&self) -> &Toc {
+    &self.toc
+  }
+
+  fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), String> {
+    let bytes = &self.bytes;
+
+    self.toc.read_sector(lba, buf, |_, offset, buf| {
+      let offset = offset as usize;
+
+      let sector = bytes.get(offset..offset + buf.len()).ok_or_else(|| format!("sector {lba} is out of range"))?;
+
+      buf.copy_from_slice(sector);
+
+      Ok(())
+    })
+  }
+}
+
+pub struct CueImage {
+  toc: Toc,
+  files: Vec<File>
+}
+
+impl CueImage {
+  pub fn open(path: &Path) -> Result<Self, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+
+    let cue_sheet = CueSheet::parse(&contents)?;
+
+    // file names in the cue sheet are relative to the cue sheet itself
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut files = Vec::new();
+    let mut file_sectors = Vec::new();
+
+    for cue_file in cue_sheet.files.iter() {
+      let file_path = directory.join(&cue_file.filename);
+
+      let file = File::open(&file_path).map_err(|e| format!("couldn't open {}: {e}", file_path.display()))?;
+
+      let length = file.metadata().map_err(|e| e.to_string())?.len();
+
+      // files are assumed to hold tracks of a single sector size
+      let sector_size = cue_file.tracks.first().map(|track| track.sector_size).unwrap_or(BYTES_PER_SECTOR as usize);
+
+      file_sectors.push((length / sector_size as u64) as usize);
+      files.push(file);
+    }
+
+    Ok(Self {
+      toc: cue_sheet.build_toc(&file_sectors)?,
+      files
+    })
+  }
+}
+
+impl DiscImage for CueImage {
+  fn toc(&self) -> &Toc {
+    &self.toc
+  }
+
+  fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), String> {
+    let files = &mut self.files;
+
+    self.toc.read_sector(lba, buf, |file_index, offset, buf| {
+      let file = &mut files[file_index];
+
+      file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+      file.read_exact(buf).map_err(|e| e.to_string())
+    })
+  }
+}
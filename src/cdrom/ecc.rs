@@ -0,0 +1,175 @@
+// per https://psx-spx.consoledev.net/cdromformat/#cdrom-sector-encoding
+// the P and Q parity bytes are reed-solomon codes over GF(2^8) computed from
+// the sector header and user data
+
+const ECC_P_OFFSET: usize = 0x81c;
+const ECC_Q_OFFSET: usize = 0x8c8;
+
+pub const SYNC_PATTERN: [u8; 12] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+const EDC_TABLE: [u32; 256] = edc_table();
+const ECC_TABLES: ([u8; 256], [u8; 256]) = ecc_tables();
+
+const fn edc_table() -> [u32; 256] {
+  let mut table = [0; 256];
+  let mut i = 0;
+
+  while i < 256 {
+    let mut edc = i as u32;
+    let mut bit = 0;
+
+    while bit < 8 {
+      edc = (edc >> 1) ^ if edc & 0b1 != 0 { 0xd8018001 } else { 0 };
+      bit += 1;
+    }
+
+    table[i] = edc;
+    i += 1;
+  }
+
+  table
+}
+
+/// computes the error detection code (a 32 bit crc) stored after the user data
+pub fn edc(data: &[u8]) -> u32 {
+  data.iter().fold(0, |edc, byte| (edc >> 8) ^ EDC_TABLE[((edc ^ *byte as u32) & 0xff) as usize])
+}
+
+const fn ecc_tables() -> ([u8; 256], [u8; 256]) {
+  let mut forward = [0; 256];
+  let mut backward = [0; 256];
+  let mut i = 0;
+
+  while i < 256 {
+    let j = ((i << 1) ^ if i & 0x80 != 0 { 0x11d } else { 0 }) as u8;
+
+    forward[i] = j;
+    backward[i ^ j as usize] = i as u8;
+    i += 1;
+  }
+
+  (forward, backward)
+}
+
+fn compute_block(
+  sector: &mut [u8],
+  major_count: usize,
+  minor_count: usize,
+  major_mult: usize,
+  minor_inc: usize,
+  dest: usize
+) {
+  let (forward, backward) = &ECC_TABLES;
+
+  let size = major_count * minor_count;
+
+  for major in 0..major_count {
+    let mut index = (major >> 1) * major_mult + (major & 0b1);
+
+    let mut ecc_a = 0;
+    let mut ecc_b = 0;
+
+    for _ in 0..minor_count {
+      let value = sector[0xc + index];
+
+      index += minor_inc;
+
+      if index >= size {
+        index -= size;
+      }
+
+      ecc_a ^= value;
+      ecc_b ^= value;
+      ecc_a = forward[ecc_a as usize];
+    }
+
+    ecc_a = backward[(forward[ecc_a as usize] ^ ecc_b) as usize];
+
+    sector[dest + major] = ecc_a;
+    sector[dest + major + major_count] = ecc_a ^ ecc_b;
+  }
+}
+
+/// regenerates the P and Q parity of a raw 2352 byte sector
+pub fn generate(sector: &mut [u8]) {
+  // mode 2 sectors compute their parity with a zeroed out header
+  let is_mode2 = sector[0xf] == 2;
+
+  let mut header = [0; 4];
+
+  if is_mode2 {
+    header.copy_from_slice(&sector[0xc..0x10]);
+    sector[0xc..0x10].fill(0);
+  }
+
+  compute_block(sector, 86, 24, 2, 86, ECC_P_OFFSET);
+  compute_block(sector, 52, 43, 86, 88, ECC_Q_OFFSET);
+
+  if is_mode2 {
+    sector[0xc..0x10].copy_from_slice(&header);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the parity of the sector below, computed separately from the ECMA-130 parity check matrices
+  const REFERENCE_EDC: u32 = 0xd707_6ffb;
+
+  const REFERENCE_P: [u8; 172] = [
+    0x7e, 0x5c, 0xc7, 0x5e, 0x27, 0xa4, 0x65, 0x81, 0x29, 0x94, 0x15, 0xa6, 0x0d, 0x6d, 0x07, 0x4a,
+    0xf7, 0xa4, 0x62, 0xb0, 0x7c, 0x64, 0xfa, 0x6d, 0x2a, 0x67, 0xe5, 0x4b, 0xda, 0x45, 0x54, 0x43,
+    0x50, 0x18, 0xba, 0x19, 0xe4, 0x22, 0x5f, 0x3d, 0x20, 0x3a, 0x43, 0xf9, 0x95, 0xbc, 0x5a, 0x6e,
+    0x1f, 0x7e, 0x56, 0xbd, 0xc4, 0xe0, 0xc5, 0x39, 0xd2, 0x7d, 0x8c, 0x3c, 0x99, 0x87, 0x6b, 0x00,
+    0x81, 0xd8, 0xaf, 0xb0, 0xcc, 0xf7, 0xeb, 0x4d, 0x05, 0xe7, 0x11, 0x42, 0xc5, 0x0d, 0xcc, 0xa8,
+    0xc6, 0xc0, 0xff, 0xb4, 0xde, 0x12, 0x41, 0xda, 0x9a, 0x1a, 0x9c, 0x06, 0x14, 0x41, 0x1e, 0xea,
+    0xe8, 0x3a, 0xdd, 0x9d, 0xa7, 0xea, 0x87, 0xf4, 0x82, 0xb0, 0xec, 0x34, 0xda, 0xcd, 0xda, 0x97,
+    0x65, 0x2b, 0xea, 0xd5, 0xb4, 0x63, 0xc0, 0x68, 0x1a, 0x99, 0xd4, 0x12, 0x3f, 0x5d, 0xf0, 0xaa,
+    0x43, 0x59, 0xc5, 0x8c, 0xba, 0x8e, 0x2f, 0x2e, 0xf6, 0xbd, 0x54, 0x30, 0xa5, 0x59, 0xe2, 0x4d,
+    0x0c, 0x9c, 0xe9, 0x17, 0x4b, 0xe0, 0x11, 0xe8, 0xcf, 0x30, 0x3c, 0x07, 0x4b, 0x6d, 0x55, 0x77,
+    0x11, 0xa2, 0x95, 0x7d, 0x6c, 0x08, 0x36, 0x10, 0x67, 0xd1, 0x58, 0x0d
+  ];
+
+  const REFERENCE_Q: [u8; 104] = [
+    0x70, 0x55, 0xee, 0x84, 0x44, 0x49, 0x40, 0x76, 0x38, 0xb0, 0xc9, 0x70, 0xd5, 0x4e, 0x45, 0x8e,
+    0xa0, 0x0e, 0xfd, 0xd7, 0x40, 0x58, 0x4a, 0x38, 0xa9, 0x80, 0xc2, 0xf1, 0xce, 0x38, 0xb1, 0x47,
+    0xb5, 0x06, 0xb6, 0x31, 0x24, 0x4c, 0x0b, 0xc4, 0xdf, 0x32, 0x6f, 0x80, 0xa6, 0x75, 0xea, 0x0e,
+    0x77, 0x95, 0x1f, 0xba, 0xed, 0x02, 0x42, 0xc4, 0x54, 0x9d, 0x3b, 0x87, 0x98, 0xd9, 0xe7, 0x0f,
+    0xe9, 0x3f, 0xd8, 0xe6, 0x8c, 0x78, 0xff, 0x3d, 0x9c, 0x55, 0x4a, 0xc2, 0x78, 0xf7, 0x1f, 0x34,
+    0xc7, 0x36, 0xdc, 0xfd, 0xb8, 0xce, 0x64, 0x30, 0xa3, 0x29, 0xbc, 0x3b, 0x8b, 0xf5, 0x05, 0x6c,
+    0x67, 0x2d, 0xea, 0x96, 0xb6, 0x6e, 0x21, 0xa9
+  ];
+
+  // a mode 2 form 1 sector at 00:02:00
+  fn sector() -> Vec<u8> {
+    let mut sector = vec![0; 2352];
+
+    sector[..12].copy_from_slice(&SYNC_PATTERN);
+    sector[0xc..0x10].copy_from_slice(&[0x00, 0x02, 0x00, 0x02]);
+    sector[0x10..0x18].copy_from_slice(&[0, 0, 0x8, 0, 0, 0, 0x8, 0]);
+
+    for (i, byte) in sector[0x18..0x818].iter_mut().enumerate() {
+      *byte = (i * 7 + 3) as u8;
+    }
+
+    let edc = edc(&sector[0x10..0x818]);
+
+    sector[0x818..0x81c].copy_from_slice(&edc.to_le_bytes());
+
+    generate(&mut sector);
+
+    sector
+  }
+
+  #[test]
+  fn mode2_form1_sector() {
+    let sector = sector();
+
+    assert_eq!(u32::from_le_bytes(sector[0x818..0x81c].try_into().unwrap()), REFERENCE_EDC);
+    assert_eq!(sector[ECC_P_OFFSET..ECC_Q_OFFSET], REFERENCE_P);
+    assert_eq!(sector[ECC_Q_OFFSET..], REFERENCE_Q);
+    // the header is restored after being zeroed for the parity
+    assert_eq!(sector[0xc..0x10], [0x00, 0x02, 0x00, 0x02]);
+  }
+}
@@ -0,0 +1,357 @@
+use super::{disc::DiscImage, BYTES_PER_SECTOR, DATA_OFFSET, HEADER_START, MODE1_DATA_OFFSET};
+
+// per https://wiki.osdev.org/ISO_9660 and the CD-ROM XA extensions described at
+// https://psx-spx.consoledev.net/cdromfileformats/
+
+pub const LOGICAL_BLOCK_SIZE: usize = 2048;
+
+const PRIMARY_VOLUME_DESCRIPTOR_LBA: usize = 16;
+const ROOT_RECORD_OFFSET: usize = 156;
+
+const FLAG_DIRECTORY: u8 = 0x2;
+
+// xa attributes, stored big endian in the system use area of directory records
+const XA_FORM2: u16 = 0x1000;
+const XA_INTERLEAVED: u16 = 0x2000;
+const XA_CDDA: u16 = 0x4000;
+
+#[derive(Clone, Debug)]
+pub struct DirectoryEntry {
+  // file name without the ";1" version suffix
+  pub name: String,
+  pub lba: usize,
+  pub size: usize,
+  pub is_directory: bool,
+  // xa files with form 2 sectors (ie: STR videos and XA audio), which have to be
+  // read as raw sectors
+  pub is_form2: bool
+}
+
+impl DirectoryEntry {
+  fn parse(record: &[u8]) -> Option<Self> {
+    let length = record[0] as usize;
+
+    if length < 34 || record.len() < length {
+      return None;
+    }
+
+    let name_length = record[32] as usize;
+
+    if 33 + name_length > length {
+      return None;
+    }
+
+    let name = match &record[33..33 + name_length] {
+      [0] => ".".to_string(),
+      [1] => "..".to_string(),
+      name => {
+        let name = String::from_utf8_lossy(name);
+
+        name.split(';').next().unwrap_or_default().to_string()
+      }
+    };
+
+    // the system use area starts after the name, padded to an even offset
+    let system_use = 33 + name_length + (1 - name_length % 2);
+
+    let attributes = record
+      .get(system_use..length)
+      .filter(|xa| xa.len() >= 8 && &xa[6..8] == b"XA")
+      .map(|xa| u16::from_be_bytes([xa[4], xa[5]]))
+      .unwrap_or(0);
+
+    Some(Self {
+      name,
+      lba: u32::from_le_bytes(record[2..6].try_into().unwrap()) as usize,
+      size: u32::from_le_bytes(record[10..14].try_into().unwrap()) as usize,
+      is_directory: record[25] & FLAG_DIRECTORY != 0,
+      is_form2: attributes & (XA_FORM2 | XA_INTERLEAVED | XA_CDDA) != 0
+    })
+  }
+
+  pub fn sectors(&self) -> usize {
+    self.size.div_ceil(LOGICAL_BLOCK_SIZE)
+  }
+}
+
+/// reads the ISO9660 file system of a disc
+pub struct IsoFilesystem<'a> {
+  disc: &'a mut dyn DiscImage,
+  pub volume_id: String,
+  root: DirectoryEntry
+}
+
+impl<'a> IsoFilesystem<'a> {
+  pub fn new(disc: &'a mut dyn DiscImage) -> Result<Self, String> {
+    let descriptor = Self::read_block(disc, PRIMARY_VOLUME_DESCRIPTOR_LBA)?;
+
+    if descriptor[0] != 1 || &descriptor[1..6] != b"CD001" {
+      return Err("disc doesn't have an ISO9660 file system".to_string());
+    }
+
+    let volume_id = String::from_utf8_lossy(&descriptor[40..72]).trim_end().to_string();
+
+    let root = DirectoryEntry::parse(&descriptor[ROOT_RECORD_OFFSET..ROOT_RECORD_OFFSET + 34]).ok_or("invalid root directory record")?;
+
+    Ok(Self {
+      disc,
+      volume_id,
+      root
+    })
+  }
+
+  /// reads the 2048 bytes of user data of a mode 1 or mode 2 form 1 sector
+  fn read_block(disc: &mut dyn DiscImage, lba: usize) -> Result<Vec<u8>, String> {
+    let mut sector = vec![0; BYTES_PER_SECTOR as usize];
+
+    disc.read_sector(lba, &mut sector)?;
+
+    let offset = if sector[HEADER_START + 3] == 1 { MODE1_DATA_OFFSET } else { DATA_OFFSET };
+
+    Ok(sector[offset..offset + LOGICAL_BLOCK_SIZE].to_vec())
+  }
+
+  pub fn root(&self) -> &DirectoryEntry {
+    &self.root
+  }
+
+  pub fn read_directory(&mut self, directory: &DirectoryEntry) -> Result<Vec<DirectoryEntry>, String> {
+    if !directory.is_directory {
+      return Err(format!("{} is not a directory", directory.name));
+    }
+
+    let mut entries = Vec::new();
+
+    for i in 0..directory.sectors() {
+      let block = Self::read_block(self.disc, directory.lba + i)?;
+
+      let mut offset = 0;
+
+      // records don't cross sector boundaries, a zero length pads out the rest of the sector
+      while offset < LOGICAL_BLOCK_SIZE && block[offset] != 0 {
+        let entry = DirectoryEntry::parse(&block[offset..]).ok_or_else(|| format!("invalid directory record in sector {}", directory.lba + i))?;
+
+        offset += block[offset] as usize;
+
+        if entry.name != "." && entry.name != ".." {
+          entries.push(entry);
+        }
+      }
+    }
+
+    Ok(entries)
+  }
+
+  /// finds a file or directory. paths are case insensitive and can use either
+  /// slash, ie: "cdrom:\SLUS_000.01;1" or "/data/movie.str"
+  pub fn find(&mut self, path: &str) -> Result<DirectoryEntry, String> {
+    let path = path.strip_prefix("cdrom:").unwrap_or(path);
+
+    let mut entry = self.root.clone();
+
+    for component in path.split(['/', '\\']).filter(|component| !component.is_empty()) {
+      let name = component.split(';').next().unwrap_or_default();
+
+      entry = self
+        .read_directory(&entry)?
+        .into_iter()
+        .find(|child| child.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("{path} not found"))?;
+    }
+
+    Ok(entry)
+  }
+
+  pub fn list(&mut self, path: &str) -> Result<Vec<DirectoryEntry>, String> {
+    let directory = self.find(path)?;
+
+    self.read_directory(&directory)
+  }
+
+  /// reads a whole file. form 2 files are returned as raw 2352 byte sectors,
+  /// everything else as the file's data
+  pub fn read_file(&mut self, entry: &DirectoryEntry) -> Result<Vec<u8>, String> {
+    if entry.is_directory {
+      return Err(format!("{} is a directory", entry.name));
+    }
+
+    if entry.is_form2 {
+      let mut data = vec![0; entry.sectors() * BYTES_PER_SECTOR as usize];
+
+      for (i, sector) in data.chunks_exact_mut(BYTES_PER_SECTOR as usize).enumerate() {
+        self.disc.read_sector(entry.lba + i, sector)?;
+      }
+
+      return Ok(data);
+    }
+
+    let mut data = Vec::with_capacity(entry.sectors() * LOGICAL_BLOCK_SIZE);
+
+    for i in 0..entry.sectors() {
+      data.extend(Self::read_block(self.disc, entry.lba + i)?);
+    }
+
+    data.truncate(entry.size);
+
+    Ok(data)
+  }
+
+  pub fn read_path(&mut self, path: &str) -> Result<Vec<u8>, String> {
+    let entry = self.find(path)?;
+
+    self.read_file(&entry)
+  }
+
+  pub fn system_cnf(&mut self) -> Result<SystemCnf, String> {
+    let contents = self.read_path("SYSTEM.CNF")?;
+
+    SystemCnf::parse(&String::from_utf8_lossy(&contents))
+  }
+}
+
+/// the boot configuration of a disc. discs without a SYSTEM.CNF boot PSX.EXE
+/// with the default settings
+#[derive(Clone, Debug)]
+pub struct SystemCnf {
+  pub boot: String,
+  pub tcb: Option<u32>,
+  pub event: Option<u32>,
+  pub stack: Option<u32>
+}
+
+impl SystemCnf {
+  pub fn parse(contents: &str) -> Result<Self, String> {
+    let mut boot = None;
+    let mut tcb = None;
+    let mut event = None;
+    let mut stack = None;
+
+    for line in contents.lines() {
+      let Some((key, value)) = line.split_once('=') else {
+        continue;
+      };
+
+      let value = value.trim();
+
+      // numbers are hex, without any prefix
+      let parse_hex = || u32::from_str_radix(value.split_whitespace().next().unwrap_or_default(), 16).map_err(|_| format!("invalid value in SYSTEM.CNF: {line}"));
+
+      match key.trim().to_uppercase().as_str() {
+        "BOOT" => boot = Some(value.to_string()),
+        "TCB" => tcb = Some(parse_hex()?),
+        "EVENT" => event = Some(parse_hex()?),
+        "STACK" => stack = Some(parse_hex()?),
+        _ => ()
+      }
+    }
+
+    Ok(Self {
+      boot: boot.ok_or("SYSTEM.CNF doesn't have a BOOT entry")?,
+      tcb,
+      event,
+      stack
+    })
+  }
+
+  /// the boot executable's path on the disc, without the "cdrom:" prefix or version
+  pub fn boot_path(&self) -> String {
+    // the BOOT line can also pass arguments to the executable
+    let path = self.boot.split_whitespace().next().unwrap_or_default();
+    let path = path.strip_prefix("cdrom:").unwrap_or(path);
+
+    path.split(';').next().unwrap_or_default().trim_start_matches('\\').to_string()
+  }
+
+  /// arguments passed to the boot executable after its path
+  pub fn boot_args(&self) -> Vec<String> {
+    self.boot.split_whitespace().skip(1).map(|arg| arg.to_string()).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn system_cnf() {
+    let cnf = SystemCnf::parse("BOOT = cdrom:\\SLUS_005.94;1\r\nTCB = 4\r\nEVENT = 10\r\nSTACK = 801FFFF0\r\n").unwrap();
+
+    assert_eq!(cnf.boot_path(), "SLUS_005.94");
+    assert!(cnf.boot_args().is_empty());
+    assert_eq!(cnf.tcb, Some(0x4));
+    assert_eq!(cnf.event, Some(0x10));
+    assert_eq!(cnf.stack, Some(0x801f_fff0));
+  }
+
+  #[test]
+  fn system_cnf_boot_arguments() {
+    let cnf = SystemCnf::parse("boot=cdrom:\\GAME\\MAIN.EXE;1 -level 2\n").unwrap();
+
+    assert_eq!(cnf.boot_path(), "GAME\\MAIN.EXE");
+    assert_eq!(cnf.boot_args(), ["-level", "2"]);
+    assert_eq!(cnf.stack, None);
+  }
+
+  #[test]
+  fn system_cnf_errors() {
+    assert!(SystemCnf::parse("TCB = 4\n").is_err());
+    assert!(SystemCnf::parse("BOOT = cdrom:\\PSX.EXE;1\nSTACK = zz\n").is_err());
+  }
+
+  fn record(name: &[u8], flags: u8, xa: Option<u16>) -> Vec<u8> {
+    let mut record = vec![0; 33];
+
+    record[2..6].copy_from_slice(&24u32.to_le_bytes());
+    record[10..14].copy_from_slice(&0x1800u32.to_le_bytes());
+    record[25] = flags;
+    record[32] = name.len() as u8;
+    record.extend_from_slice(name);
+
+    if name.len().is_multiple_of(2) {
+      record.push(0);
+    }
+
+    if let Some(attributes) = xa {
+      record.extend_from_slice(&[0, 0, 0, 0]);
+      record.extend_from_slice(&attributes.to_be_bytes());
+      record.extend_from_slice(b"XA");
+      record.extend_from_slice(&[0; 6]);
+    }
+
+    record[0] = record.len() as u8;
+
+    record
+  }
+
+  #[test]
+  fn directory_entry() {
+    let entry = DirectoryEntry::parse(&record(b"MOVIE.STR;1", 0, Some(XA_FORM2 | XA_INTERLEAVED))).unwrap();
+
+    assert_eq!(entry.name, "MOVIE.STR");
+    assert_eq!(entry.lba, 24);
+    assert_eq!(entry.sectors(), 3);
+    assert!(!entry.is_directory);
+    assert!(entry.is_form2);
+
+    let entry = DirectoryEntry::parse(&record(&[1], FLAG_DIRECTORY, None)).unwrap();
+
+    assert_eq!(entry.name, "..");
+    assert!(entry.is_directory);
+    assert!(!entry.is_form2);
+  }
+
+  #[test]
+  fn directory_entry_out_of_bounds() {
+    let mut record = record(b"SYSTEM.CNF;1", 0, None);
+
+    // name longer than the record
+    record[32] = 0x40;
+    assert!(DirectoryEntry::parse(&record).is_none());
+
+    // record longer than the buffer
+    record[0] = 0xff;
+    assert!(DirectoryEntry::parse(&record).is_none());
+
+    assert!(DirectoryEntry::parse(&[0x22; 16]).is_none());
+  }
+}
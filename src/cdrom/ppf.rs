@@ -0,0 +1,428 @@
+use super::{disc::DiscImage, toc::{Toc, Track}, SubchannelQ, BYTES_PER_SECTOR};
+
+// PPF patches replace byte ranges of a raw (2352 bytes per sector) bin image.
+// offsets are relative to the start of the image, so LBA 0 is at offset 0.
+// per https://www.romhacking.net/utilities/353/ (ppf3.txt)
+
+const DESCRIPTION_LENGTH: usize = 50;
+const BLOCK_CHECK_LENGTH: usize = 1024;
+// the block check is a copy of the image at this offset (sector 16, the
+// primary volume descriptor)
+const BLOCK_CHECK_OFFSET: u64 = 0x9320;
+const FILE_ID_MAGIC: &[u8] = b"@BEGIN_FILE_ID.DIZ";
+
+// records are at most 255 bytes long
+const MAX_RECORD_LENGTH: u64 = 0xff;
+
+pub struct PpfPatch {
+  pub version: u8,
+  pub description: String,
+  block_check: Option<Vec<u8>>,
+  // in file order, so later records overwrite earlier ones where they overlap
+  records: Vec<Record>,
+  // indexes into records sorted by address, to find the ones in a sector
+  by_address: Vec<usize>
+}
+
+#[derive(PartialEq, Debug)]
+struct Record {
+  address: u64,
+  data: Vec<u8>,
+  // the original bytes, if the patch has undo data
+  undo: Option<Vec<u8>>
+}
+
+impl PpfPatch {
+  pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+    if bytes.len() < 56 || &bytes[..3] != b"PPF" {
+      return Err("not a ppf file".to_string());
+    }
+
+    let version = match &bytes[3..5] {
+      b"10" => 1,
+      b"20" => 2,
+      b"30" => 3,
+      v => return Err(format!("unsupported ppf version {}", String::from_utf8_lossy(v)))
+    };
+
+    let description = String::from_utf8_lossy(&bytes[6..6 + DESCRIPTION_LENGTH]).trim_end_matches(['\0', ' ']).to_string();
+
+    let mut offset = 6 + DESCRIPTION_LENGTH;
+
+    let mut block_check = None;
+    let mut has_undo = false;
+    let mut offset_size = 4;
+
+    match version {
+      2 => {
+        // 4 bytes of original image size, then the block check
+        offset += 4;
+
+        block_check = Some(Self::slice(bytes, offset, BLOCK_CHECK_LENGTH)?.to_vec());
+        offset += BLOCK_CHECK_LENGTH;
+      }
+      3 => {
+        let header = Self::slice(bytes, offset, 4)?;
+
+        // image type 1 is a PrimoDVD .gi image, whose offsets don't match a bin image
+        if header[0] != 0 {
+          return Err("ppf patches for .gi images are not supported".to_string());
+        }
+
+        let has_block_check = header[1] != 0;
+
+        has_undo = header[2] != 0;
+        offset_size = 8;
+        offset += 4;
+
+        if has_block_check {
+          block_check = Some(Self::slice(bytes, offset, BLOCK_CHECK_LENGTH)?.to_vec());
+          offset += BLOCK_CHECK_LENGTH;
+        }
+      }
+      _ => ()
+    }
+
+    let mut records = Vec::new();
+
+    while offset < bytes.len() {
+      if bytes[offset..].starts_with(FILE_ID_MAGIC) {
+        break;
+      }
+
+      let address = Self::slice(bytes, offset, offset_size)?;
+
+      let address = if offset_size == 8 {
+        u64::from_le_bytes(address.try_into().unwrap())
+      } else {
+        u32::from_le_bytes(address.try_into().unwrap()) as u64
+      };
+
+      let length = Self::slice(bytes, offset + offset_size, 1)?[0] as usize;
+
+      offset += offset_size + 1;
+
+      let data = Self::slice(bytes, offset, length)?.to_vec();
+      offset += length;
+
+      let undo = if has_undo {
+        let undo = Self::slice(bytes, offset, length)?.to_vec();
+        offset += length;
+
+        Some(undo)
+      } else {
+        None
+      };
+
+      records.push(Record { address, data, undo });
+    }
+
+    let mut by_address: Vec<usize> = (0..records.len()).collect();
+    by_address.sort_by_key(|i| records[*i].address);
+
+    Ok(Self {
+      version,
+      description,
+      block_check,
+      records,
+      by_address
+    })
+  }
+
+  fn slice(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8], String> {
+    bytes.get(offset..offset + length).ok_or_else(|| "truncated ppf file".to_string())
+  }
+
+  /// makes sure the patch was made for the given image, using the block check
+  /// or, failing that, the undo data
+  pub fn verify(&self, disc: &mut dyn DiscImage) -> Result<(), String> {
+    let mismatch = || "the patch doesn't match this disc image".to_string();
+
+    if let Some(block_check) = &self.block_check {
+      let original = Self::read_original(disc, BLOCK_CHECK_OFFSET, BLOCK_CHECK_LENGTH)?;
+
+      if original != *block_check {
+        return Err(mismatch());
+      }
+    } else if let Some(Record { address, undo: Some(undo), .. }) = self.records.first() {
+      let original = Self::read_original(disc, *address, undo.len())?;
+
+      if original != *undo {
+        return Err(mismatch());
+      }
+    }
+
+    Ok(())
+  }
+
+  fn read_original(disc: &mut dyn DiscImage, address: u64, length: usize) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(length);
+    let mut buf = vec![0; BYTES_PER_SECTOR as usize];
+
+    let mut address = address;
+
+    while bytes.len() < length {
+      let lba = (address / BYTES_PER_SECTOR) as usize;
+      let sector_offset = (address % BYTES_PER_SECTOR) as usize;
+
+      disc.read_sector(lba, &mut buf)?;
+
+      let count = (BYTES_PER_SECTOR as usize - sector_offset).min(length - bytes.len());
+
+      bytes.extend_from_slice(&buf[sector_offset..sector_offset + count]);
+      address += count as u64;
+    }
+
+    Ok(bytes)
+  }
+
+  /// overlays the patched bytes onto the raw sector at the given LBA
+  pub fn apply(&self, lba: usize, sector: &mut [u8]) {
+    let start = lba as u64 * BYTES_PER_SECTOR;
+    let end = start + sector.len() as u64;
+
+    let first = self.by_address.partition_point(|i| self.records[*i].address < start.saturating_sub(MAX_RECORD_LENGTH));
+
+    let mut overlapping: Vec<usize> = self.by_address[first..]
+      .iter()
+      .copied()
+      .take_while(|i| self.records[*i].address < end)
+      .filter(|i| self.records[*i].address + self.records[*i].data.len() as u64 > start)
+      .collect();
+
+    overlapping.sort_unstable();
+
+    for Record { address, data, .. } in overlapping.iter().map(|i| &self.records[*i]) {
+      let record_end = address + data.len() as u64;
+
+      let from = (*address).max(start);
+      let to = record_end.min(end);
+
+      let data_start = (from - address) as usize;
+      let sector_start = (from - start) as usize;
+      let length = (to - from) as usize;
+
+      sector[sector_start..sector_start + length].copy_from_slice(&data[data_start..data_start + length]);
+    }
+  }
+}
+
+/// wraps a disc image, applying a ppf patch to every sector read
+pub struct PpfDisc {
+  disc: Box<dyn DiscImage>,
+  patch: PpfPatch
+}
+
+impl PpfDisc {
+  pub fn new(disc: Box<dyn DiscImage>, patch: PpfPatch) -> Self {
+    Self {
+      disc,
+      patch
+    }
+  }
+}
+
+impl DiscImage for PpfDisc {
+  fn toc(&self) -> &Toc {
+    self.disc.toc()
+  }
+
+  fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), String> {
+    self.disc.read_sector(lba, buf)?;
+
+    self.patch.apply(lba, buf);
+
+    Ok(())
+  }
+
+  fn tracks(&self) -> &[Track] {
+    self.disc.tracks()
+  }
+
+  fn subchannel_q(&self, lba: usize) -> SubchannelQ {
+    self.disc.subchannel_q(lba)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cdrom::{disc::MemoryImage, ecc::SYNC_PATTERN};
+
+  fn header(version: &[u8; 2]) -> Vec<u8> {
+    let mut bytes = b"PPF".to_vec();
+
+    bytes.extend_from_slice(version);
+    bytes.push(0);
+
+    let mut description = [b' '; DESCRIPTION_LENGTH];
+    description[..4].copy_from_slice(b"test");
+
+    bytes.extend_from_slice(&description);
+
+    bytes
+  }
+
+  fn record(bytes: &mut Vec<u8>, address: u64, data: &[u8], wide: bool) {
+    if wide {
+      bytes.extend_from_slice(&address.to_le_bytes());
+    } else {
+      bytes.extend_from_slice(&(address as u32).to_le_bytes());
+    }
+
+    bytes.push(data.len() as u8);
+    bytes.extend_from_slice(data);
+  }
+
+  fn image() -> Vec<u8> {
+    let mut image: Vec<u8> = (0..BYTES_PER_SECTOR as usize * 20).map(|i| (i % 251) as u8).collect();
+
+    image[..12].copy_from_slice(&SYNC_PATTERN);
+
+    image
+  }
+
+  #[test]
+  fn ppf1() {
+    let mut bytes = header(b"10");
+    record(&mut bytes, 0x10, &[1, 2, 3], false);
+
+    let patch = PpfPatch::parse(&bytes).unwrap();
+
+    assert_eq!(patch.version, 1);
+    assert_eq!(patch.description, "test");
+    assert!(patch.block_check.is_none());
+    assert_eq!(patch.records, [Record { address: 0x10, data: vec![1, 2, 3], undo: None }]);
+  }
+
+  #[test]
+  fn ppf2_block_check() {
+    let image = image();
+
+    let mut bytes = header(b"20");
+    bytes.extend_from_slice(&(image.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&image[BLOCK_CHECK_OFFSET as usize..BLOCK_CHECK_OFFSET as usize + BLOCK_CHECK_LENGTH]);
+    record(&mut bytes, 0x20, &[0xaa], false);
+    bytes.extend_from_slice(FILE_ID_MAGIC);
+    bytes.extend_from_slice(b"file_id.diz contents");
+
+    let patch = PpfPatch::parse(&bytes).unwrap();
+
+    assert_eq!(patch.version, 2);
+    assert_eq!(patch.records, [Record { address: 0x20, data: vec![0xaa], undo: None }]);
+
+    assert!(patch.verify(&mut MemoryImage::new(image.clone())).is_ok());
+
+    let mut other = image;
+    other[BLOCK_CHECK_OFFSET as usize] ^= 0xff;
+
+    assert!(patch.verify(&mut MemoryImage::new(other)).is_err());
+  }
+
+  #[test]
+  fn ppf3_undo() {
+    let image = image();
+
+    let address = BYTES_PER_SECTOR * 3 + 0x18;
+    let original = &image[address as usize..address as usize + 2];
+
+    let mut bytes = header(b"30");
+    // bin image, no block check, undo data
+    bytes.extend_from_slice(&[0, 0, 1, 0]);
+    record(&mut bytes, address, &[0x55, 0x66], true);
+    bytes.extend_from_slice(original);
+
+    let patch = PpfPatch::parse(&bytes).unwrap();
+
+    assert_eq!(patch.version, 3);
+    assert_eq!(patch.records, [Record { address, data: vec![0x55, 0x66], undo: Some(original.to_vec()) }]);
+    assert!(patch.verify(&mut MemoryImage::new(image.clone())).is_ok());
+
+    let mut sector = vec![0; BYTES_PER_SECTOR as usize];
+    patch.apply(3, &mut sector);
+
+    assert_eq!(sector[0x18..0x1a], [0x55, 0x66]);
+  }
+
+  #[test]
+  fn ppf3_block_check_offset() {
+    let mut bytes = header(b"30");
+    bytes.extend_from_slice(&[0, 1, 0, 0]);
+    bytes.extend_from_slice(&[0; BLOCK_CHECK_LENGTH]);
+    record(&mut bytes, 0x1234, &[7], true);
+
+    let patch = PpfPatch::parse(&bytes).unwrap();
+
+    assert_eq!(patch.block_check.as_deref(), Some(&[0; BLOCK_CHECK_LENGTH][..]));
+    assert_eq!(patch.records, [Record { address: 0x1234, data: vec![7], undo: None }]);
+  }
+
+  #[test]
+  fn ppf3_gi_image() {
+    let mut bytes = header(b"30");
+    bytes.extend_from_slice(&[1, 0, 0, 0]);
+    record(&mut bytes, 0x1234, &[7], true);
+
+    assert!(PpfPatch::parse(&bytes).is_err());
+  }
+
+  #[test]
+  fn truncated() {
+    let mut bytes = header(b"10");
+    record(&mut bytes, 0x10, &[1, 2, 3], false);
+    bytes.pop();
+
+    assert!(PpfPatch::parse(&bytes).is_err());
+    assert!(PpfPatch::parse(&header(b"40")).is_err());
+    assert!(PpfPatch::parse(b"PPF10").is_err());
+  }
+
+  #[test]
+  fn records_across_sectors() {
+    let sector_size = BYTES_PER_SECTOR;
+
+    let mut bytes = header(b"10");
+    // ends 2 bytes into sector 1
+    record(&mut bytes, sector_size - 3, &[1, 2, 3, 4, 5], false);
+    // a record of the longest length, starting in sector 1 and ending 0xf bytes into sector 2
+    record(&mut bytes, sector_size * 2 - 0xf0, &[9; 0xff], false);
+    // overlaps the end of the previous record, later records win
+    record(&mut bytes, sector_size * 2 + 0x8, &[0xee; 4], false);
+
+    let patch = PpfPatch::parse(&bytes).unwrap();
+
+    let mut sector = vec![0; sector_size as usize];
+    patch.apply(0, &mut sector);
+
+    assert_eq!(sector[sector_size as usize - 3..], [1, 2, 3]);
+
+    let mut sector = vec![0; sector_size as usize];
+    patch.apply(1, &mut sector);
+
+    assert_eq!(sector[..3], [4, 5, 0]);
+    assert_eq!(sector[sector_size as usize - 0xf0..], [9; 0xf0]);
+
+    let mut sector = vec![0; sector_size as usize];
+    patch.apply(2, &mut sector);
+
+    assert_eq!(sector[..0x8], [9; 0x8]);
+    assert_eq!(sector[0x8..0xc], [0xee; 4]);
+    assert_eq!(sector[0xc..0x10], [9, 9, 9, 0]);
+  }
+
+  #[test]
+  fn later_records_win() {
+    let mut bytes = header(b"10");
+    record(&mut bytes, 0x10, &[1; 8], false);
+    // earlier in the image but later in the file, so it overwrites the start of the first
+    record(&mut bytes, 0xc, &[2; 8], false);
+    record(&mut bytes, 0x16, &[3; 4], false);
+
+    let patch = PpfPatch::parse(&bytes).unwrap();
+
+    let mut sector = vec![0; BYTES_PER_SECTOR as usize];
+    patch.apply(0, &mut sector);
+
+    assert_eq!(sector[0xc..0x1a], [2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 3, 3, 3, 3]);
+  }
+}
@@ -0,0 +1,67 @@
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+  NtscU,
+  NtscJ,
+  Pal
+}
+
+impl Region {
+  // licensed discs have sector 4 filled with the license text, which ends with
+  // the name of the regional Sony branch, ie: "Sony Computer Entertainment Amer  ica"
+  const LICENSE_STRINGS: [(&'static [u8], Region); 3] = [
+    (b"Entertainment Amer", Region::NtscU),
+    (b"Entertainment Euro", Region::Pal),
+    (b"Entertainment Inc", Region::NtscJ)
+  ];
+
+  /// finds the region from the license sector's user data, or None for unlicensed discs
+  pub fn from_license(data: &[u8]) -> Option<Self> {
+    Self::LICENSE_STRINGS
+      .iter()
+      .find(|(license, _)| data.windows(license.len()).any(|window| window == *license))
+      .map(|(_, region)| *region)
+  }
+
+  /// finds the region of a BIOS from its version string, ie: "System ROM Version 2.2 12/04/95 A"
+  pub fn from_bios(bios: &[u8]) -> Option<Self> {
+    const VERSION_STRING: &[u8] = b"System ROM Version";
+
+    let start = bios.windows(VERSION_STRING.len()).position(|window| window == VERSION_STRING)?;
+
+    let version = bios[start..].split(|byte| *byte == 0).next()?;
+
+    match version.trim_ascii_end().last()? {
+      b'A' => Some(Region::NtscU),
+      b'E' => Some(Region::Pal),
+      b'J' => Some(Region::NtscJ),
+      _ => None
+    }
+  }
+
+  pub fn parse(region: &str) -> Option<Self> {
+    match region.to_lowercase().as_str() {
+      "ntsc-u" | "ntsc_u" | "us" | "usa" => Some(Region::NtscU),
+      "ntsc-j" | "ntsc_j" | "jp" | "japan" => Some(Region::NtscJ),
+      "pal" | "eu" | "europe" => Some(Region::Pal),
+      _ => None
+    }
+  }
+
+  /// the SCEx string reported by GetID
+  pub fn scex(&self) -> &'static [u8; 4] {
+    match self {
+      Region::NtscU => b"SCEA",
+      Region::NtscJ => b"SCEI",
+      Region::Pal => b"SCEE"
+    }
+  }
+
+  /// the string returned by Test(0x22)
+  pub fn id_string(&self) -> &'static [u8] {
+    match self {
+      Region::NtscU => b"for U/C",
+      Region::NtscJ => b"for Japan",
+      Region::Pal => b"for Europe"
+    }
+  }
+}
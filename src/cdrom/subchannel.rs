@@ -0,0 +1,279 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use super::{disc::DiscImage, toc::{Toc, Track}, Cdrom, SubchannelQ, LEAD_IN_SECTORS};
+
+// LibCrypt protected discs have a few sectors with deliberately corrupted Q
+// subchannel data, which the game checks for with GetlocP. images don't store
+// subchannel data, so the corrupted frames come from a separate .sbi or .lsd
+// file. per https://psx-spx.consoledev.net/cdromdrive/#cdrom-protection-libcrypt
+
+const SBI_MAGIC: &[u8; 4] = b"SBI\0";
+const LSD_ENTRY_SIZE: usize = 15;
+
+enum QPatch {
+  // Q0-Q9, without the crc
+  Full([u8; 10]),
+  // Q3-Q5
+  Relative([u8; 3]),
+  // Q7-Q9
+  Absolute([u8; 3])
+}
+
+impl QPatch {
+  fn apply(&self, subq: &mut SubchannelQ) {
+    match self {
+      QPatch::Full(q) => {
+        subq.track = q[1];
+        subq.index = q[2];
+        subq.mm = q[3];
+        subq.ss = q[4];
+        subq.sect = q[5];
+        subq.amm = q[7];
+        subq.ass = q[8];
+        subq.asect = q[9];
+      }
+      QPatch::Relative(msf) => {
+        subq.mm = msf[0];
+        subq.ss = msf[1];
+        subq.sect = msf[2];
+      }
+      QPatch::Absolute(msf) => {
+        subq.amm = msf[0];
+        subq.ass = msf[1];
+        subq.asect = msf[2];
+      }
+    }
+  }
+}
+
+pub struct SubchannelPatch {
+  patches: HashMap<usize, QPatch>
+}
+
+impl SubchannelPatch {
+  /// looks for a .sbi or .lsd file with the same name as the disc image
+  pub fn find(image_path: &Path) -> Result<Option<Self>, String> {
+    for extension in ["sbi", "lsd"] {
+      let path = image_path.with_extension(extension);
+
+      if !path.is_file() {
+        continue;
+      }
+
+      let bytes = fs::read(&path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+
+      let patch = if extension == "sbi" { Self::parse_sbi(&bytes)? } else { Self::parse_lsd(&bytes)? };
+
+      return Ok(Some(patch));
+    }
+
+    Ok(None)
+  }
+
+  pub fn parse_sbi(bytes: &[u8]) -> Result<Self, String> {
+    if !bytes.starts_with(SBI_MAGIC) {
+      return Err("invalid sbi file".to_string());
+    }
+
+    let mut patches = HashMap::new();
+
+    let mut offset = SBI_MAGIC.len();
+
+    // each entry is a bcd msf, a type byte and the type's Q data
+    while offset < bytes.len() {
+      let header = bytes.get(offset..offset + 4).ok_or("truncated sbi entry")?;
+
+      let lba = Self::msf_to_lba(&header[..3]);
+
+      let length = match header[3] {
+        1 => 10,
+        2 | 3 => 3,
+        t => return Err(format!("invalid sbi entry type {t}"))
+      };
+
+      let data = bytes.get(offset + 4..offset + 4 + length).ok_or("truncated sbi entry")?;
+
+      let patch = match header[3] {
+        1 => QPatch::Full(data.try_into().unwrap()),
+        2 => QPatch::Relative(data.try_into().unwrap()),
+        _ => QPatch::Absolute(data.try_into().unwrap())
+      };
+
+      patches.insert(lba, patch);
+
+      offset += 4 + length;
+    }
+
+    Ok(Self {
+      patches
+    })
+  }
+
+  pub fn parse_lsd(bytes: &[u8]) -> Result<Self, String> {
+    if !bytes.len().is_multiple_of(LSD_ENTRY_SIZE) {
+      return Err("invalid lsd file".to_string());
+    }
+
+    // each entry is a bcd msf followed by the full 12 byte Q frame (including the crc)
+    let patches = bytes
+      .chunks_exact(LSD_ENTRY_SIZE)
+      .map(|entry| (Self::msf_to_lba(&entry[..3]), QPatch::Full(entry[3..13].try_into().unwrap())))
+      .collect();
+
+    Ok(Self {
+      patches
+    })
+  }
+
+  fn msf_to_lba(msf: &[u8]) -> usize {
+    let sector = Toc::msf_to_lba(Cdrom::bcd_to_u8(msf[0]), Cdrom::bcd_to_u8(msf[1]), Cdrom::bcd_to_u8(msf[2]));
+
+    sector.saturating_sub(LEAD_IN_SECTORS as usize)
+  }
+
+  pub fn apply(&self, lba: usize, subq: &mut SubchannelQ) {
+    if let Some(patch) = self.patches.get(&lba) {
+      patch.apply(subq);
+    }
+  }
+}
+
+/// wraps a disc image, replacing the Q subchannel of the patched sectors
+pub struct PatchedDisc {
+  disc: Box<dyn DiscImage>,
+  patch: SubchannelPatch
+}
+
+impl PatchedDisc {
+  pub fn new(disc: Box<dyn DiscImage>, patch: SubchannelPatch) -> Self {
+    Self {
+      disc,
+      patch
+    }
+  }
+}
+
+impl DiscImage for PatchedDisc {
+  fn toc(&self) -> &Toc {
+    self.disc.toc()
+  }
+
+  fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> Result<(), String> {
+    self.disc.read_sector(lba, buf)
+  }
+
+  fn tracks(&self) -> &[Track] {
+    self.disc.tracks()
+  }
+
+  fn subchannel_q(&self, lba: usize) -> SubchannelQ {
+    let mut subq = self.disc.subchannel_q(lba);
+
+    self.patch.apply(lba, &mut subq);
+
+    subq
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cdrom::disc::MemoryImage;
+
+  // Q0-Q9 of a corrupted LibCrypt frame
+  const Q: [u8; 10] = [0x41, 0x01, 0x01, 0x07, 0x06, 0x05, 0x00, 0x23, 0x08, 0x05];
+
+  fn fields(subq: &SubchannelQ) -> [u8; 8] {
+    [subq.track, subq.index, subq.mm, subq.ss, subq.sect, subq.amm, subq.ass, subq.asect]
+  }
+
+  fn patched(patch: &SubchannelPatch, lba: usize) -> [u8; 8] {
+    let mut subq = SubchannelQ::new();
+
+    patch.apply(lba, &mut subq);
+
+    fields(&subq)
+  }
+
+  fn sbi(entries: &[(&[u8], u8, &[u8])]) -> Vec<u8> {
+    let mut bytes = SBI_MAGIC.to_vec();
+
+    for (msf, kind, data) in entries {
+      bytes.extend_from_slice(msf);
+      bytes.push(*kind);
+      bytes.extend_from_slice(data);
+    }
+
+    bytes
+  }
+
+  #[test]
+  fn bcd_msf_to_lba() {
+    assert_eq!(SubchannelPatch::msf_to_lba(&[0x00, 0x02, 0x00]), 0);
+    assert_eq!(SubchannelPatch::msf_to_lba(&[0x00, 0x12, 0x34]), 12 * 75 + 34 - 150);
+    assert_eq!(SubchannelPatch::msf_to_lba(&[0x03, 0x08, 0x05]), 3 * 4500 + 8 * 75 + 5 - 150);
+    // inside the lead in
+    assert_eq!(SubchannelPatch::msf_to_lba(&[0x00, 0x01, 0x00]), 0);
+  }
+
+  #[test]
+  fn sbi_entry_types() {
+    let patch = SubchannelPatch::parse_sbi(&sbi(&[
+      (&[0x00, 0x02, 0x10], 1, &Q),
+      (&[0x00, 0x02, 0x11], 2, &[0x01, 0x02, 0x03]),
+      (&[0x00, 0x02, 0x12], 3, &[0x04, 0x05, 0x06])
+    ])).unwrap();
+
+    assert_eq!(patched(&patch, 10), [0x01, 0x01, 0x07, 0x06, 0x05, 0x23, 0x08, 0x05]);
+    assert_eq!(patched(&patch, 11), [0, 0, 0x01, 0x02, 0x03, 0, 0, 0]);
+    assert_eq!(patched(&patch, 12), [0, 0, 0, 0, 0, 0x04, 0x05, 0x06]);
+    assert_eq!(patched(&patch, 13), [0; 8]);
+  }
+
+  #[test]
+  fn invalid_sbi() {
+    assert_eq!(SubchannelPatch::parse_sbi(b"SBJ\0").err().as_deref(), Some("invalid sbi file"));
+
+    let bad_type = sbi(&[(&[0x00, 0x02, 0x10], 4, &[0; 3])]);
+
+    assert_eq!(SubchannelPatch::parse_sbi(&bad_type).err().as_deref(), Some("invalid sbi entry type 4"));
+
+    let mut truncated = sbi(&[(&[0x00, 0x02, 0x10], 1, &Q)]);
+    truncated.pop();
+
+    assert_eq!(SubchannelPatch::parse_sbi(&truncated).err().as_deref(), Some("truncated sbi entry"));
+    assert_eq!(SubchannelPatch::parse_sbi(&truncated[..6]).err().as_deref(), Some("truncated sbi entry"));
+  }
+
+  #[test]
+  fn lsd() {
+    let mut bytes = vec![0x00, 0x02, 0x10];
+    bytes.extend_from_slice(&Q);
+    // the crc, which isn't used
+    bytes.extend_from_slice(&[0xab, 0xcd]);
+
+    let patch = SubchannelPatch::parse_lsd(&bytes).unwrap();
+
+    assert_eq!(patched(&patch, 10), [0x01, 0x01, 0x07, 0x06, 0x05, 0x23, 0x08, 0x05]);
+
+    bytes.push(0);
+
+    assert_eq!(SubchannelPatch::parse_lsd(&bytes).err().as_deref(), Some("invalid lsd file"));
+  }
+
+  #[test]
+  fn patched_disc() {
+    let disc = || Box::new(MemoryImage::new(vec![0; 2048 * 32]));
+
+    let patch = SubchannelPatch::parse_sbi(&sbi(&[(&[0x00, 0x02, 0x10], 1, &Q)])).unwrap();
+
+    let original = disc();
+    let patched = PatchedDisc::new(disc(), patch);
+
+    assert_eq!(fields(&patched.subchannel_q(10)), [0x01, 0x01, 0x07, 0x06, 0x05, 0x23, 0x08, 0x05]);
+
+    for lba in [9, 11] {
+      assert_eq!(fields(&patched.subchannel_q(lba)), fields(&original.subchannel_q(lba)));
+    }
+  }
+}
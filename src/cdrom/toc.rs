@@ -0,0 +1,250 @@
+use super::{ecc::{self, SYNC_PATTERN}, Cdrom, SubchannelQ, BYTES_PER_SECTOR, HEADER_START, LEAD_IN_SECTORS, SECTORS_PER_MINUTE, SECTORS_PER_SECOND, SUBHEADER_START};
+
+// bytes per sector of "cooked" images that only store the user data
+pub const COOKED_SECTOR_SIZE: usize = 2048;
+// mode 2 images that store everything but the sync pattern and header
+pub const MODE2_SECTOR_SIZE: usize = 2336;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrackType {
+  Audio,
+  Mode1,
+  Mode2
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+  pub number: u8,
+  pub track_type: TrackType,
+  // bytes stored in the file for every sector: 2352 for raw images, 2336 or
+  // 2048 for images that strip the sync pattern, header and/or error correction
+  pub sector_size: usize,
+  pub file_index: usize,
+  // disc LBA that maps to byte 0 of the track's file
+  pub file_lba: usize,
+  // first LBA of the track that is actually backed by the file. sectors before
+  // this (ie: from a PREGAP command) have to be synthesized
+  pub data_start: usize,
+  // INDEX 00
+  pub pregap_start: usize,
+  // INDEX 01
+  pub start: usize,
+  pub end: usize
+}
+
+impl Track {
+  pub fn length(&self) -> usize {
+    self.end - self.start
+  }
+
+  pub fn contains(&self, lba: usize) -> bool {
+    lba >= self.pregap_start && lba < self.end
+  }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Toc {
+  pub tracks: Vec<Track>,
+  pub lead_out: usize
+}
+
+impl Toc {
+  pub fn single_track(num_sectors: usize, sector_size: usize) -> Self {
+    Self {
+      tracks: vec![Track {
+        number: 1,
+        track_type: TrackType::Mode2,
+        sector_size,
+        file_index: 0,
+        file_lba: 0,
+        data_start: 0,
+        pregap_start: 0,
+        start: 0,
+        end: num_sectors
+      }],
+      lead_out: num_sectors
+    }
+  }
+
+  pub fn first_track(&self) -> u8 {
+    self.tracks.first().map(|track| track.number).unwrap_or(1)
+  }
+
+  pub fn last_track(&self) -> u8 {
+    self.tracks.last().map(|track| track.number).unwrap_or(1)
+  }
+
+  pub fn get_track(&self, number: u8) -> Option<&Track> {
+    self.tracks.iter().find(|track| track.number == number)
+  }
+
+  pub fn find_track(&self, lba: usize) -> Option<&Track> {
+    self.tracks.iter().find(|track| track.contains(lba))
+  }
+
+  /// returns the file index and byte offset for the given LBA, or None if the
+  /// sector isn't stored in any file (pregaps or past the lead out)
+  pub fn locate(&self, lba: usize) -> Option<(usize, u64)> {
+    let track = self.find_track(lba)?;
+
+    if lba < track.data_start {
+      return None;
+    }
+
+    Some((track.file_index, (lba - track.file_lba) as u64 * track.sector_size as u64))
+  }
+
+  /// reads the raw sector at the given LBA. `read_stored` is handed the file
+  /// index, byte offset and a buffer sized to what the file stores per sector.
+  /// cooked sectors are expanded back into raw ones, and sectors not stored in
+  /// any file are synthesized
+  pub fn read_sector<F>(&self, lba: usize, buf: &mut [u8], read_stored: F) -> Result<(), String>
+  where
+    F: FnOnce(usize, u64, &mut [u8]) -> Result<(), String>
+  {
+    let Some((file_index, offset)) = self.locate(lba) else {
+      self.synthesize_sector(lba, buf);
+
+      return Ok(());
+    };
+
+    let sector_size = self.find_track(lba).unwrap().sector_size;
+
+    if sector_size == BYTES_PER_SECTOR as usize {
+      return read_stored(file_index, offset, buf);
+    }
+
+    let mut stored = vec![0; sector_size];
+
+    read_stored(file_index, offset, &mut stored)?;
+
+    self.expand_sector(lba, &stored, buf);
+
+    Ok(())
+  }
+
+  /// rebuilds a raw 2352 byte sector from a cooked one by generating the sync
+  /// pattern, header, subheader and error correction. cooked mode 2 sectors are
+  /// assumed to be form 1 data sectors, as that's all a 2048 byte image can hold
+  pub fn expand_sector(&self, lba: usize, stored: &[u8], buf: &mut [u8]) {
+    let track_type = self.find_track(lba).map(|track| track.track_type).unwrap_or(TrackType::Mode2);
+
+    if track_type == TrackType::Audio {
+      buf.fill(0);
+      buf[..stored.len()].copy_from_slice(stored);
+
+      return;
+    }
+
+    self.write_header(lba, track_type, buf);
+
+    if stored.len() == MODE2_SECTOR_SIZE {
+      buf[SUBHEADER_START..].copy_from_slice(stored);
+
+      return;
+    }
+
+    match track_type {
+      TrackType::Mode1 => {
+        buf[SUBHEADER_START..SUBHEADER_START + COOKED_SECTOR_SIZE].copy_from_slice(stored);
+
+        let edc = ecc::edc(&buf[..0x810]);
+
+        buf[0x810..0x814].copy_from_slice(&edc.to_le_bytes());
+      }
+      _ => {
+        // file 0, channel 0, submode data, coding 0, stored twice
+        buf[SUBHEADER_START..SUBHEADER_START + 8].copy_from_slice(&[0, 0, 0x8, 0, 0, 0, 0x8, 0]);
+        buf[SUBHEADER_START + 8..SUBHEADER_START + 8 + COOKED_SECTOR_SIZE].copy_from_slice(stored);
+
+        let edc = ecc::edc(&buf[SUBHEADER_START..0x818]);
+
+        buf[0x818..0x81c].copy_from_slice(&edc.to_le_bytes());
+      }
+    }
+
+    ecc::generate(buf);
+  }
+
+  /// fills in sectors that aren't stored in the image (pregaps and the lead out).
+  /// these are returned as empty sectors, with a valid header for data tracks
+  pub fn synthesize_sector(&self, lba: usize, buf: &mut [u8]) {
+    let track_type = self.find_track(lba).map(|track| track.track_type).unwrap_or(TrackType::Audio);
+
+    if track_type == TrackType::Audio {
+      buf.fill(0);
+
+      return;
+    }
+
+    self.write_header(lba, track_type, buf);
+  }
+
+  fn write_header(&self, lba: usize, track_type: TrackType, buf: &mut [u8]) {
+    buf.fill(0);
+
+    buf[..SYNC_PATTERN.len()].copy_from_slice(&SYNC_PATTERN);
+
+    let (mm, ss, sect) = Self::lba_to_msf(lba + LEAD_IN_SECTORS as usize);
+
+    buf[HEADER_START] = Cdrom::u8_to_bcd(mm);
+    buf[HEADER_START + 1] = Cdrom::u8_to_bcd(ss);
+    buf[HEADER_START + 2] = Cdrom::u8_to_bcd(sect);
+    buf[HEADER_START + 3] = if track_type == TrackType::Mode1 { 1 } else { 2 };
+  }
+
+  pub fn subchannel_q(&self, lba: usize) -> SubchannelQ {
+    let mut subq = SubchannelQ::new();
+
+    let (amm, ass, asect) = Self::lba_to_msf(lba + LEAD_IN_SECTORS as usize);
+
+    subq.amm = Cdrom::u8_to_bcd(amm);
+    subq.ass = Cdrom::u8_to_bcd(ass);
+    subq.asect = Cdrom::u8_to_bcd(asect);
+
+    if let Some(track) = self.find_track(lba) {
+      subq.track = Cdrom::u8_to_bcd(track.number);
+
+      // relative time counts down towards index 01 while inside the pregap
+      let relative = if lba < track.start {
+        subq.index = 0;
+        track.start - lba
+      } else {
+        subq.index = 1;
+        lba - track.start
+      };
+
+      let (mm, ss, sect) = Self::lba_to_msf(relative);
+
+      subq.mm = Cdrom::u8_to_bcd(mm);
+      subq.ss = Cdrom::u8_to_bcd(ss);
+      subq.sect = Cdrom::u8_to_bcd(sect);
+    } else {
+      // lead out area
+      subq.track = 0xaa;
+      subq.index = 1;
+
+      let (mm, ss, sect) = Self::lba_to_msf(lba.saturating_sub(self.lead_out));
+
+      subq.mm = Cdrom::u8_to_bcd(mm);
+      subq.ss = Cdrom::u8_to_bcd(ss);
+      subq.sect = Cdrom::u8_to_bcd(sect);
+    }
+
+    subq
+  }
+
+  pub fn lba_to_msf(lba: usize) -> (u8, u8, u8) {
+    let lba = lba as u64;
+
+    let mm = lba / SECTORS_PER_MINUTE;
+    let ss = (lba % SECTORS_PER_MINUTE) / SECTORS_PER_SECOND;
+    let sect = lba % SECTORS_PER_SECOND;
+
+    (mm as u8, ss as u8, sect as u8)
+  }
+
+  pub fn msf_to_lba(mm: u8, ss: u8, sect: u8) -> usize {
+    (mm as u64 * SECTORS_PER_MINUTE + ss as u64 * SECTORS_PER_SECOND + sect as u64) as usize
+  }
+}
@@ -1,4 +1,4 @@
-use std::{fs::{self, File}, io::{Write, Read, Seek, SeekFrom}};
+use std::{fs::{self, File}, io::{Write, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
 
 #[derive(PartialEq)]
 pub enum CardState {
@@ -10,7 +10,6 @@ pub enum CardState {
 }
 
 const MEMORY_CARD_SIZE: usize = 0x20000;
-const FILENAME: &str = "../cards/memory_card.mcd";
 
 pub struct MemoryCard {
   pub has_saved: bool,
@@ -26,24 +25,16 @@ pub struct MemoryCard {
   card: Box<[u8]>,
   flag: u8,
   card_file: Option<File>,
+  path: PathBuf,
+  // a removed card doesn't respond, so games see an empty slot
+  pub inserted: bool
 }
 
 impl MemoryCard {
-  pub fn new(is_wasm: bool) -> Self {
-    let mut file = None;
-
-    if !is_wasm {
-      fs::create_dir_all("../cards").unwrap();
-
-      file = Some(fs::OpenOptions::new()
-        .create(true)
-        .read(true)
-        .write(true)
-        .append(true)
-        .open(FILENAME)
-        .unwrap());
-    }
-
+  /// saves are written to the card file at path, if given. otherwise the card only
+  /// lives in memory, and the frontend loads and stores it (ie: with load_card)
+  pub fn new(path: Option<&Path>) -> Self {
+    let file = path.map(Self::open_file);
 
     Self {
       has_saved: false,
@@ -58,13 +49,50 @@ impl MemoryCard {
       checksum_match: false,
       card: vec![0; MEMORY_CARD_SIZE].into_boxed_slice(),
       flag: 0x8,
-      card_file: file
+      card_file: file,
+      path: path.map(Path::to_path_buf).unwrap_or_default(),
+      inserted: true
+    }
+  }
+
+  fn open_file(path: &Path) -> File {
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir).unwrap();
+    }
+
+    fs::OpenOptions::new()
+      .create(true)
+      .read(true)
+      .write(true)
+      .append(true)
+      .open(path)
+      .unwrap()
+  }
+
+  /// switches to a different card file, ie: one card per game. does nothing for cards
+  /// that aren't saved to a file
+  pub fn use_file(&mut self, path: &Path) {
+    if self.card_file.is_none() || self.path == path {
+      return;
     }
+
+    self.card_file = Some(Self::open_file(path));
+    self.path = path.to_path_buf();
+
+    self.card.fill(0);
+    self.load_file_contents();
   }
 
   pub fn load_file_contents(&mut self) {
     if let Some(card_file) = &mut self.card_file {
-      card_file.read_exact(&mut self.card).unwrap();
+      let mut contents = Vec::new();
+
+      card_file.read_to_end(&mut contents).unwrap();
+
+      // a newly created card file is empty until the first save
+      if contents.len() == MEMORY_CARD_SIZE {
+        self.card.copy_from_slice(&contents);
+      }
     }
   }
 
@@ -312,7 +340,7 @@ impl MemoryCard {
 
   fn write_to_file(&mut self) {
     if let Some(card_file) = &mut self.card_file {
-      fs::write(FILENAME, &self.card).unwrap();
+      fs::write(&self.path, &self.card).unwrap();
       card_file.flush().unwrap();
 
       let mut buffer_copy = [0; MEMORY_CARD_SIZE];
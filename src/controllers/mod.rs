@@ -1,4 +1,4 @@
-use std::{collections::VecDeque, rc::Rc, cell::Cell};
+use std::{collections::VecDeque, rc::Rc, cell::Cell, path::Path};
 
 use crate::cpu::interrupt::{interrupt_registers::InterruptRegisters, interrupt_register::Interrupt};
 
@@ -37,8 +37,8 @@ pub struct Controllers {
 }
 
 impl Controllers {
-  pub fn new(interrupts: Rc<Cell<InterruptRegisters>>, is_wasm: bool) -> Self {
-    let mut memory_card = MemoryCard::new(is_wasm);
+  pub fn new(interrupts: Rc<Cell<InterruptRegisters>>, card_path: Option<&Path>) -> Self {
+    let mut memory_card = MemoryCard::new(card_path);
 
     memory_card.load_file_contents();
 
@@ -165,7 +165,7 @@ impl Controllers {
     if self.active_device == ControllerDevice::None {
       if command == 0x1 {
         self.active_device = ControllerDevice::Controller
-      } else if command == 0x81 {
+      } else if command == 0x81 && self.memory_card.inserted {
         self.active_device = ControllerDevice::MemoryCard
       }
     }
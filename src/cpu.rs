@@ -1,8 +1,8 @@
-use std::{cell::Cell, collections::HashSet, fs::{self, File}, rc::Rc};
+use std::{cell::Cell, mem, path::Path, rc::Rc};
 
-use crate::{cpu::instruction::Instruction, gpu::{CYCLES_PER_SCANLINE, NUM_SCANLINES_PER_FRAME, GPU_FREQUENCY}, util};
+use crate::{cdrom::{disc::DiscImage, iso9660::IsoFilesystem}, cpu::instruction::Instruction, gpu::{CYCLES_PER_SCANLINE, NUM_SCANLINES_PER_FRAME, GPU_FREQUENCY}};
 
-use self::{bus::Bus, dma::DMA, interrupt::interrupt_registers::InterruptRegisters, gte::Gte};
+use self::{block_cache::{Block, BlockCache, Interpreter}, debugger::{Debugger, StopReason}, bus::Bus, dma::DMA, exe::{PsxExe, ARGS_ADDRESS}, interrupt::interrupt_registers::InterruptRegisters, gte::Gte, trace::Trace};
 
 pub mod bus;
 pub mod execute;
@@ -14,10 +14,41 @@ pub mod timers;
 pub mod gte;
 pub mod mdec;
 pub mod disassembler;
+pub mod exe;
+pub mod block_cache;
+pub mod lockstep;
+pub mod debugger;
+pub mod gdb;
+pub mod trace;
+#[cfg(feature = "dynarec")]
+pub mod dynarec;
 
 // 33.868MHZ
 pub const CPU_FREQUENCY: f64 = 33_868_800.0;
 
+// the BIOS jumps here to run the shell (the logo and license screens) once the kernel is initialized
+const SHELL_ENTRY: u32 = 0x80030000;
+// devices are synced after this many cycles
+const SYNC_CYCLES: i32 = 128;
+
+// the kernel's default stack pointer when SYSTEM.CNF doesn't set one
+pub(crate) const DEFAULT_STACK: u32 = 0x801ffff0;
+
+// DCIC, the hardware breakpoint control register. the low bits are set when a breakpoint hits
+const DCIC_ANY_BREAK: u32 = 1 << 0;
+const DCIC_CODE_BREAK: u32 = 1 << 1;
+const DCIC_DATA_BREAK: u32 = 1 << 2;
+const DCIC_READ_BREAK: u32 = 1 << 3;
+const DCIC_WRITE_BREAK: u32 = 1 << 4;
+// bits 23 and 31 enable the debug hardware and 30 enables the breakpoints below
+const DCIC_MASTER_ENABLE: u32 = (1 << 23) | (1 << 30) | (1 << 31);
+const DCIC_CODE_ENABLE: u32 = 1 << 24;
+const DCIC_DATA_ENABLE: u32 = 1 << 25;
+const DCIC_READ_ENABLE: u32 = 1 << 26;
+const DCIC_WRITE_ENABLE: u32 = 1 << 27;
+// the rest always read as 0. the any-jump break (bits 5, 28 and 29) is stored but never hits
+const DCIC_WRITE_MASK: u32 = 0xff80_f03f;
+
 pub const CYCLES_PER_FRAME: i64 = ((CYCLES_PER_SCANLINE * NUM_SCANLINES_PER_FRAME) as f64 * (CPU_FREQUENCY / GPU_FREQUENCY)) as i64;
 
 
@@ -38,7 +69,7 @@ impl IsolatedCacheLine {
   }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Cause {
   Interrupt = 0x0,
   LoadAddressError = 0x4,
@@ -59,6 +90,8 @@ pub struct COP0 {
   pub jumpdest: u32,
   pub bad_vaddr: u32,
   pub dcic: u32,
+  pub bpc: u32,
+  pub bda: u32,
   pub bdam: u32,
   pub bpcm: u32
 }
@@ -97,6 +130,27 @@ impl COP0 {
     exception_address
   }
 
+  // pc matches BPC in the bits set in BPCM
+  pub fn code_breakpoint(&self, pc: u32) -> bool {
+    let enable = DCIC_MASTER_ENABLE | DCIC_CODE_ENABLE;
+
+    self.dcic & enable == enable && (pc ^ self.bpc) & self.bpcm == 0
+  }
+
+  // returns the DCIC status bits to set when a load or store hits the data breakpoint
+  pub fn data_breakpoint(&self, address: u32, write: bool) -> Option<u32> {
+    let enable = DCIC_MASTER_ENABLE | DCIC_DATA_ENABLE;
+
+    let (kind_enable, status) = if write {
+      (DCIC_WRITE_ENABLE, DCIC_WRITE_BREAK)
+    } else {
+      (DCIC_READ_ENABLE, DCIC_READ_BREAK)
+    };
+
+    (self.dcic & enable == enable && self.dcic & kind_enable != 0 && (address ^ self.bda) & self.bdam == 0)
+      .then_some(DCIC_DATA_BREAK | status)
+  }
+
   pub fn return_from_exception(&mut self) {
     let mode = self.sr & 0x3f;
     self.sr &= !0xf;
@@ -127,16 +181,30 @@ pub struct CPU {
   dma: Rc<Cell<DMA>>,
   interrupts: Rc<Cell<InterruptRegisters>>,
   current_instruction: u32,
+  // set when a data breakpoint cancels the current instruction's memory access
+  access_cancelled: bool,
   isolated_cache: [IsolatedCacheLine; 256],
   pub gte: Gte,
-  pub debug_on: bool,
   output: String,
-  pub exe_file: Option<String>,
-  pub found: HashSet<u32>,
+  boot_exe: Option<PsxExe>,
+  // cpu speed in percent of the real hardware's
+  overclock: u32,
+  overclock_counter: u32,
+  fast_boot: bool,
+  interpreter: Interpreter,
+  block_cache: BlockCache<Block>,
+  #[cfg(feature = "dynarec")]
+  native_blocks: BlockCache<dynarec::NativeBlock>,
+  pub debugger: Debugger,
+  // records the instructions run while set
+  pub trace: Option<Trace>,
+  stop: Option<StopReason>
 }
 
 impl CPU {
-  pub fn new(bios: Vec<u8>, game_file: Option<File>, game_bytes: Option<Vec<u8>>, is_wasm: bool) -> Self {
+  /// card_path is the memory card file saves are written to. without one, the card
+  /// is kept in memory
+  pub fn new(bios: Vec<u8>, disc: Option<Box<dyn DiscImage>>, card_path: Option<&Path>) -> Self {
     let interrupts = Rc::new(Cell::new(InterruptRegisters::new()));
     let dma = Rc::new(Cell::new(DMA::new()));
 
@@ -147,7 +215,7 @@ impl CPU {
       r: [0; 32],
       hi: 0,
       low: 0,
-      bus: Bus::new(bios, interrupts.clone(), dma.clone(), game_file, game_bytes, is_wasm),
+      bus: Bus::new(bios, interrupts.clone(), dma.clone(), disc, card_path),
       load: None,
       branch: false,
       delay_slot: false,
@@ -158,18 +226,29 @@ impl CPU {
         jumpdest: 0,
         bad_vaddr: 0,
         dcic: 0,
+        bpc: 0,
+        bda: 0,
         bdam: 0,
         bpcm: 0
       },
       dma,
       interrupts,
       current_instruction: 0,
+      access_cancelled: false,
       isolated_cache: [IsolatedCacheLine::new(); 256],
       gte: Gte::new(),
-      debug_on: false,
       output: "".to_string(),
-      exe_file: None,
-      found: HashSet::new()
+      boot_exe: None,
+      overclock: 100,
+      overclock_counter: 0,
+      fast_boot: false,
+      interpreter: Interpreter::Simple,
+      block_cache: BlockCache::default(),
+      #[cfg(feature = "dynarec")]
+      native_blocks: BlockCache::default(),
+      debugger: Debugger::default(),
+      trace: None,
+      stop: None
     }
   }
 
@@ -199,25 +278,123 @@ impl CPU {
 
     self.pc = exception_address;
     self.next_pc = self.pc.wrapping_add(4);
+
+    if self.debugger.breaks_on(cause) {
+      self.stop.get_or_insert(StopReason::Exception { cause, epc: self.cop0.epc });
+    }
+  }
+
+  // hardware breakpoints raise a break exception, but at 0x80000040 instead of the usual vector
+  fn debug_exception(&mut self, status: u32) {
+    self.cop0.dcic |= DCIC_ANY_BREAK | status;
+
+    self.exception(Cause::Break);
+
+    self.pc = if self.cop0.bev() { 0xbfc0_0140 } else { 0x8000_0040 };
+    self.next_pc = self.pc.wrapping_add(4);
+  }
+
+  // checked before the instruction at current_pc runs
+  pub(super) fn check_code_breakpoint(&mut self) -> bool {
+    if !self.cop0.code_breakpoint(self.current_pc) {
+      return false;
+    }
+
+    self.debug_exception(DCIC_CODE_BREAK);
+
+    self.execute_load_delay();
+
+    true
+  }
+
+  // a data breakpoint hits before the access happens, so the load or store is dropped and
+  // the instruction doesn't write its destination register. returns whether it was cancelled
+  fn check_data_breakpoint(&mut self, address: u32, write: bool) -> bool {
+    if self.access_cancelled {
+      return true;
+    }
+
+    if let Some(status) = self.cop0.data_breakpoint(address, write) {
+      self.debug_exception(status);
+
+      self.access_cancelled = true;
+    }
+
+    self.access_cancelled
+  }
+
+  // clears the cancelled access once the instruction is done with it
+  pub(super) fn take_cancelled_access(&mut self) -> bool {
+    mem::take(&mut self.access_cancelled)
   }
 
   pub fn check_irqs(&mut self) {
     self.cop0.set_interrupt(self.interrupts.get().pending());
   }
 
-  pub fn run_frame(&mut self) {
+  /// runs the rest of the frame, or until the debugger stops the cpu. running again
+  /// continues from where it stopped
+  pub fn run_frame(&mut self) -> Option<StopReason> {
     while !self.bus.gpu.frame_complete {
-      while self.bus.cycles - self.bus.last_sync < 128 {
-        self.step();
+      self.run_until_sync();
+
+      if let Some(stop) = self.take_stop() {
+        return Some(stop);
       }
+    }
 
+    self.bus.gpu.frame_complete = false;
+
+    None
+  }
+
+  /// runs until the devices are next synced, or the debugger stops the cpu
+  pub fn run_until_sync(&mut self) {
+    while self.bus.cycles - self.bus.last_sync < SYNC_CYCLES && self.stop.is_none() {
+      match self.interpreter {
+        Interpreter::Simple => {
+          self.step();
+        }
+        Interpreter::Cached => self.run_block(),
+        #[cfg(feature = "dynarec")]
+        Interpreter::Dynarec => self.run_native_block()
+      }
+
+      self.check_watchpoints();
+    }
+
+    if self.bus.cycles - self.bus.last_sync >= SYNC_CYCLES {
       self.bus.sync_devices();
     }
+  }
 
-    self.bus.gpu.frame_complete = false;
+  fn take_stop(&mut self) -> Option<StopReason> {
+    let stop = self.stop.take();
+
+    // these stop before the instruction at pc runs, so resuming has to run it
+    if let Some(StopReason::Breakpoint(pc) | StopReason::Step(pc)) = stop {
+      self.debugger.resume_at(pc);
+    }
+
+    stop
+  }
+
+  pub fn set_interpreter(&mut self, interpreter: Interpreter) {
+    self.interpreter = interpreter;
+
+    self.clear_blocks();
+  }
+
+  // drops every compiled block, for when code changes without going through the bus
+  fn clear_blocks(&mut self) {
+    self.block_cache.clear();
+
+    #[cfg(feature = "dynarec")]
+    self.native_blocks.clear();
   }
 
-  fn step(&mut self) {
+  // returns false when the cpu was stalled or stopped at a breakpoint instead
+  fn step(&mut self) -> bool {
     let mut dma = self.dma.get();
 
     if dma.is_active() {
@@ -226,7 +403,7 @@ impl CPU {
         self.dma.set(dma);
 
         if !dma.chopping_enabled() {
-          return;
+          return false;
         }
       } else {
         let count = dma.tick(&mut self.bus);
@@ -234,14 +411,26 @@ impl CPU {
 
         self.bus.tick(count);
 
-        return;
+        return false;
       }
     }
 
-    if self.pc == 0x80030000 {
-      if let Some(exe_file) = &self.exe_file {
-        let exe_file = exe_file.clone();
-        self.load_exe(exe_file.as_str());
+    // the shell entry is only hooked once, games often have code at the same address
+    if self.pc == SHELL_ENTRY && self.boot_pending() {
+      if let Some(exe) = self.boot_exe.take() {
+        self.load_exe(&exe);
+      } else if mem::take(&mut self.fast_boot) {
+        if let Err(e) = self.boot_disc() {
+          println!("fast boot failed, running the BIOS shell: {e}");
+        }
+      }
+    }
+
+    if self.debugger.is_active() {
+      if let Some(stop) = self.check_breakpoints() {
+        self.stop = Some(stop);
+
+        return false;
       }
     }
 
@@ -253,7 +442,7 @@ impl CPU {
 
       self.execute_load_delay();
 
-      return;
+      return true;
     }
 
     self.check_irqs();
@@ -274,7 +463,11 @@ impl CPU {
 
       self.execute_load_delay();
 
-      return;
+      return true;
+    }
+
+    if self.check_code_breakpoint() {
+      return true;
     }
 
     self.update_tty();
@@ -282,23 +475,32 @@ impl CPU {
     self.pc = self.next_pc;
     self.next_pc = self.next_pc.wrapping_add(4);
 
-    if !self.found.contains(&self.current_pc) && self.debug_on {
-      println!(
-          "[Opcode: 0x{:x}] [PC: 0x{:x}] {}",
-          instr,
-          self.current_pc,
-          self.disassemble(instr)
-      );
-      self.found.insert(self.current_pc);
-    }
     self.tick_instruction();
 
+    if self.debugger.is_active() {
+      self.track_calls(Instruction::new(instr));
+    }
+
+    if let Some(trace) = &mut self.trace {
+      trace.begin(self.current_pc, instr);
+    }
+
     self.execute(Instruction::new(instr));
+
+    if let Some(trace) = &mut self.trace {
+      trace.end();
+    }
+
+    true
   }
 
   pub fn set_reg(&mut self, rt: usize, val: u32) {
     if rt != 0 {
       self.r[rt] = val;
+
+      if let Some(trace) = &mut self.trace {
+        trace.register_write(rt, val);
+      }
     }
   }
 
@@ -321,45 +523,94 @@ impl CPU {
     }
   }
 
-  pub fn load_exe(&mut self, filename: &str) {
-    let bytes = fs::read(filename).unwrap();
+  /// skips the BIOS shell and boots the disc's executable directly
+  pub fn set_fast_boot(&mut self, enabled: bool) {
+    self.fast_boot = enabled;
+  }
 
-    let mut index = 0x10;
+  /// lets imports boot: the console takes the BIOS's region, and discs licensed for
+  /// another region (or none) are reported as licensed for it
+  pub fn set_region_bypass(&mut self, enabled: bool) {
+    if enabled {
+      if let Some(region) = self.bus.bios_region() {
+        self.bus.cdrom.set_region(Some(region));
+      }
+    }
 
-    self.pc = util::read_word(&bytes, index);
-    self.next_pc = self.pc + 4;
+    self.bus.cdrom.set_region_bypass(enabled);
+  }
 
-    index += 4;
+  // loads the executable named in SYSTEM.CNF (or PSX.EXE without one) the same way the
+  // BIOS would after the shell. only STACK is applied, the kernel keeps its default
+  // TCB and EVENT counts
+  fn boot_disc(&mut self) -> Result<(), String> {
+    let disc = self.bus.cdrom.disc_mut().ok_or("no disc inserted")?;
 
-    self.r[28] = util::read_word(&bytes, index);
+    let mut fs = IsoFilesystem::new(disc)?;
 
-    index += 4;
+    let (path, args, stack) = match fs.system_cnf() {
+      Ok(cnf) => (cnf.boot_path(), cnf.boot_args(), cnf.stack),
+      Err(_) => ("PSX.EXE".to_string(), Vec::new(), None)
+    };
 
-    let file_dest = util::read_word(&bytes, index);
+    let exe = PsxExe::parse(&fs.read_path(&path)?).and_then(|exe| exe.with_args(args)).map_err(|e| format!("{path}: {e}"))?;
 
-    index += 4;
+    self.r[29] = stack.unwrap_or(DEFAULT_STACK);
+    self.r[30] = self.r[29];
 
-    let file_size = util::read_word(&bytes, index);
+    self.load_exe(&exe);
 
-    index += 0x10 + 4;
+    Ok(())
+  }
 
-    let sp_base = util::read_word(&bytes, index);
+  /// boots the exe instead of the BIOS shell, once the kernel is initialized
+  pub fn set_boot_exe(&mut self, exe: PsxExe) {
+    self.boot_exe = Some(exe);
+  }
 
-    index += 4;
+  // whether step still has to take over at the shell entry
+  pub(super) fn boot_pending(&self) -> bool {
+    self.boot_exe.is_some() || self.fast_boot
+  }
 
-    if sp_base != 0 {
-      let sp_offset = util::read_word(&bytes, index);
+  /// copies the exe to RAM and jumps to it. called before the first frame, this
+  /// boots the exe directly without running the BIOS at all
+  pub fn load_exe(&mut self, exe: &PsxExe) {
+    for (i, byte) in exe.data.iter().enumerate() {
+      self.bus.ram[(exe.dest as usize + i) & 0x1f_ffff] = *byte;
+    }
+
+    for i in 0..exe.bss_size {
+      self.bus.ram[(exe.bss_start.wrapping_add(i) & 0x1f_ffff) as usize] = 0;
+    }
 
-      self.r[29] = sp_base + sp_offset;
-      self.r[30] = self.r[29];
+    // without arguments, a0 and a1 are left as the kernel set them
+    if !exe.args.is_empty() {
+      let args = exe.args_block();
+
+      self.bus.ram[ARGS_ADDRESS as usize..ARGS_ADDRESS as usize + args.len()].copy_from_slice(&args);
+
+      self.r[4] = exe.args.len() as u32;
+      self.r[5] = 0x8000_0000 | ARGS_ADDRESS;
     }
 
-    index = 0x800;
+    self.r[28] = exe.gp;
 
-    for i in 0..file_size {
-      self.bus.ram[((file_dest + i) & 0x1f_ffff) as usize] = bytes[index];
-      index += 1;
+    if let Some(stack) = exe.stack {
+      self.r[29] = stack;
+      self.r[30] = stack;
     }
+
+    self.pc = exe.pc;
+    self.next_pc = self.pc.wrapping_add(4);
+
+    // RAM was written behind the bus's back
+    self.clear_blocks();
+
+    // don't run a delay slot or pending load from before the jump
+    self.branch = false;
+    self.delay_slot = false;
+    self.load = None;
   }
 
   fn write_to_cache(&mut self, address: u32, value: u32) {
@@ -406,7 +657,7 @@ impl CPU {
       let mut address = (address & !0xf) + (4 * index as u32);
 
       for i in index..4 {
-        let value = self.bus.mem_read_32(address);
+        let value = self.bus.fetch_32(address);
 
         cache_line.data[i] = value;
 
@@ -429,7 +680,7 @@ impl CPU {
 
     self.bus.tick(5);
 
-    self.bus.mem_read_32(self.pc)
+    self.bus.fetch_32(self.pc)
   }
 
   pub fn store_32(&mut self, address: u32, value: u32) {
@@ -439,6 +690,14 @@ impl CPU {
       return;
     }
 
+    if self.check_data_breakpoint(address, true) {
+      self.access_cancelled = false;
+
+      return;
+    }
+
+    self.trace_access(address, value, 4, true);
+
     self.bus.tick(5);
 
     self.bus.mem_write_32(address, value);
@@ -451,6 +710,14 @@ impl CPU {
       return;
     }
 
+    if self.check_data_breakpoint(address, true) {
+      self.access_cancelled = false;
+
+      return;
+    }
+
+    self.trace_access(address, value as u32, 2, true);
+
     self.bus.tick(5);
 
     self.bus.mem_write_16(address, value)
@@ -463,20 +730,42 @@ impl CPU {
       return;
     }
 
+    if self.check_data_breakpoint(address, true) {
+      self.access_cancelled = false;
+
+      return;
+    }
+
+    self.trace_access(address, value as u32, 1, true);
+
     self.bus.tick(5);
 
     self.bus.mem_write_8(address, value)
   }
 
+  fn trace_access(&mut self, address: u32, value: u32, size: u8, write: bool) {
+    if let Some(trace) = &mut self.trace {
+      trace.memory_access(address, value, size, write);
+    }
+  }
+
   // TODO: refactor this into just one method
   pub fn load_32(&mut self, address: u32) -> u32 {
     if !self.cop0.is_cache_disabled() {
       return self.read_from_cache(address);
     }
 
+    if self.check_data_breakpoint(address, false) {
+      return 0;
+    }
+
     self.bus.tick(5);
 
-    self.bus.mem_read_32(address)
+    let value = self.bus.mem_read_32(address);
+
+    self.trace_access(address, value, 4, false);
+
+    value
   }
 
   pub fn load_16(&mut self, address: u32) -> u16 {
@@ -484,9 +773,17 @@ impl CPU {
       return self.read_from_cache(address) as u16;
     }
 
+    if self.check_data_breakpoint(address, false) {
+      return 0;
+    }
+
     self.bus.tick(5);
 
-    self.bus.mem_read_16(address)
+    let value = self.bus.mem_read_16(address);
+
+    self.trace_access(address, value as u32, 2, false);
+
+    value
   }
 
   pub fn load_8(&mut self, address: u32) -> u8 {
@@ -494,12 +791,194 @@ impl CPU {
       return self.read_from_cache(address) as u8;
     }
 
+    if self.check_data_breakpoint(address, false) {
+      return 0;
+    }
+
     self.bus.tick(5);
 
-    self.bus.mem_read_8(address)
+    let value = self.bus.mem_read_8(address);
+
+    self.trace_access(address, value as u32, 1, false);
+
+    value
+  }
+
+  /// speeds up the cpu by the given percentage of its real clock (ie: 200 for twice as fast),
+  /// which helps games that slow down on hardware. memory accesses keep their timing
+  pub fn set_overclock(&mut self, percent: u32) {
+    self.overclock = percent.max(100);
+    self.overclock_counter = 0;
   }
 
   pub fn tick_instruction(&mut self) {
-    self.bus.tick(1);
+    if self.overclock == 100 {
+      self.bus.tick(1);
+
+      return;
+    }
+
+    // only tick once every overclock / 100 instructions
+    self.overclock_counter += 100;
+
+    while self.overclock_counter >= self.overclock {
+      self.overclock_counter -= self.overclock;
+      self.bus.tick(1);
+    }
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CODE: u32 = 0x8001_0000;
+  const DATA: u32 = 0x8002_0000;
+
+  const ENABLE_ALL: u32 = DCIC_MASTER_ENABLE | DCIC_CODE_ENABLE | DCIC_DATA_ENABLE | DCIC_READ_ENABLE | DCIC_WRITE_ENABLE;
+
+  // lw $t0, 0($a0) / sw $t1, 0($a0) / mtc0 $t0, dcic / addiu $t0, $zero, 1
+  const LW: u32 = 0x8c88_0000;
+  const SW: u32 = 0xac89_0000;
+  const MTC0_DCIC: u32 = 0x4088_3800;
+  const ADDIU: u32 = 0x2408_0001;
+
+  fn cpu(code: &[u32]) -> CPU {
+    let mut cpu = CPU::new(vec![0; 512 * 1024], None, None);
+
+    for (i, instr) in code.iter().enumerate() {
+      cpu.bus.mem_write_32(CODE + i as u32 * 4, *instr);
+    }
+
+    cpu.pc = CODE;
+    cpu.next_pc = CODE + 4;
+    cpu.r[4] = DATA;
+
+    cpu
+  }
+
+  fn assert_debug_exception(cpu: &CPU, status: u32, epc: u32) {
+    assert_eq!(cpu.pc, 0x8000_0040);
+    assert_eq!(cpu.cop0.dcic & 0x3f, DCIC_ANY_BREAK | status);
+    assert_eq!(cpu.cop0.epc, epc);
+    assert_eq!((cpu.cop0.cause >> 2) & 0x1f, Cause::Break as u32);
+  }
+
+  #[test]
+  fn code_breakpoint_mask() {
+    let cop0 = COP0 { sr: 0, cause: 0, epc: 0, jumpdest: 0, bad_vaddr: 0, dcic: ENABLE_ALL, bpc: 0x8001_0004, bda: 0, bdam: 0, bpcm: 0xffff_fff0 };
+
+    // only the bits in BPCM are compared
+    assert!(cop0.code_breakpoint(0x8001_0000));
+    assert!(cop0.code_breakpoint(0x8001_000c));
+    assert!(!cop0.code_breakpoint(0x8001_0010));
+
+    let disabled = COP0 { dcic: ENABLE_ALL & !DCIC_CODE_ENABLE, ..cop0 };
+
+    assert!(!disabled.code_breakpoint(0x8001_0004));
+
+    let no_master = COP0 { dcic: ENABLE_ALL & !(1 << 31), ..cop0 };
+
+    assert!(!no_master.code_breakpoint(0x8001_0004));
+  }
+
+  #[test]
+  fn data_breakpoint_mask() {
+    let cop0 = COP0 { sr: 0, cause: 0, epc: 0, jumpdest: 0, bad_vaddr: 0, dcic: ENABLE_ALL, bpc: 0, bda: 0x8002_0000, bdam: 0xffff_ff00, bpcm: 0 };
+
+    assert_eq!(cop0.data_breakpoint(0x8002_00fc, false), Some(DCIC_DATA_BREAK | DCIC_READ_BREAK));
+    assert_eq!(cop0.data_breakpoint(0x8002_0010, true), Some(DCIC_DATA_BREAK | DCIC_WRITE_BREAK));
+    assert_eq!(cop0.data_breakpoint(0x8002_0100, false), None);
+
+    let writes_only = COP0 { dcic: ENABLE_ALL & !DCIC_READ_ENABLE, ..cop0 };
+
+    assert_eq!(writes_only.data_breakpoint(0x8002_0000, false), None);
+    assert_eq!(writes_only.data_breakpoint(0x8002_0000, true), Some(DCIC_DATA_BREAK | DCIC_WRITE_BREAK));
+
+    let disabled = COP0 { dcic: ENABLE_ALL & !DCIC_DATA_ENABLE, ..cop0 };
+
+    assert_eq!(disabled.data_breakpoint(0x8002_0000, true), None);
+  }
+
+  #[test]
+  fn dcic_write_mask() {
+    let mut cpu = cpu(&[MTC0_DCIC]);
+
+    cpu.r[8] = 0xffff_ffff;
+
+    cpu.step();
+
+    assert_eq!(cpu.cop0.dcic, DCIC_WRITE_MASK);
+  }
+
+  #[test]
+  fn code_breakpoint_skips_instruction() {
+    let mut cpu = cpu(&[0, ADDIU]);
+
+    cpu.cop0.dcic = ENABLE_ALL;
+    cpu.cop0.bpc = CODE + 4;
+    cpu.cop0.bpcm = 0xffff_ffff;
+
+    cpu.step();
+    cpu.step();
+
+    assert_debug_exception(&cpu, DCIC_CODE_BREAK, CODE + 4);
+    assert_eq!(cpu.r[8], 0);
+  }
+
+  #[test]
+  fn write_breakpoint_drops_store() {
+    let mut cpu = cpu(&[SW]);
+
+    cpu.r[9] = 0x1234_5678;
+    cpu.cop0.dcic = ENABLE_ALL & !DCIC_CODE_ENABLE;
+    cpu.cop0.bda = DATA;
+    cpu.cop0.bdam = 0xffff_ffff;
+
+    cpu.step();
+
+    assert_debug_exception(&cpu, DCIC_DATA_BREAK | DCIC_WRITE_BREAK, CODE);
+    assert_eq!(cpu.bus.mem_read_32(DATA), 0);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn read_breakpoint_drops_load() {
+    let mut cpu = cpu(&[LW]);
+
+    cpu.bus.mem_write_32(DATA, 0x1234_5678);
+    cpu.r[8] = 0xaaaa_aaaa;
+    cpu.cop0.dcic = ENABLE_ALL & !DCIC_CODE_ENABLE;
+    cpu.cop0.bda = DATA;
+    cpu.cop0.bdam = 0xffff_ffff;
+
+    cpu.step();
+    cpu.execute_load_delay();
+
+    assert_debug_exception(&cpu, DCIC_DATA_BREAK | DCIC_READ_BREAK, CODE);
+    assert_eq!(cpu.r[8], 0xaaaa_aaaa);
+
+    // the flag doesn't leak into the next load
+    cpu.cop0.dcic = 0;
+    cpu.pc = CODE;
+    cpu.next_pc = CODE + 4;
+
+    cpu.step();
+    cpu.execute_load_delay();
+
+    assert_eq!(cpu.r[8], 0x1234_5678);
+  }
+
+  #[test]
+  fn bev_vector() {
+    let mut cpu = cpu(&[ADDIU]);
+
+    cpu.cop0.sr = 1 << 22;
+    cpu.cop0.dcic = ENABLE_ALL;
+    cpu.cop0.bpc = CODE;
+    cpu.cop0.bpcm = 0xffff_ffff;
+
+    cpu.step();
+
+    assert_eq!(cpu.pc, 0xbfc0_0140);
+    assert_eq!(cpu.next_pc, 0xbfc0_0144);
+  }
+}
@@ -0,0 +1,330 @@
+use std::rc::Rc;
+
+use crate::util;
+
+use super::{bus::{Bus, PAGE_SHIFT, RAM_SIZE}, instruction::Instruction, Cause, CPU, SHELL_ENTRY, SYNC_CYCLES};
+
+// long runs of code without branches are split up, so a block doesn't hog a device sync
+const MAX_BLOCK_LENGTH: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Interpreter {
+  // fetches and decodes every instruction
+  Simple,
+  // runs pre-decoded blocks of RAM code, with the same results as Simple
+  Cached,
+  // runs blocks of RAM code translated to native code
+  #[cfg(feature = "dynarec")]
+  Dynarec
+}
+
+struct Op {
+  handler: fn(&mut CPU, Instruction),
+  instr: u32,
+  // loads and stores can start a DMA transfer
+  accesses_memory: bool
+}
+
+// straight line code up to and including the delay slot of the first branch
+pub struct Block {
+  ops: Vec<Op>
+}
+
+impl Block {
+  fn compile(address: usize, ram: &[u8]) -> Self {
+    let ops = block_instructions(address, ram).into_iter().map(|instr| Op {
+      handler: CPU::decode(&instr),
+      instr: instr.0,
+      accesses_memory: instr.op_code() >= 0x20
+    }).collect();
+
+    Self {
+      ops
+    }
+  }
+}
+
+/// the instructions of the block starting at the given RAM offset. blocks end after
+/// the delay slot of the first branch and never cross a page, so a write to the page
+/// invalidates them
+pub fn block_instructions(address: usize, ram: &[u8]) -> Vec<Instruction> {
+  let page_end = (address | ((1 << PAGE_SHIFT) - 1)) + 1;
+
+  let mut instructions = Vec::new();
+  let mut address = address;
+  let mut in_delay_slot = false;
+
+  while address < page_end && instructions.len() < MAX_BLOCK_LENGTH {
+    let instr = Instruction::new(util::read_word(ram, address));
+
+    let is_branch = is_branch(&instr);
+
+    instructions.push(instr);
+
+    if in_delay_slot {
+      break;
+    }
+
+    in_delay_slot = is_branch;
+    address += 4;
+  }
+
+  instructions
+}
+
+fn is_branch(instr: &Instruction) -> bool {
+  match instr.op_code() {
+    // jr, jalr
+    0x0 => matches!(instr.op_code_secondary(), 0x8 | 0x9),
+    // bcondz, j, jal, beq, bne, blez, bgtz
+    0x1..=0x7 => true,
+    _ => false
+  }
+}
+
+pub struct BlockCache<B> {
+  // indexed by the physical address of the block's first instruction, in words,
+  // along with the version of its page when it was compiled
+  blocks: Vec<Option<(u32, Rc<B>)>>
+}
+
+impl<B> Default for BlockCache<B> {
+  fn default() -> Self {
+    Self {
+      blocks: Vec::new()
+    }
+  }
+}
+
+impl<B> BlockCache<B> {
+  pub fn lookup(&mut self, address: usize, bus: &Bus, compile: impl FnOnce(usize, &[u8]) -> B) -> Rc<B> {
+    if self.blocks.is_empty() {
+      self.blocks = vec![None; RAM_SIZE / 4];
+    }
+
+    let version = bus.page_versions[address >> PAGE_SHIFT];
+
+    let slot = &mut self.blocks[address >> 2];
+
+    match slot {
+      Some((block_version, block)) if *block_version == version => block.clone(),
+      _ => {
+        let block = Rc::new(compile(address, &bus.ram));
+
+        *slot = Some((version, block.clone()));
+
+        block
+      }
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.blocks.fill(None);
+  }
+}
+
+impl CPU {
+  /// the RAM offset of the block at pc, or None when the current instruction has to
+  /// go through step: BIOS and uncached code, DMA transfers, the boot hook, tracing
+  /// and the debugger
+  pub(super) fn block_address(&self) -> Option<usize> {
+    let address = Bus::translate_address(self.pc) as usize;
+
+    if address >= RAM_SIZE
+      || self.pc & 0b11 != 0
+      || self.pc >= 0xa000_0000
+      || self.trace.is_some()
+      || self.debugger.is_active()
+      || !self.bus.watchpoints.is_empty()
+      || (self.pc == SHELL_ENTRY && self.boot_pending())
+      || self.dma.get().is_active() {
+      return None;
+    }
+
+    Some(address)
+  }
+
+  /// runs a block of pre-decoded RAM code
+  pub(super) fn run_block(&mut self) {
+    let Some(address) = self.block_address() else {
+      self.step();
+
+      return;
+    };
+
+    let block = self.block_cache.lookup(address, &self.bus, Block::compile);
+
+    let start = self.pc;
+
+    for (i, op) in block.ops.iter().enumerate() {
+      let after_memory_access = i > 0 && block.ops[i - 1].accesses_memory;
+
+      if !self.enter_instruction(start.wrapping_add(4 * i as u32), op.instr, i == 0, after_memory_access) {
+        return;
+      }
+
+      (op.handler)(self, Instruction::new(op.instr));
+    }
+  }
+
+  /// goes through the same steps as step for an instruction of a compiled block, up to
+  /// executing it (including the instruction cache, so only the decoding is skipped).
+  /// returns false when the block has to end before the instruction runs
+  pub(super) fn enter_instruction(&mut self, pc: u32, compiled: u32, first: bool, after_memory_access: bool) -> bool {
+    if !first {
+      // stop after exceptions, at device syncs and wherever step has more work to do
+      if self.pc != pc || (self.pc == SHELL_ENTRY && self.boot_pending()) || self.bus.cycles - self.bus.last_sync >= SYNC_CYCLES {
+        return false;
+      }
+
+      // loads and stores can start a DMA transfer
+      if after_memory_access && self.dma.get().is_active() {
+        return false;
+      }
+    }
+
+    self.current_pc = self.pc;
+
+    self.check_irqs();
+
+    // the fetch still goes through the instruction cache to keep its timing and state
+    let instr = self.fetch_instruction();
+    self.current_instruction = instr;
+
+    self.delay_slot = self.branch;
+    self.branch = false;
+
+    if self.cop0.interrupts_ready() {
+      self.exception(Cause::Interrupt);
+
+      if (instr >> 25) == 0x25 {
+        self.cop2_command(Instruction::new(instr));
+      }
+
+      self.execute_load_delay();
+
+      return false;
+    }
+
+    if self.check_code_breakpoint() {
+      return false;
+    }
+
+    self.update_tty();
+
+    self.pc = self.next_pc;
+    self.next_pc = self.next_pc.wrapping_add(4);
+
+    self.tick_instruction();
+
+    if instr != compiled {
+      // the instruction cache still holds code that has since been overwritten in RAM
+      self.execute(Instruction::new(instr));
+
+      return false;
+    }
+
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CODE: u32 = 0x8001_0000;
+  const MAIN: u32 = CODE + 0x40;
+
+  // addiu t0, t0, 1 / addiu t0, t0, 0x10
+  const ADD_1: u32 = 0x2508_0001;
+  const ADD_16: u32 = 0x2508_0010;
+  // jal CODE / jr ra
+  const CALL: u32 = 0x0c00_4000;
+  const RETURN: u32 = 0x03e0_0008;
+
+  fn cpu(main: &[u32]) -> CPU {
+    let mut cpu = CPU::new(vec![0; 512 * 1024], None, None);
+
+    // a function at CODE that adds to t0
+    for (i, instr) in [ADD_1, RETURN, 0].iter().enumerate() {
+      cpu.bus.mem_write_32(CODE + i as u32 * 4, *instr);
+    }
+
+    for (i, instr) in main.iter().enumerate() {
+      cpu.bus.mem_write_32(MAIN + i as u32 * 4, *instr);
+    }
+
+    cpu.pc = MAIN;
+    cpu.next_pc = MAIN + 4;
+    cpu.r[4] = CODE;
+    cpu.r[10] = ADD_16;
+
+    cpu
+  }
+
+  fn run_until(cpu: &mut CPU, interpreter: Interpreter, end: u32) {
+    while cpu.pc != end {
+      match interpreter {
+        Interpreter::Simple => {
+          cpu.step();
+        }
+        _ => cpu.run_block()
+      }
+    }
+  }
+
+  // the first instruction of the block compiled for the function
+  fn compiled(cpu: &CPU) -> Option<u32> {
+    let (_, block) = cpu.block_cache.blocks[(CODE & 0x1f_ffff) as usize >> 2].as_ref()?;
+
+    Some(block.ops[0].instr)
+  }
+
+  fn assert_same_state(simple: &CPU, cached: &CPU) {
+    assert_eq!(simple.r, cached.r);
+    assert_eq!((simple.pc, simple.next_pc), (cached.pc, cached.next_pc));
+    assert_eq!((simple.hi, simple.low), (cached.hi, cached.low));
+    assert_eq!(simple.bus.cycles, cached.bus.cycles);
+  }
+
+  #[test]
+  fn ram_write_recompiles_block() {
+    // call the function, overwrite its first instruction with sw t2, 0(a0) and call it again
+    let main = [CALL, 0, 0xac8a_0000, CALL, 0];
+    let end = MAIN + 4 * main.len() as u32;
+
+    let mut simple = cpu(&main);
+    let mut cached = cpu(&main);
+
+    run_until(&mut simple, Interpreter::Simple, end);
+    run_until(&mut cached, Interpreter::Cached, end);
+
+    assert_eq!(cached.r[8], 0x11);
+    assert_eq!(compiled(&cached), Some(ADD_16));
+    assert_same_state(&simple, &cached);
+  }
+
+  #[test]
+  fn cache_isolation_clears_blocks() {
+    // call the function, isolate the cache and unisolate it (lui t3, 1 / mtc0 t3, sr /
+    // mtc0 zero, sr) and call it again
+    let main = [CALL, 0, 0x3c0b_0001, 0x408b_6000, 0x4080_6000, CALL, 0];
+    let end = MAIN + 4 * main.len() as u32;
+
+    let mut simple = cpu(&main);
+    let mut cached = cpu(&main);
+
+    for (cpu, interpreter) in [(&mut simple, Interpreter::Simple), (&mut cached, Interpreter::Cached)] {
+      run_until(cpu, interpreter, MAIN + 8);
+
+      // code written behind the bus's back, like the BIOS loading an exe without tracking it
+      cpu.bus.ram[(CODE & 0x1f_ffff) as usize..][..4].copy_from_slice(&ADD_16.to_le_bytes());
+
+      run_until(cpu, interpreter, end);
+    }
+
+    assert_eq!(cached.r[8], 0x11);
+    assert_eq!(compiled(&cached), Some(ADD_16));
+    assert_same_state(&simple, &cached);
+  }
+}
@@ -1,10 +1,12 @@
-use std::{rc::Rc, cell::Cell, fs::File};
+use std::{rc::Rc, cell::Cell, path::Path};
 
-use crate::{gpu::GPU, spu::SPU, cdrom::Cdrom, controllers::Controllers};
+use crate::{gpu::GPU, spu::SPU, cdrom::{Cdrom, disc::DiscImage, region::Region}, controllers::Controllers};
 
-use super::{counter::Counter, interrupt::interrupt_registers::InterruptRegisters, timers::timers::Timers, dma::DMA, mdec::Mdec};
+use super::{counter::Counter, debugger::Watchpoints, interrupt::interrupt_registers::InterruptRegisters, timers::timers::Timers, dma::DMA, mdec::Mdec};
 
-const RAM_SIZE: usize = 2 * 1024 * 1024;
+pub const RAM_SIZE: usize = 2 * 1024 * 1024;
+// 4kb pages
+pub const PAGE_SHIFT: usize = 12;
 
 const EXP2_WRITE_ADDR: u32 = 0x1f802021;
 const EXP2_READ_ADDR: u32 = 0x1f802023;
@@ -48,19 +50,22 @@ pub struct Bus {
   exp2_buffer: Vec<u8>,
   scratchpad: Box<[u8]>,
   last_device_sync: [i32; 4],
-  pub last_sync: i32
+  pub last_sync: i32,
+  // bumped on every write to a page of RAM, so the block cache knows when code changed
+  pub page_versions: Box<[u32]>,
+  pub watchpoints: Watchpoints
 }
 
 impl Bus {
-  pub fn new(bios: Vec<u8>, interrupts: Rc<Cell<InterruptRegisters>>, dma: Rc<Cell<DMA>>, game_file: Option<File>, game_bytes: Option<Vec<u8>>, is_wasm: bool) -> Self {
+  pub fn new(bios: Vec<u8>, interrupts: Rc<Cell<InterruptRegisters>>, dma: Rc<Cell<DMA>>, disc: Option<Box<dyn DiscImage>>, card_path: Option<&Path>) -> Self {
     Self {
       bios,
       ram: vec![0; RAM_SIZE].into_boxed_slice(),
       gpu: GPU::new(interrupts.clone()),
       spu: SPU::new(),
       timers: Timers::new(interrupts.clone()),
-      cdrom: Cdrom::new(interrupts.clone(), game_file, game_bytes, is_wasm),
-      controllers: Controllers::new(interrupts.clone(), is_wasm),
+      cdrom: Cdrom::new(interrupts.clone(), disc),
+      controllers: Controllers::new(interrupts.clone(), card_path),
       counter: Counter::new(),
       interrupts,
       dma,
@@ -71,7 +76,9 @@ impl Bus {
       scratchpad: vec![0; 0x400].into_boxed_slice(),
       last_device_sync: [0; 4],
       last_sync: 0,
-      total_cycles: 0
+      total_cycles: 0,
+      page_versions: vec![0; RAM_SIZE >> PAGE_SHIFT].into_boxed_slice(),
+      watchpoints: Watchpoints::default()
     }
   }
 
@@ -86,6 +93,10 @@ impl Bus {
   }
 
   pub fn mem_read_8(&mut self, address: u32) -> u8 {
+    if !self.watchpoints.is_empty() {
+      self.watchpoints.check(address, 1, false);
+    }
+
     let address = Bus::translate_address(address);
 
     match address {
@@ -112,6 +123,15 @@ impl Bus {
   }
 
   pub fn mem_read_32(&mut self, address: u32) -> u32 {
+    if !self.watchpoints.is_empty() {
+      self.watchpoints.check(address, 4, false);
+    }
+
+    self.fetch_32(address)
+  }
+
+  /// reads a word without triggering watchpoints, for instruction fetches
+  pub fn fetch_32(&mut self, address: u32) -> u32 {
     if (address & 0b11) != 0 {
       panic!("unaligned address received: {:08x}", address);
     }
@@ -183,6 +203,10 @@ impl Bus {
   }
 
   pub fn mem_read_16(&mut self, address: u32) -> u16 {
+    if !self.watchpoints.is_empty() {
+      self.watchpoints.check(address, 2, false);
+    }
+
     if (address & 0b1) != 0 {
       panic!("unaligned address received: {:032b}", address);
     }
@@ -240,11 +264,54 @@ impl Bus {
     }
   }
 
+  pub fn mark_page_written(&mut self, offset: usize) {
+    let page = &mut self.page_versions[offset >> PAGE_SHIFT];
+
+    *page = page.wrapping_add(1);
+  }
+
+  /// reads a byte for debuggers, without the side effects or timing of mem_read_8.
+  /// returns None for I/O registers
+  pub fn peek_8(&self, address: u32) -> Option<u8> {
+    let address = Bus::translate_address(address);
+
+    match address {
+      0x0000_0000..=0x001f_ffff => Some(self.ram[address as usize]),
+      0x1f80_0000..=0x1f80_03ff => Some(self.scratchpad[(address - 0x1f80_0000) as usize]),
+      0x1fc0_0000..=0x1fc7_ffff => Some(self.bios[(address - 0x1fc0_0000) as usize]),
+      _ => None
+    }
+  }
+
+  /// writes a byte for debuggers, only RAM and the scratchpad can be written.
+  /// returns false for anything else
+  pub fn poke_8(&mut self, address: u32, value: u8) -> bool {
+    let address = Bus::translate_address(address);
+
+    match address {
+      0x0000_0000..=0x001f_ffff => {
+        self.ram[address as usize] = value;
+        self.mark_page_written(address as usize);
+      }
+      0x1f80_0000..=0x1f80_03ff => self.scratchpad[(address - 0x1f80_0000) as usize] = value,
+      _ => return false
+    }
+
+    true
+  }
+
   pub fn mem_write_8(&mut self, address: u32, value: u8) {
+    if !self.watchpoints.is_empty() {
+      self.watchpoints.check(address, 1, true);
+    }
+
     let address = Bus::translate_address(address);
 
     match address {
-      0x0000_0000..=0x001f_ffff => self.ram[address as usize] = value,
+      0x0000_0000..=0x001f_ffff => {
+        self.ram[address as usize] = value;
+        self.mark_page_written(address as usize);
+      }
       0x1f80_0000..=0x1f80_03ff => {
         let offset = (address - 0x1f80_0000) as usize;
 
@@ -280,6 +347,10 @@ impl Bus {
   }
 
   pub fn mem_write_16(&mut self, address: u32, value: u16) {
+    if !self.watchpoints.is_empty() {
+      self.watchpoints.check(address, 2, true);
+    }
+
     if (address & 0b1) != 0 {
       panic!("unaligned address received: {:X}", address);
     }
@@ -292,6 +363,8 @@ impl Bus {
 
         self.ram[offset] = (value & 0xff) as u8;
         self.ram[offset + 1] = ((value >> 8) & 0xff) as u8;
+
+        self.mark_page_written(offset);
       }
       0x1f80_0000..=0x1f80_03ff => {
         let offset = (address - 0x1f80_0000) as usize;
@@ -349,6 +422,10 @@ impl Bus {
   }
 
   pub fn mem_write_32(&mut self, address: u32, value: u32) {
+    if !self.watchpoints.is_empty() {
+      self.watchpoints.check(address, 4, true);
+    }
+
     if (address & 0b11) != 0 {
       panic!("unaligned address received: {:X}", address);
     }
@@ -363,6 +440,8 @@ impl Bus {
         self.ram[offset + 1] = ((value >> 8) & 0xff) as u8;
         self.ram[offset + 2] = ((value >> 16) & 0xff) as u8;
         self.ram[offset + 3] = ((value >> 24)) as u8;
+
+        self.mark_page_written(offset);
       }
       0x1f80_0000..=0x1f80_03ff => {
         let offset = (address - 0x1f80_0000) as usize;
@@ -430,6 +509,10 @@ impl Bus {
     self.dma.set(dma);
   }
 
+  pub fn bios_region(&self) -> Option<Region> {
+    Region::from_bios(&self.bios)
+  }
+
   pub fn reset_cycles(&mut self) {
     let cycles = self.cycles;
     self.cycles = 0;
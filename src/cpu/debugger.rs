@@ -0,0 +1,498 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{bus::Bus, disassembler::REGISTER_NAMES, instruction::Instruction, Cause, CPU, SYNC_CYCLES};
+
+// the debugger is only consulted while it has something to do, so the cpu keeps running
+// compiled blocks otherwise. breakpoints stop before the instruction at their address runs,
+// watchpoints and exceptions stop after the instruction that triggered them
+
+const SP_REGISTER: usize = 29;
+const RA_REGISTER: usize = 31;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operand {
+  Register(usize),
+  Hi,
+  Lo
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Comparison {
+  Equal,
+  NotEqual,
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual
+}
+
+/// a breakpoint condition comparing a register to a value, ie: "a0 == 0x80010000".
+/// comparisons are unsigned
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Condition {
+  pub operand: Operand,
+  pub comparison: Comparison,
+  pub value: u32
+}
+
+impl Condition {
+  /// parses "<register> <comparison> <value>". registers are r0-r31, their ABI names
+  /// (with or without a $), hi or lo. values are decimal or hex with 0x
+  pub fn parse(text: &str) -> Result<Self, String> {
+    let parts: Vec<&str> = text.split_whitespace().collect();
+
+    let [operand, comparison, value] = parts[..] else {
+      return Err(format!("expected <register> <comparison> <value>, got \"{text}\""));
+    };
+
+    let operand = operand.trim_start_matches('$').to_lowercase();
+
+    let operand = match operand.as_str() {
+      "hi" => Operand::Hi,
+      "lo" => Operand::Lo,
+      _ => {
+        let register = REGISTER_NAMES.iter().position(|name| *name == operand)
+          .or_else(|| operand.strip_prefix('r').and_then(|index| index.parse().ok()).filter(|index| *index < 32))
+          .ok_or_else(|| format!("unknown register {operand}"))?;
+
+        Operand::Register(register)
+      }
+    };
+
+    let comparison = match comparison {
+      "==" => Comparison::Equal,
+      "!=" => Comparison::NotEqual,
+      "<" => Comparison::Less,
+      "<=" => Comparison::LessOrEqual,
+      ">" => Comparison::Greater,
+      ">=" => Comparison::GreaterOrEqual,
+      _ => return Err(format!("unknown comparison {comparison}"))
+    };
+
+    let value = match value.strip_prefix("0x") {
+      Some(hex) => u32::from_str_radix(hex, 16),
+      None => value.parse()
+    }.map_err(|_| format!("invalid value {value}"))?;
+
+    Ok(Self {
+      operand,
+      comparison,
+      value
+    })
+  }
+
+  fn holds(&self, cpu: &CPU) -> bool {
+    let operand = match self.operand {
+      Operand::Register(register) => cpu.r[register],
+      Operand::Hi => cpu.hi,
+      Operand::Lo => cpu.low
+    };
+
+    match self.comparison {
+      Comparison::Equal => operand == self.value,
+      Comparison::NotEqual => operand != self.value,
+      Comparison::Less => operand < self.value,
+      Comparison::LessOrEqual => operand <= self.value,
+      Comparison::Greater => operand > self.value,
+      Comparison::GreaterOrEqual => operand >= self.value
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchKind {
+  Read,
+  Write,
+  Access
+}
+
+/// watches length bytes from address, in any mirror (ie: kseg0 and kseg1)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Watchpoint {
+  pub address: u32,
+  pub length: u32,
+  pub kind: WatchKind
+}
+
+impl Watchpoint {
+  fn matches(&self, address: u32, size: u32, write: bool) -> bool {
+    let kind_matches = match self.kind {
+      WatchKind::Read => !write,
+      WatchKind::Write => write,
+      WatchKind::Access => true
+    };
+
+    let start = Bus::translate_address(self.address);
+    let address = Bus::translate_address(address);
+
+    kind_matches && address < start.wrapping_add(self.length) && start < address.wrapping_add(size)
+  }
+}
+
+/// the watchpoints, checked by the bus on every read and write (including DMA)
+#[derive(Default)]
+pub struct Watchpoints {
+  watchpoints: Vec<Watchpoint>,
+  // the first access that matched, until the cpu picks it up
+  hit: Option<(u32, WatchKind)>
+}
+
+impl Watchpoints {
+  pub fn add(&mut self, watchpoint: Watchpoint) {
+    self.watchpoints.push(watchpoint);
+  }
+
+  pub fn remove(&mut self, watchpoint: Watchpoint) {
+    self.watchpoints.retain(|w| *w != watchpoint);
+  }
+
+  pub fn clear(&mut self) {
+    self.watchpoints.clear();
+    self.hit = None;
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.watchpoints.is_empty()
+  }
+
+  pub fn check(&mut self, address: u32, size: u32, write: bool) {
+    if self.hit.is_none() {
+      self.hit = self.watchpoints.iter().find(|w| w.matches(address, size, write)).map(|w| (address, w.kind));
+    }
+  }
+
+  fn take_hit(&mut self) -> Option<(u32, WatchKind)> {
+    self.hit.take()
+  }
+}
+
+/// why the cpu stopped. breakpoints and steps stop before the instruction at pc runs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StopReason {
+  Breakpoint(u32),
+  Watchpoint { pc: u32, address: u32, kind: WatchKind },
+  Exception { cause: Cause, epc: u32 },
+  Step(u32)
+}
+
+// where step over, step out and run to stop
+#[derive(Clone, Copy)]
+struct Target {
+  pc: u32,
+  // step over only stops once the stack is back to where it was, so recursive calls
+  // don't stop early
+  min_sp: Option<u32>
+}
+
+#[derive(Default)]
+pub struct Debugger {
+  breakpoints: HashMap<u32, Option<Condition>>,
+  // cause codes that stop the cpu when raised
+  exceptions: HashSet<u32>,
+  target: Option<Target>,
+  // step out counts calls and returns until the current function returns
+  step_out_depth: Option<u32>,
+  // resuming from a breakpoint runs the instruction there instead of stopping again
+  skip: Option<u32>
+}
+
+impl Debugger {
+  pub fn add_breakpoint(&mut self, pc: u32, condition: Option<Condition>) {
+    self.breakpoints.insert(pc, condition);
+  }
+
+  pub fn remove_breakpoint(&mut self, pc: u32) {
+    self.breakpoints.remove(&pc);
+  }
+
+  pub fn breakpoints(&self) -> impl Iterator<Item = (&u32, &Option<Condition>)> {
+    self.breakpoints.iter()
+  }
+
+  pub fn break_on_exception(&mut self, cause: Cause, enabled: bool) {
+    if enabled {
+      self.exceptions.insert(cause as u32);
+    } else {
+      self.exceptions.remove(&(cause as u32));
+    }
+  }
+
+  /// stops any step over, step out or run to in progress
+  pub fn cancel(&mut self) {
+    self.target = None;
+    self.step_out_depth = None;
+  }
+
+  pub fn clear(&mut self) {
+    self.breakpoints.clear();
+    self.exceptions.clear();
+    self.cancel();
+  }
+
+  pub fn is_active(&self) -> bool {
+    !self.breakpoints.is_empty() || !self.exceptions.is_empty() || self.target.is_some() || self.step_out_depth.is_some()
+  }
+
+  pub(super) fn breaks_on(&self, cause: Cause) -> bool {
+    self.exceptions.contains(&(cause as u32))
+  }
+
+  pub(super) fn resume_at(&mut self, pc: u32) {
+    self.skip = Some(pc);
+  }
+}
+
+impl CPU {
+  // checked before the instruction at pc runs
+  pub(super) fn check_breakpoints(&mut self) -> Option<StopReason> {
+    let pc = self.pc;
+
+    if self.debugger.skip.take() == Some(pc) {
+      return None;
+    }
+
+    if let Some(target) = self.debugger.target {
+      if target.pc == pc && target.min_sp.is_none_or(|sp| self.r[SP_REGISTER] >= sp) {
+        self.debugger.target = None;
+
+        return Some(StopReason::Step(pc));
+      }
+    }
+
+    match self.debugger.breakpoints.get(&pc) {
+      Some(condition) if condition.is_none_or(|condition| condition.holds(self)) => Some(StopReason::Breakpoint(pc)),
+      _ => None
+    }
+  }
+
+  // called with each instruction that's about to run while stepping out
+  pub(super) fn track_calls(&mut self, instr: Instruction) {
+    let Some(depth) = self.debugger.step_out_depth else {
+      return;
+    };
+
+    let is_call = match instr.op_code() {
+      0x0 => instr.op_code_secondary() == 0x9,
+      // bltzal and bgezal, only when taken
+      0x1 => instr.should_link() && (((self.r[instr.rs()] as i32) < 0) as u32 ^ instr.bcond()) == 1,
+      0x3 => true,
+      _ => false
+    };
+
+    let is_return = instr.op_code() == 0 && instr.op_code_secondary() == 0x8 && instr.rs() == RA_REGISTER;
+
+    if is_call {
+      self.debugger.step_out_depth = Some(depth + 1);
+    } else if is_return {
+      if depth == 0 {
+        // stop where it returns to, once the delay slot has run
+        self.debugger.step_out_depth = None;
+        self.debugger.target = Some(Target { pc: self.r[RA_REGISTER], min_sp: None });
+      } else {
+        self.debugger.step_out_depth = Some(depth - 1);
+      }
+    }
+  }
+
+  // picks up watchpoints hit by the last instruction or DMA transfer
+  pub(super) fn check_watchpoints(&mut self) {
+    if let Some((address, kind)) = self.bus.watchpoints.take_hit() {
+      self.stop.get_or_insert(StopReason::Watchpoint { pc: self.current_pc, address, kind });
+    }
+  }
+
+  /// runs a single instruction, not counting DMA transfers that stall the cpu. a
+  /// breakpoint at pc doesn't stop it, but watchpoints and exceptions still do
+  pub fn step_instruction(&mut self) -> StopReason {
+    loop {
+      self.debugger.resume_at(self.pc);
+
+      let executed = self.step();
+
+      self.check_watchpoints();
+
+      if self.bus.cycles - self.bus.last_sync >= SYNC_CYCLES {
+        self.bus.sync_devices();
+      }
+
+      if let Some(stop) = self.take_stop() {
+        return stop;
+      }
+
+      if executed {
+        // resuming runs this instruction even if it has a breakpoint
+        self.debugger.resume_at(self.pc);
+
+        return StopReason::Step(self.pc);
+      }
+    }
+  }
+
+  /// runs until the instruction after this one, running through calls. the stop is
+  /// returned by run_frame
+  pub fn step_over(&mut self) {
+    let instr = Instruction::new(self.peek_instruction());
+
+    let is_call = match instr.op_code() {
+      0x0 => instr.op_code_secondary() == 0x9,
+      0x1 => instr.should_link(),
+      0x3 => true,
+      _ => false
+    };
+
+    // calls return after their delay slot
+    let pc = if is_call { self.pc.wrapping_add(8) } else { self.next_pc };
+
+    self.debugger.target = Some(Target { pc, min_sp: Some(self.r[SP_REGISTER]) });
+  }
+
+  /// runs until the current function returns. the stop is returned by run_frame
+  pub fn step_out(&mut self) {
+    self.debugger.step_out_depth = Some(0);
+  }
+
+  /// runs until pc reaches the address. the stop is returned by run_frame
+  pub fn run_to(&mut self, address: u32) {
+    self.debugger.target = Some(Target { pc: address, min_sp: None });
+  }
+
+  fn peek_instruction(&self) -> u32 {
+    (0..4).fold(0, |word, i| word | (self.bus.peek_8(self.pc.wrapping_add(i)).unwrap_or(0) as u32) << (i * 8))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CODE: u32 = 0x8001_0000;
+  const FUNC: u32 = 0x8001_0100;
+  const LEAF: u32 = 0x8001_0200;
+  const DATA: u32 = 0x8002_0000;
+
+  const NOP: u32 = 0;
+  // beq zero, zero, -1 spins in place
+  const SPIN: u32 = 0x1000_ffff;
+  const RETURN: u32 = 0x03e0_0008;
+
+  // calls FUNC, then adds 1 to t0 and spins
+  const MAIN: [u32; 5] = [0x0c00_4040, NOP, 0x2508_0001, SPIN, NOP];
+  // adds 1 to t1 and calls LEAF, keeping ra in s0 (or s0, ra, zero / jal LEAF /
+  // addiu t1, t1, 1 / or ra, s0, zero)
+  const FUNCTION: [u32; 6] = [0x03e0_8025, 0x0c00_4080, 0x2529_0001, 0x0200_f825, RETURN, NOP];
+
+  fn cpu(start: u32) -> CPU {
+    let mut cpu = CPU::new(vec![0; 512 * 1024], None, None);
+
+    for (address, code) in [(CODE, &MAIN[..]), (FUNC, &FUNCTION[..]), (LEAF, &[RETURN, NOP][..])] {
+      for (i, instr) in code.iter().enumerate() {
+        cpu.bus.mem_write_32(address + i as u32 * 4, *instr);
+      }
+    }
+
+    cpu.pc = start;
+    cpu.next_pc = start + 4;
+    cpu.r[4] = DATA;
+
+    cpu
+  }
+
+  fn watch(code: &[u32], watchpoint: Watchpoint) -> Option<StopReason> {
+    let mut cpu = cpu(CODE);
+
+    for (i, instr) in code.iter().chain(&[SPIN, NOP]).enumerate() {
+      cpu.bus.mem_write_32(CODE + i as u32 * 4, *instr);
+    }
+
+    cpu.bus.watchpoints.add(watchpoint);
+
+    cpu.run_frame()
+  }
+
+  #[test]
+  fn parse_condition() {
+    assert_eq!(Condition::parse("$a0 == 0x80010000"), Ok(Condition { operand: Operand::Register(4), comparison: Comparison::Equal, value: 0x8001_0000 }));
+    assert_eq!(Condition::parse("r31 >= 16"), Ok(Condition { operand: Operand::Register(31), comparison: Comparison::GreaterOrEqual, value: 16 }));
+    assert_eq!(Condition::parse("HI < 2").map(|c| c.operand), Ok(Operand::Hi));
+    assert_eq!(Condition::parse("r32 == 0"), Err("unknown register r32".to_string()));
+    assert_eq!(Condition::parse("t0 =< 0"), Err("unknown comparison =<".to_string()));
+    assert!(Condition::parse("t0 ==").is_err());
+  }
+
+  #[test]
+  fn conditional_breakpoint() {
+    // loop back to the call instead of spinning, so t1 counts the calls
+    let mut cpu = cpu(CODE);
+
+    cpu.bus.mem_write_32(CODE + 12, 0x1000_fffc);
+
+    cpu.debugger.add_breakpoint(FUNC, Some(Condition::parse("t1 == 2").unwrap()));
+
+    assert_eq!(cpu.run_frame(), Some(StopReason::Breakpoint(FUNC)));
+    assert_eq!(cpu.r[9], 2);
+
+    // resuming runs the instruction at the breakpoint instead of stopping again
+    cpu.debugger.add_breakpoint(FUNC, Some(Condition::parse("t1 >= 2").unwrap()));
+
+    assert_eq!(cpu.run_frame(), Some(StopReason::Breakpoint(FUNC)));
+    assert_eq!(cpu.r[9], 3);
+  }
+
+  #[test]
+  fn read_watchpoint() {
+    // lw t1, 0(a0) / sw t1, 0x10(a0), watched through the kseg1 mirror
+    let stop = watch(&[0x8c89_0000, 0xac89_0010], Watchpoint { address: DATA | 0x2000_0000, length: 4, kind: WatchKind::Read });
+
+    assert_eq!(stop, Some(StopReason::Watchpoint { pc: CODE, address: DATA, kind: WatchKind::Read }));
+  }
+
+  #[test]
+  fn write_watchpoint() {
+    let stop = watch(&[0x8c89_0010, 0xac89_0010], Watchpoint { address: DATA + 0x10, length: 4, kind: WatchKind::Write });
+
+    assert_eq!(stop, Some(StopReason::Watchpoint { pc: CODE + 4, address: DATA + 0x10, kind: WatchKind::Write }));
+  }
+
+  #[test]
+  fn range_watchpoint() {
+    // the load is outside the range, the store's last byte is inside it
+    let watchpoint = Watchpoint { address: DATA + 0x13, length: 0x10, kind: WatchKind::Access };
+
+    let stop = watch(&[0x8c89_0000, 0xac89_0010], watchpoint);
+
+    assert_eq!(stop, Some(StopReason::Watchpoint { pc: CODE + 4, address: DATA + 0x10, kind: WatchKind::Access }));
+
+    // lw t1, 0x24(a0) is past the end, lw t1, 0x20(a0) is inside
+    assert_eq!(watch(&[0x8c89_0024, 0x8c89_0020], watchpoint), Some(StopReason::Watchpoint { pc: CODE + 4, address: DATA + 0x20, kind: WatchKind::Access }));
+  }
+
+  #[test]
+  fn step_over() {
+    let mut cpu = cpu(CODE);
+
+    // runs through the call and its delay slot
+    cpu.step_over();
+
+    assert_eq!(cpu.run_frame(), Some(StopReason::Step(CODE + 8)));
+    assert_eq!(cpu.r[9], 1);
+
+    cpu.step_over();
+
+    assert_eq!(cpu.run_frame(), Some(StopReason::Step(CODE + 12)));
+    assert_eq!(cpu.r[8], 1);
+  }
+
+  #[test]
+  fn step_out() {
+    let mut cpu = cpu(FUNC);
+
+    cpu.r[31] = CODE + 8;
+
+    // the call to LEAF returns first, which mustn't stop
+    cpu.step_out();
+
+    assert_eq!(cpu.run_frame(), Some(StopReason::Step(CODE + 8)));
+    assert_eq!(cpu.r[9], 1);
+    assert_eq!(cpu.r[8], 0);
+  }
+}
@@ -1,196 +1,444 @@
-use super::{instruction::Instruction, CPU};
-
-impl CPU {
-    pub fn disassemble(&self, instruction: u32) -> String {
-        let instr = Instruction(instruction);
-
-        let upper = instruction >> 26;
-
-        let mut taken = false;
-
-        let command = match upper {
-            0x0 => match instruction & 0x3f {
-                0x0 => "SLL",
-                0x2 => "SRL",
-                0x3 => "SRA",
-                0x4 => "SLLV",
-                0x6 => "SRLV",
-                0x7 => "SRAV",
-                0x8 => "JR",
-                0x9 => "JALR",
-                0xc => "SYSCALL",
-                0xd => "BREAK",
-                0x10 => "MFHI",
-                0x11 => "MTHI",
-                0x12 => "MFLO",
-                0x13 => "MTLO",
-                0x18 => "MULT",
-                0x19 => "MULTU",
-                0x1a => "DIV",
-                0x1b => "DIVU",
-                0x20 => "ADD",
-                0x21 => "ADDU",
-                0x22 => "SUB",
-                0x23 => "SUBU",
-                0x24 => "AND",
-                0x25 => "OR",
-                0x26 => "XOR",
-                0x27 => "NOR",
-                0x2a => "SLT",
-                0x2b => "SLTU",
-                _ => panic!("unknown instruction received: 0x{:x}", instruction & 0x3f)
-            }
-            0x1 => match instr.rt() {
-                0x0 => {
-                    taken = (self.r[instr.rs()] as i32) < 0;
-                    "BLTZ"
-                }
-                0x1 => {
-                    taken = (self.r[instr.rs()] as i32) >= 0;
-                    "BGEZ"
-                }
-                0x10 => {
-                    taken = (self.r[instr.rs()] as i32) < 0;
-                    "BLTZAL"
-                }
-                0x11 => {
-                    taken = (self.r[instr.rs()] as i32) >= 0;
-                    "BGEZAL"
-                }
-                _ => panic!("unknown value for BcondZ given: 0x{:x}", instr.rt())
-            },
-            0x2 => "J",
-            0x3 => "JAL",
-            0x4 => {
-                taken = self.r[instr.rs()] == self.r[instr.rt()];
-                "BEQ"
-            }
-            0x5 => {
-                taken = self.r[instr.rs()] != self.r[instr.rt()];
-                "BNE"
-            }
-            0x6 => {
-                taken = (self.r[instr.rs()] as i32) <= 0;
-                "BLEZ"
-            }
-            0x7 => {
-                taken = (self.r[instr.rs()] as i32) > 0;
-                "BGTZ"
-            }
-            0x8 => "ADDI",
-            0x9 => "ADDIU",
-            0xa => "SLTI",
-            0xb => "SLTIU",
-            0xc => "ANDI",
-            0xd => "ORI",
-            0xe => "XORI",
-            0xf => "LUI",
-            0x10 => "COP0",
-            0x11 => "COP1",
-            0x12 => "COP2",
-            0x13 => "COP3",
-            0x20 => "LB",
-            0x21 => "LH",
-            0x22 => "LWL",
-            0x23 => "LW",
-            0x24 => "LBU",
-            0x25 => "LHU",
-            0x26 => "LWR",
-            0x28 => "SB",
-            0x29 => "SH",
-            0x2a => "SWL",
-            0x2b => "SW",
-            0x2e => "SWR",
-            0x30 => "LWC0",
-            0x31 => "LWC1",
-            0x32 => "LWC2",
-            0x33 => "LWC3",
-            0x38 => "SWC0",
-            0x39 => "SWC1",
-            0x3a => "SWC2",
-            0x3b => "SWC3",
-            _ => panic!("invalid value given to disassembler: 0x{:x}", upper)
-        };
-
-        let taken_str = format!("(Taken? {})", if taken  { "Yes ✅" } else { "No ❌" });
-
-        // see https://psx-spx.consoledev.net/cpuspecifications/#cpu-opcode-encoding
-        if upper == 0 {
-            if instruction & 0b111100 == 0b100 {
-                return format!("{command} r{}, r{}, r{}", instr.rd(), instr.rt(), instr.rs());
-            }
-            if instruction & 0b111111 <= 0b11 {
-                return format!("{command} r{}, r{}, 0x{:x}", instr.rd(), instr.rt(), instr.imm5());
-            }
-            if instruction & 0b111111 == 0b1000 {
-                return format!("{command} 0x{:x} (r{})", self.r[instr.rs()], instr.rs());
-            }
-            if instruction & 0b111111 == 0b1001 {
-                return format!("{command} r{}, r{}", instr.rd(), instr.rs());
-            }
-            if instruction & 0b111110 == 0b1100 {
-                return command.to_string();
-            }
-            if instruction & 0b111101 == 0b10000 {
-                return format!("{command} r{}", instr.rd());
-            }
-            if instruction & 0b111101 == 0b10001 {
-                return format!("{command} r{}", instr.rs());
-            }
-            if instruction & 0b111100 == 0b11000 {
-                return format!("{command} r{}, r{}", instr.rs(), instr.rt());
-            }
-            if instruction & 0b110000 == 0b100000 {
-                return format!("{command} r{}, r{}, r{}", instr.rd(), instr.rs(), instr.rt());
-            }
-        }
+use std::{collections::BTreeMap, fmt};
 
-        if upper == 1 {
-            return format!("{command} r{}, 0x{:x}", instr.rs(), instr.immediate());
-        }
+use super::instruction::Instruction;
 
-        if upper & 0b111110 == 0b10 {
-            return format!("{command} 0x{:x}", (instr.j_imm() << 2) | (self.pc & 0xf0000000));
-        }
+// a standalone disassembler, so it can be used on any word without a running cpu. encodings
+// the cpu has no instruction for come out as .word instead of panicking
 
-        if upper & 0b111110 == 0b100 {
-            let destination = ((self.pc as i32) + ((instr.immediate_signed() as i32) << 2)) as u32;
-            return format!("{command} r{}, r{}, 0x{:x} {}", instr.rs(), instr.rt(), destination, taken_str);
-        }
+pub const REGISTER_NAMES: [&str; 32] = [
+  "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3",
+  "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+  "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7",
+  "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra"
+];
 
-        if upper & 0b111110 == 0b110 {
-            let destination = ((self.pc as i32) + ((instr.immediate_signed() as i32) << 2)) as u32;
-            return format!("{command} r{}, 0x{:x} {}", instr.rs(), destination, taken_str);
-        }
+const COP0_REGISTER_NAMES: [Option<&str>; 16] = [
+  None, None, None, Some("bpc"), None, Some("bda"), Some("jumpdest"), Some("dcic"),
+  Some("badvaddr"), Some("bdam"), None, Some("bpcm"), Some("sr"), Some("cause"), Some("epc"), Some("prid")
+];
 
-        if upper & 0b111000 == 0b1000 {
-            return format!("{command} r{}, r{}, 0x{:x}", instr.rt(), instr.rs(), instr.immediate());
-        }
+const GTE_DATA_NAMES: [&str; 32] = [
+  "vxy0", "vz0", "vxy1", "vz1", "vxy2", "vz2", "rgbc", "otz",
+  "ir0", "ir1", "ir2", "ir3", "sxy0", "sxy1", "sxy2", "sxyp",
+  "sz0", "sz1", "sz2", "sz3", "rgb0", "rgb1", "rgb2", "res1",
+  "mac0", "mac1", "mac2", "mac3", "irgb", "orgb", "lzcs", "lzcr"
+];
 
-        if upper & 0b111111 == 0b1111 {
-            return format!("{command} r{}, 0x{:x}", instr.rt(), instr.immediate());
-        }
+const GTE_CONTROL_NAMES: [&str; 32] = [
+  "rt11rt12", "rt13rt21", "rt22rt23", "rt31rt32", "rt33", "trx", "try", "trz",
+  "l11l12", "l13l21", "l22l23", "l31l32", "l33", "rbk", "gbk", "bbk",
+  "lr1lr2", "lr3lg1", "lg2lg3", "lb1lb2", "lb3", "rfc", "gfc", "bfc",
+  "ofx", "ofy", "h", "dqa", "dqb", "zsf3", "zsf4", "flag"
+];
 
-        if upper & 0b110000 == 0b100000 {
-            return format!("{command} r{}, [r{} + 0x{:x}]", instr.rt(), instr.rs(), instr.immediate());
-        }
+// mvmva's matrix, vector and translation vector
+const GTE_MATRICES: [&str; 4] = ["rt", "llm", "lcm", "garbage"];
+const GTE_VECTORS: [&str; 4] = ["v0", "v1", "v2", "ir"];
+const GTE_TRANSLATIONS: [&str; 4] = ["tr", "bk", "fc", "none"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operand {
+  Register(usize),
+  Cop0Register(usize),
+  GteData(usize),
+  GteControl(usize),
+  Immediate(i32),
+  // a branch or jump target
+  Target(u32),
+  // offset(base) of a load or store
+  Memory { base: usize, offset: i32 },
+  // the code field of syscall and break
+  Code(u32),
+  // a GTE command flag, ie: sf=1 or mx=rt
+  Flag(&'static str, &'static str),
+  // the raw word of an invalid encoding
+  Word(u32)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Disassembly {
+  pub mnemonic: &'static str,
+  pub operands: Vec<Operand>
+}
+
+/// symbol names for annotating branch targets, ie: loaded from a .sym or nm listing
+#[derive(Default)]
+pub struct Symbols {
+  names: BTreeMap<u32, String>
+}
+
+impl Symbols {
+  /// parses lines of "<hex address> [type] <name>", as written by nm and no$psx. blank
+  /// lines and lines starting with # are skipped
+  pub fn parse(text: &str) -> Result<Self, String> {
+    let mut symbols = Self::default();
+
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let mut parts = line.split_whitespace();
+
+      let (Some(address), Some(name)) = (parts.next(), parts.last()) else {
+        return Err(format!("line {}: expected an address and a name", i + 1));
+      };
+
+      let address = u32::from_str_radix(address.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("line {}: invalid address {address}", i + 1))?;
+
+      symbols.insert(address, name.to_string());
+    }
+
+    Ok(symbols)
+  }
+
+  pub fn insert(&mut self, address: u32, name: String) {
+    self.names.insert(address, name);
+  }
+
+  pub fn get(&self, address: u32) -> Option<&str> {
+    self.names.get(&address).map(|name| name.as_str())
+  }
+
+  /// the closest symbol at or before address, ie: "main+0x10"
+  pub fn describe(&self, address: u32) -> Option<String> {
+    let (start, name) = self.names.range(..=address).next_back()?;
+
+    Some(match address - start {
+      0 => name.clone(),
+      offset => format!("{name}+0x{offset:x}")
+    })
+  }
+}
+
+impl Disassembly {
+  fn new(mnemonic: &'static str, operands: Vec<Operand>) -> Self {
+    Self {
+      mnemonic,
+      operands
+    }
+  }
+
+  fn invalid(instr: Instruction) -> Self {
+    Self::new(".word", vec![Operand::Word(instr.0)])
+  }
 
-        let upper_cop0 = instruction >> 28;
-        let mid = (instruction >> 21) & 0x1f;
-
-        let processor_number = (instruction >> 26) & 0x3;
-        match upper_cop0 {
-            0x4 => match mid {
-                0x0 => return format!("MFC{} r{}, r{}", processor_number, instr.rt(), instr.rd()),
-                0x2 => return format!("CFC{} r{}, r{}", processor_number, instr.rt(), instr.rd()),
-                0x4 => return format!("MTC{} r{}, r{}", processor_number, instr.rt(), instr.rd()),
-                0x6 => return format!("CTC{} r{}, r{}", processor_number, instr.rt(), instr.rd()),
-                0x10 => return "RFE".to_string(),
-                _ => format!("GTE 0x{:x}", instr.cop2_command())
-            }
-            0xc => format!("LWC{} r{}, [r{} + 0x{:x}]", processor_number, instr.rt(), instr.rs(), instr.immediate()),
-            0xe => format!("SWC{} r{}, [r{} + 0x{:x}]", processor_number, instr.rt(), instr.rs(), instr.immediate()),
-            _ => panic!("invalid option for cop instruction given: 0x{:x}", upper_cop0)
+  /// like to_string, with branch and jump targets annotated with their symbols
+  pub fn annotate(&self, symbols: &Symbols) -> String {
+    let mut text = self.to_string();
+
+    for operand in &self.operands {
+      if let Operand::Target(address) = operand {
+        if let Some(name) = symbols.describe(*address) {
+          text.push_str(&format!(" <{name}>"));
         }
+      }
+    }
+
+    text
+  }
+}
+
+impl fmt::Display for Operand {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      Operand::Register(register) => write!(f, "{}", REGISTER_NAMES[register]),
+      Operand::Cop0Register(register) => match COP0_REGISTER_NAMES.get(register).copied().flatten() {
+        Some(name) => write!(f, "{name}"),
+        None => write!(f, "${register}")
+      }
+      Operand::GteData(register) => write!(f, "{}", GTE_DATA_NAMES[register]),
+      Operand::GteControl(register) => write!(f, "{}", GTE_CONTROL_NAMES[register]),
+      Operand::Immediate(value) if value < 0 => write!(f, "-0x{:x}", value.unsigned_abs()),
+      Operand::Immediate(value) => write!(f, "0x{value:x}"),
+      Operand::Target(address) => write!(f, "0x{address:08x}"),
+      Operand::Memory { base, offset } if offset < 0 => write!(f, "-0x{:x}({})", offset.unsigned_abs(), REGISTER_NAMES[base]),
+      Operand::Memory { base, offset } => write!(f, "0x{offset:x}({})", REGISTER_NAMES[base]),
+      Operand::Code(code) => write!(f, "0x{code:x}"),
+      Operand::Flag(name, value) => write!(f, "{name}={value}"),
+      Operand::Word(word) => write!(f, "0x{word:08x}")
+    }
+  }
+}
+
+impl fmt::Display for Disassembly {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.mnemonic)?;
+
+    for (i, operand) in self.operands.iter().enumerate() {
+      write!(f, "{}{operand}", if i == 0 { " " } else { ", " })?;
+    }
+
+    Ok(())
+  }
+}
+
+/// disassembles the instruction at address, which branch and jump targets are relative to
+pub fn disassemble(address: u32, instr: Instruction) -> Disassembly {
+  use Operand::*;
+
+  let rs = Register(instr.rs());
+  let rt = Register(instr.rt());
+  let signed = Immediate(instr.immediate_signed() as i32);
+  let unsigned = Immediate(instr.immediate() as i32);
+  let memory = Memory { base: instr.rs(), offset: instr.immediate_signed() as i32 };
+  let branch_target = Target(address.wrapping_add(4).wrapping_add(instr.immediate_signed() << 2));
+  let jump_target = Target((address.wrapping_add(4) & 0xf000_0000) | (instr.j_imm() << 2));
+
+  let (mnemonic, operands) = match instr.op_code() {
+    0x0 => return disassemble_special(instr),
+    // the cpu only looks at the lowest bit and the link bits of rt
+    0x1 => {
+      let mnemonic = match (instr.bcond() == 1, instr.should_link()) {
+        (false, false) => "bltz",
+        (true, false) => "bgez",
+        (false, true) => "bltzal",
+        (true, true) => "bgezal"
+      };
+
+      (mnemonic, vec![rs, branch_target])
     }
-}
\ No newline at end of file
+    0x2 => ("j", vec![jump_target]),
+    0x3 => ("jal", vec![jump_target]),
+    0x4 if instr.rs() == 0 && instr.rt() == 0 => ("b", vec![branch_target]),
+    0x4 => ("beq", vec![rs, rt, branch_target]),
+    0x5 => ("bne", vec![rs, rt, branch_target]),
+    0x6 => ("blez", vec![rs, branch_target]),
+    0x7 => ("bgtz", vec![rs, branch_target]),
+    0x8 => ("addi", vec![rt, rs, signed]),
+    0x9 if instr.rs() == 0 => ("li", vec![rt, signed]),
+    0x9 => ("addiu", vec![rt, rs, signed]),
+    0xa => ("slti", vec![rt, rs, signed]),
+    0xb => ("sltiu", vec![rt, rs, signed]),
+    0xc => ("andi", vec![rt, rs, unsigned]),
+    0xd => ("ori", vec![rt, rs, unsigned]),
+    0xe => ("xori", vec![rt, rs, unsigned]),
+    0xf => ("lui", vec![rt, unsigned]),
+    0x10 => return disassemble_cop0(instr),
+    0x12 => return disassemble_cop2(instr),
+    0x20 => ("lb", vec![rt, memory]),
+    0x21 => ("lh", vec![rt, memory]),
+    0x22 => ("lwl", vec![rt, memory]),
+    0x23 => ("lw", vec![rt, memory]),
+    0x24 => ("lbu", vec![rt, memory]),
+    0x25 => ("lhu", vec![rt, memory]),
+    0x26 => ("lwr", vec![rt, memory]),
+    0x28 => ("sb", vec![rt, memory]),
+    0x29 => ("sh", vec![rt, memory]),
+    0x2a => ("swl", vec![rt, memory]),
+    0x2b => ("sw", vec![rt, memory]),
+    0x2e => ("swr", vec![rt, memory]),
+    0x32 => ("lwc2", vec![GteData(instr.rt()), memory]),
+    0x3a => ("swc2", vec![GteData(instr.rt()), memory]),
+    // cop1 and cop3 don't exist on the psx
+    _ => return Disassembly::invalid(instr)
+  };
+
+  Disassembly::new(mnemonic, operands)
+}
+
+fn disassemble_special(instr: Instruction) -> Disassembly {
+  use Operand::*;
+
+  if instr.0 == 0 {
+    return Disassembly::new("nop", Vec::new());
+  }
+
+  let rs = Register(instr.rs());
+  let rt = Register(instr.rt());
+  let rd = Register(instr.rd());
+  let shift = Immediate(instr.imm5() as i32);
+  let code = Code((instr.0 >> 6) & 0xf_ffff);
+
+  let (mnemonic, operands) = match instr.op_code_secondary() {
+    0x0 => ("sll", vec![rd, rt, shift]),
+    0x2 => ("srl", vec![rd, rt, shift]),
+    0x3 => ("sra", vec![rd, rt, shift]),
+    0x4 => ("sllv", vec![rd, rt, rs]),
+    0x6 => ("srlv", vec![rd, rt, rs]),
+    0x7 => ("srav", vec![rd, rt, rs]),
+    0x8 => ("jr", vec![rs]),
+    0x9 if instr.rd() == 31 => ("jalr", vec![rs]),
+    0x9 => ("jalr", vec![rd, rs]),
+    0xc => ("syscall", vec![code]),
+    0xd => ("break", vec![code]),
+    0x10 => ("mfhi", vec![rd]),
+    0x11 => ("mthi", vec![rs]),
+    0x12 => ("mflo", vec![rd]),
+    0x13 => ("mtlo", vec![rs]),
+    0x18 => ("mult", vec![rs, rt]),
+    0x19 => ("multu", vec![rs, rt]),
+    0x1a => ("div", vec![rs, rt]),
+    0x1b => ("divu", vec![rs, rt]),
+    0x20 => ("add", vec![rd, rs, rt]),
+    0x21 if instr.rt() == 0 => ("move", vec![rd, rs]),
+    0x21 => ("addu", vec![rd, rs, rt]),
+    0x22 => ("sub", vec![rd, rs, rt]),
+    0x23 => ("subu", vec![rd, rs, rt]),
+    0x24 => ("and", vec![rd, rs, rt]),
+    0x25 => ("or", vec![rd, rs, rt]),
+    0x26 => ("xor", vec![rd, rs, rt]),
+    0x27 => ("nor", vec![rd, rs, rt]),
+    0x2a => ("slt", vec![rd, rs, rt]),
+    0x2b => ("sltu", vec![rd, rs, rt]),
+    _ => return Disassembly::invalid(instr)
+  };
+
+  Disassembly::new(mnemonic, operands)
+}
+
+fn disassemble_cop0(instr: Instruction) -> Disassembly {
+  use Operand::*;
+
+  let (mnemonic, operands) = match instr.cop_code() {
+    0x0 => ("mfc0", vec![Register(instr.rt()), Cop0Register(instr.rd())]),
+    0x4 => ("mtc0", vec![Register(instr.rt()), Cop0Register(instr.rd())]),
+    0x10 if instr.op_code_secondary() == 0x10 => ("rfe", Vec::new()),
+    _ => return Disassembly::invalid(instr)
+  };
+
+  Disassembly::new(mnemonic, operands)
+}
+
+fn disassemble_cop2(instr: Instruction) -> Disassembly {
+  use Operand::*;
+
+  let (mnemonic, operands) = match instr.cop_code() {
+    0x0 => ("mfc2", vec![Register(instr.rt()), GteData(instr.rd())]),
+    0x2 => ("cfc2", vec![Register(instr.rt()), GteControl(instr.rd())]),
+    0x4 => ("mtc2", vec![Register(instr.rt()), GteData(instr.rd())]),
+    0x6 => ("ctc2", vec![Register(instr.rt()), GteControl(instr.rd())]),
+    op_code if op_code & 0x10 != 0 => return disassemble_gte_command(instr),
+    _ => return Disassembly::invalid(instr)
+  };
+
+  Disassembly::new(mnemonic, operands)
+}
+
+// see https://psx-spx.consoledev.net/geometrytransformationenginegte/#gte-command-encoding-cop2-imm25
+fn disassemble_gte_command(instr: Instruction) -> Disassembly {
+  let command = instr.cop2_command();
+
+  let mnemonic = match command & 0x3f {
+    0x01 => "rtps",
+    0x06 => "nclip",
+    0x0c => "op",
+    0x10 => "dpcs",
+    0x11 => "intpl",
+    0x12 => "mvmva",
+    0x13 => "ncds",
+    0x14 => "cdp",
+    0x16 => "ncdt",
+    0x1b => "nccs",
+    0x1c => "cc",
+    0x1e => "ncs",
+    0x20 => "nct",
+    0x28 => "sqr",
+    0x29 => "dpcl",
+    0x2a => "dpct",
+    0x2d => "avsz3",
+    0x2e => "avsz4",
+    0x30 => "rtpt",
+    0x3d => "gpf",
+    0x3e => "gpl",
+    0x3f => "ncct",
+    _ => return Disassembly::invalid(instr)
+  };
+
+  let flag = |bit: u32| if (command >> bit) & 0b1 == 1 { "1" } else { "0" };
+
+  let mut operands = vec![Operand::Flag("sf", flag(19))];
+
+  if mnemonic == "mvmva" {
+    operands.push(Operand::Flag("mx", GTE_MATRICES[((command >> 17) & 0x3) as usize]));
+    operands.push(Operand::Flag("v", GTE_VECTORS[((command >> 15) & 0x3) as usize]));
+    operands.push(Operand::Flag("cv", GTE_TRANSLATIONS[((command >> 13) & 0x3) as usize]));
+  }
+
+  operands.push(Operand::Flag("lm", flag(10)));
+
+  Disassembly::new(mnemonic, operands)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn text(address: u32, word: u32) -> String {
+    disassemble(address, Instruction::new(word)).to_string()
+  }
+
+  #[test]
+  fn branch_targets() {
+    // beq a0, a1, -1 branches back to itself
+    assert_eq!(text(0x8001_0000, 0x1085_ffff), "beq a0, a1, 0x80010000");
+    assert_eq!(text(0x8001_0000, 0x1000_0003), "b 0x80010010");
+    assert_eq!(text(0x8001_0000, 0x1c80_0001), "bgtz a0, 0x80010008");
+  }
+
+  #[test]
+  fn jump_targets_use_delay_slot() {
+    // the top 4 bits come from the delay slot's address, not the jump's
+    assert_eq!(text(0x8fff_fffc, 0x0800_0100), "j 0x90000400");
+    assert_eq!(text(0x8fff_fffc, 0x0c00_0100), "jal 0x90000400");
+    assert_eq!(text(0x8001_0000, 0x0c00_4000), "jal 0x80010000");
+  }
+
+  #[test]
+  fn regimm() {
+    assert_eq!(text(0x8001_0000, 0x0480_0002), "bltz a0, 0x8001000c");
+    assert_eq!(text(0x8001_0000, 0x0481_0002), "bgez a0, 0x8001000c");
+    assert_eq!(text(0x8001_0000, 0x0490_0002), "bltzal a0, 0x8001000c");
+    assert_eq!(text(0x8001_0000, 0x0491_0002), "bgezal a0, 0x8001000c");
+    // only rt's lowest bit and the link bits are decoded
+    assert_eq!(text(0x8001_0000, 0x0492_0002), "bltz a0, 0x8001000c");
+    assert_eq!(text(0x8001_0000, 0x0483_0002), "bgez a0, 0x8001000c");
+  }
+
+  #[test]
+  fn invalid_encodings() {
+    // cop1 and cop3
+    assert_eq!(text(0, 0x4400_0000), ".word 0x44000000");
+    assert_eq!(text(0, 0x4c00_0000), ".word 0x4c000000");
+    // special function 0x01
+    assert_eq!(text(0, 0x0000_0001), ".word 0x00000001");
+    // cop0 command other than rfe
+    assert_eq!(text(0, 0x4200_0001), ".word 0x42000001");
+    assert_eq!(text(0, 0x4200_0010), "rfe");
+  }
+
+  #[test]
+  fn gte_flags() {
+    assert_eq!(text(0, 0x4a00_0001), "rtps sf=0, lm=0");
+    assert_eq!(text(0, 0x4a08_0406), "nclip sf=1, lm=1");
+    // mx=llm, v=ir, cv=fc
+    assert_eq!(text(0, 0x4a0b_c412), "mvmva sf=1, mx=llm, v=ir, cv=fc, lm=1");
+    assert_eq!(text(0, 0x4a00_6012), "mvmva sf=0, mx=rt, v=v0, cv=none, lm=0");
+  }
+
+  #[test]
+  fn parse_symbols() {
+    let symbols = Symbols::parse("# comment\n\n80010000 T main\n0x80020000 helper\n").unwrap();
+
+    assert_eq!(symbols.get(0x8001_0000), Some("main"));
+    assert_eq!(symbols.get(0x8002_0000), Some("helper"));
+
+    assert_eq!(Symbols::parse("80010000 main\n80020000").err().as_deref(), Some("line 2: expected an address and a name"));
+    assert_eq!(Symbols::parse("\nmain 80010000").err().as_deref(), Some("line 2: invalid address main"));
+  }
+
+  #[test]
+  fn describe() {
+    let symbols = Symbols::parse("80010000 main\n80020000 helper").unwrap();
+
+    assert_eq!(symbols.describe(0x8001_0000).as_deref(), Some("main"));
+    assert_eq!(symbols.describe(0x8001_0010).as_deref(), Some("main+0x10"));
+    assert_eq!(symbols.describe(0x8002_0004).as_deref(), Some("helper+0x4"));
+    assert_eq!(symbols.describe(0x8000_fffc), None);
+
+    assert_eq!(disassemble(0x8001_0100, Instruction::new(0x0c00_4004)).annotate(&symbols), "jal 0x80010010 <main+0x10>");
+  }
+}
@@ -0,0 +1,593 @@
+use std::mem::{self, offset_of};
+
+use dynasmrt::{dynasm, x64::Assembler, AssemblyOffset, DynamicLabel, DynasmApi, DynasmLabelApi, ExecutableBuffer};
+
+use super::{block_cache::block_instructions, instruction::Instruction, CPU};
+
+#[cfg(not(target_arch = "x86_64"))]
+compile_error!("the dynarec only supports x86-64 hosts");
+
+// translates blocks of RAM code (the same blocks as the cached interpreter) to x86-64.
+// every instruction first calls enter_instruction, so interrupts, the instruction cache,
+// timing and the end of the block work exactly like in the interpreter. alu ops and
+// branches then run natively on the cpu's registers, loads and stores go through the
+// same cache and bus accessors as the interpreter, and the rest calls its handler.
+//
+// register use in the generated code:
+//   rbx: the CPU
+//   r12, r13: values that have to survive a call
+//   r14: the pc the block was entered at
+
+pub struct NativeBlock {
+  code: ExecutableBuffer,
+  entry: AssemblyOffset,
+  // pointed to by the generated code, for instructions that fall back to the interpreter
+  _handlers: Vec<fn(&mut CPU, Instruction)>
+}
+
+impl NativeBlock {
+  pub fn compile(address: usize, ram: &[u8]) -> Self {
+    let instructions = block_instructions(address, ram);
+
+    let handlers: Vec<fn(&mut CPU, Instruction)> = instructions.iter().map(CPU::decode).collect();
+
+    let mut compiler = Compiler::new();
+
+    let entry = compiler.ops.offset();
+
+    compiler.prologue();
+
+    for (i, (instr, handler)) in instructions.iter().zip(handlers.iter()).enumerate() {
+      let after_memory_access = i > 0 && instructions[i - 1].op_code() >= 0x20;
+
+      compiler.enter_instruction(i, instr, after_memory_access);
+      compiler.instruction(instr, handler);
+    }
+
+    compiler.epilogue();
+
+    Self {
+      code: compiler.ops.finalize().unwrap_or_else(|_| panic!("couldn't finalize dynarec block")),
+      entry,
+      _handlers: handlers
+    }
+  }
+
+  fn run(&self, cpu: &mut CPU) {
+    let entry = unsafe { mem::transmute::<*const u8, extern "sysv64" fn(*mut CPU, u32)>(self.code.ptr(self.entry)) };
+
+    let pc = cpu.pc;
+
+    entry(cpu, pc);
+  }
+}
+
+struct Compiler {
+  ops: Assembler,
+  exit: DynamicLabel,
+  // whether a load issued by an earlier instruction could still be waiting in its delay slot
+  load_pending: bool
+}
+
+impl Compiler {
+  fn new() -> Self {
+    let mut ops = Assembler::new().unwrap_or_else(|e| panic!("couldn't allocate dynarec memory: {e}"));
+
+    let exit = ops.new_dynamic_label();
+
+    Self {
+      ops,
+      exit,
+      // the block can be entered right after a load
+      load_pending: true
+    }
+  }
+
+  fn reg(r: usize) -> i32 {
+    (offset_of!(CPU, r) + r * 4) as i32
+  }
+
+  fn pc() -> i32 {
+    offset_of!(CPU, pc) as i32
+  }
+
+  fn next_pc() -> i32 {
+    offset_of!(CPU, next_pc) as i32
+  }
+
+  fn branch() -> i32 {
+    offset_of!(CPU, branch) as i32
+  }
+
+  fn prologue(&mut self) {
+    // 4 pushes and the return address leave the stack 8 bytes off the 16 byte alignment calls need
+    dynasm!(self.ops
+      ; .arch x64
+      ; push rbx
+      ; push r12
+      ; push r13
+      ; push r14
+      ; sub rsp, 8
+      ; mov rbx, rdi
+      ; mov r14d, esi
+    );
+  }
+
+  fn epilogue(&mut self) {
+    let exit = self.exit;
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; =>exit
+      ; add rsp, 8
+      ; pop r14
+      ; pop r13
+      ; pop r12
+      ; pop rbx
+      ; ret
+    );
+  }
+
+  fn call(&mut self, function: *const u8) {
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov rax, QWORD function as i64
+      ; call rax
+    );
+  }
+
+  fn enter_instruction(&mut self, i: usize, instr: &Instruction, after_memory_access: bool) {
+    let exit = self.exit;
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov rdi, rbx
+      ; mov esi, r14d
+      ; add esi, (i * 4) as i32
+      ; mov edx, instr.0 as i32
+      ; mov ecx, (i == 0) as i32
+      ; mov r8d, after_memory_access as i32
+    );
+
+    self.call(enter_instruction as *const u8);
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; test eax, eax
+      ; jz =>exit
+    );
+  }
+
+  fn load_delay(&mut self) {
+    if self.load_pending {
+      dynasm!(self.ops
+        ; .arch x64
+        ; mov rdi, rbx
+      );
+
+      self.call(load_delay as *const u8);
+
+      self.load_pending = false;
+    }
+  }
+
+  // writes the result in eax to a register, after any pending load like set_reg in the interpreter
+  fn set_reg(&mut self, reg: usize) {
+    if self.load_pending {
+      dynasm!(self.ops
+        ; .arch x64
+        ; mov r12d, eax
+      );
+
+      self.load_delay();
+
+      dynasm!(self.ops
+        ; .arch x64
+        ; mov eax, r12d
+      );
+    }
+
+    if reg != 0 {
+      dynasm!(self.ops
+        ; .arch x64
+        ; mov DWORD [rbx + Self::reg(reg)], eax
+      );
+    }
+  }
+
+  fn interpret(&mut self, instr: &Instruction, handler: &fn(&mut CPU, Instruction)) {
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov rdi, rbx
+      ; mov rsi, QWORD handler as *const _ as i64
+      ; mov edx, instr.0 as i32
+    );
+
+    self.call(interpret as *const u8);
+
+    self.load_pending = true;
+  }
+
+  fn instruction(&mut self, instr: &Instruction, handler: &fn(&mut CPU, Instruction)) {
+    let (rs, rt, rd) = (Self::reg(instr.rs()), Self::reg(instr.rt()), instr.rd());
+
+    let imm5 = instr.imm5() as i8;
+    let immediate = instr.immediate() as i32;
+    let immediate_signed = instr.immediate_signed() as i32;
+
+    match instr.op_code() {
+      0x0 => match instr.op_code_secondary() {
+        // sll, srl, sra
+        0x0 => {
+          dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rt] ; shl eax, imm5);
+          self.set_reg(rd);
+        }
+        0x2 => {
+          dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rt] ; shr eax, imm5);
+          self.set_reg(rd);
+        }
+        0x3 => {
+          dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rt] ; sar eax, imm5);
+          self.set_reg(rd);
+        }
+        // sllv, srlv, srav. x86 masks the shift amount to 5 bits just like the R3000
+        0x4 => {
+          dynasm!(self.ops ; .arch x64 ; mov ecx, DWORD [rbx + rs] ; mov eax, DWORD [rbx + rt] ; shl eax, cl);
+          self.set_reg(rd);
+        }
+        0x6 => {
+          dynasm!(self.ops ; .arch x64 ; mov ecx, DWORD [rbx + rs] ; mov eax, DWORD [rbx + rt] ; shr eax, cl);
+          self.set_reg(rd);
+        }
+        0x7 => {
+          dynasm!(self.ops ; .arch x64 ; mov ecx, DWORD [rbx + rs] ; mov eax, DWORD [rbx + rt] ; sar eax, cl);
+          self.set_reg(rd);
+        }
+        // jr
+        0x8 => {
+          dynasm!(self.ops
+            ; .arch x64
+            ; mov eax, DWORD [rbx + rs]
+            ; mov DWORD [rbx + Self::next_pc()], eax
+            ; mov BYTE [rbx + Self::branch()], 1
+          );
+
+          self.load_delay();
+        }
+        // jalr
+        0x9 => {
+          dynasm!(self.ops
+            ; .arch x64
+            ; mov r12d, DWORD [rbx + Self::next_pc()]
+            ; mov eax, DWORD [rbx + rs]
+            ; mov DWORD [rbx + Self::next_pc()], eax
+            ; mov BYTE [rbx + Self::branch()], 1
+          );
+
+          self.load_delay();
+
+          dynasm!(self.ops ; .arch x64 ; mov eax, r12d);
+          self.set_reg(rd);
+        }
+        // addu, subu, and, or, xor, nor
+        0x21 => {
+          dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; add eax, DWORD [rbx + rt]);
+          self.set_reg(rd);
+        }
+        0x23 => {
+          dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; sub eax, DWORD [rbx + rt]);
+          self.set_reg(rd);
+        }
+        0x24 => {
+          dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; and eax, DWORD [rbx + rt]);
+          self.set_reg(rd);
+        }
+        0x25 => {
+          dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; or eax, DWORD [rbx + rt]);
+          self.set_reg(rd);
+        }
+        0x26 => {
+          dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; xor eax, DWORD [rbx + rt]);
+          self.set_reg(rd);
+        }
+        0x27 => {
+          dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; or eax, DWORD [rbx + rt] ; not eax);
+          self.set_reg(rd);
+        }
+        // slt, sltu
+        0x2a => {
+          dynasm!(self.ops ; .arch x64 ; mov ecx, DWORD [rbx + rs] ; xor eax, eax ; cmp ecx, DWORD [rbx + rt] ; setl al);
+          self.set_reg(rd);
+        }
+        0x2b => {
+          dynasm!(self.ops ; .arch x64 ; mov ecx, DWORD [rbx + rs] ; xor eax, eax ; cmp ecx, DWORD [rbx + rt] ; setb al);
+          self.set_reg(rd);
+        }
+        _ => self.interpret(instr, handler)
+      },
+      // j, jal
+      0x2 => self.jump(instr.j_imm() << 2, false),
+      0x3 => self.jump(instr.j_imm() << 2, true),
+      // beq, bne, blez, bgtz
+      0x4..=0x7 => self.branch_if(instr),
+      // addiu, slti, sltiu, andi, ori, xori, lui
+      0x9 => {
+        dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; add eax, immediate_signed);
+        self.set_reg(instr.rt());
+      }
+      0xa => {
+        dynasm!(self.ops ; .arch x64 ; mov ecx, DWORD [rbx + rs] ; xor eax, eax ; cmp ecx, immediate_signed ; setl al);
+        self.set_reg(instr.rt());
+      }
+      0xb => {
+        dynasm!(self.ops ; .arch x64 ; mov ecx, DWORD [rbx + rs] ; xor eax, eax ; cmp ecx, immediate_signed ; setb al);
+        self.set_reg(instr.rt());
+      }
+      0xc => {
+        dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; and eax, immediate);
+        self.set_reg(instr.rt());
+      }
+      0xd => {
+        dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; or eax, immediate);
+        self.set_reg(instr.rt());
+      }
+      0xe => {
+        dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; xor eax, immediate);
+        self.set_reg(instr.rt());
+      }
+      0xf => {
+        dynasm!(self.ops ; .arch x64 ; mov eax, immediate << 16);
+        self.set_reg(instr.rt());
+      }
+      // gte commands
+      0x12 if instr.cop_code() & 0x10 == 0x10 => {
+        dynasm!(self.ops
+          ; .arch x64
+          ; mov rdi, rbx
+          ; mov esi, instr.0 as i32
+        );
+
+        self.call(gte_command as *const u8);
+
+        self.load_pending = false;
+      }
+      // lb, lh, lw, lbu, lhu
+      0x20 => self.load(instr, handler, 0, load_8_signed as *const u8),
+      0x21 => self.load(instr, handler, 0b1, load_16_signed as *const u8),
+      0x23 => self.load(instr, handler, 0b11, load_32 as *const u8),
+      0x24 => self.load(instr, handler, 0, load_8 as *const u8),
+      0x25 => self.load(instr, handler, 0b1, load_16 as *const u8),
+      // sb, sh, sw
+      0x28 => self.store(instr, handler, 0, store_8 as *const u8),
+      0x29 => self.store(instr, handler, 0b1, store_16 as *const u8),
+      0x2b => self.store(instr, handler, 0b11, store_32 as *const u8),
+      _ => self.interpret(instr, handler)
+    }
+  }
+
+  fn jump(&mut self, target: u32, link: bool) {
+    self.load_delay();
+
+    // the return address is read before next_pc is replaced, like in the interpreter
+    if link {
+      dynasm!(self.ops
+        ; .arch x64
+        ; mov r12d, DWORD [rbx + Self::next_pc()]
+      );
+    }
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov eax, DWORD [rbx + Self::pc()]
+      ; and eax, 0xf000_0000_u32 as i32
+      ; or eax, target as i32
+      ; mov DWORD [rbx + Self::next_pc()], eax
+      ; mov BYTE [rbx + Self::branch()], 1
+    );
+
+    if link {
+      dynasm!(self.ops
+        ; .arch x64
+        ; mov DWORD [rbx + Self::reg(31)], r12d
+      );
+    }
+  }
+
+  fn branch_if(&mut self, instr: &Instruction) {
+    let (rs, rt) = (Self::reg(instr.rs()), Self::reg(instr.rt()));
+    let offset = (instr.immediate_signed() << 2) as i32;
+
+    let not_taken = self.ops.new_dynamic_label();
+
+    // compare and skip the branch when the condition doesn't hold
+    match instr.op_code() {
+      0x4 => dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; cmp eax, DWORD [rbx + rt] ; jne =>not_taken),
+      0x5 => dynasm!(self.ops ; .arch x64 ; mov eax, DWORD [rbx + rs] ; cmp eax, DWORD [rbx + rt] ; je =>not_taken),
+      0x6 => dynasm!(self.ops ; .arch x64 ; cmp DWORD [rbx + rs], 0 ; jg =>not_taken),
+      _ => dynasm!(self.ops ; .arch x64 ; cmp DWORD [rbx + rs], 0 ; jle =>not_taken)
+    }
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov eax, DWORD [rbx + Self::pc()]
+      ; add eax, offset
+      ; mov DWORD [rbx + Self::next_pc()], eax
+      ; mov BYTE [rbx + Self::branch()], 1
+      ; =>not_taken
+    );
+
+    self.load_delay();
+  }
+
+  // misaligned accesses take the interpreter's path, which raises the address error
+  fn load(&mut self, instr: &Instruction, handler: &fn(&mut CPU, Instruction), alignment: i32, function: *const u8) {
+    let misaligned = self.ops.new_dynamic_label();
+    let done = self.ops.new_dynamic_label();
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov r12d, DWORD [rbx + Self::reg(instr.rs())]
+      ; add r12d, instr.immediate_signed() as i32
+      ; test r12d, alignment
+      ; jnz =>misaligned
+      ; mov rdi, rbx
+      ; mov esi, r12d
+      ; mov edx, instr.rt() as i32
+    );
+
+    self.call(function);
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; jmp =>done
+      ; =>misaligned
+    );
+
+    self.interpret(instr, handler);
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; =>done
+    );
+
+    self.load_pending = true;
+  }
+
+  fn store(&mut self, instr: &Instruction, handler: &fn(&mut CPU, Instruction), alignment: i32, function: *const u8) {
+    let misaligned = self.ops.new_dynamic_label();
+    let done = self.ops.new_dynamic_label();
+
+    let load_pending = self.load_pending;
+
+    // the value is read before the pending load lands
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov r12d, DWORD [rbx + Self::reg(instr.rs())]
+      ; add r12d, instr.immediate_signed() as i32
+      ; test r12d, alignment
+      ; jnz =>misaligned
+      ; mov r13d, DWORD [rbx + Self::reg(instr.rt())]
+    );
+
+    self.load_delay();
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov rdi, rbx
+      ; mov esi, r12d
+      ; mov edx, r13d
+    );
+
+    self.call(function);
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; jmp =>done
+      ; =>misaligned
+    );
+
+    self.load_pending = load_pending;
+
+    self.interpret(instr, handler);
+
+    dynasm!(self.ops
+      ; .arch x64
+      ; =>done
+    );
+
+    self.load_pending = false;
+  }
+}
+
+impl CPU {
+  /// runs a block of RAM code translated to native code
+  pub(super) fn run_native_block(&mut self) {
+    let Some(address) = self.block_address() else {
+      self.step();
+
+      return;
+    };
+
+    let block = self.native_blocks.lookup(address, &self.bus, NativeBlock::compile);
+
+    block.run(self);
+  }
+}
+
+// called from the generated code, cpu is the CPU running the block
+
+extern "sysv64" fn enter_instruction(cpu: *mut CPU, pc: u32, instr: u32, first: u32, after_memory_access: u32) -> u32 {
+  let cpu = unsafe { &mut *cpu };
+
+  cpu.enter_instruction(pc, instr, first != 0, after_memory_access != 0) as u32
+}
+
+extern "sysv64" fn load_delay(cpu: *mut CPU) {
+  unsafe { &mut *cpu }.execute_load_delay();
+}
+
+extern "sysv64" fn interpret(cpu: *mut CPU, handler: *const fn(&mut CPU, Instruction), instr: u32) {
+  let (cpu, handler) = unsafe { (&mut *cpu, *handler) };
+
+  handler(cpu, Instruction::new(instr));
+}
+
+extern "sysv64" fn gte_command(cpu: *mut CPU, instr: u32) {
+  let cpu = unsafe { &mut *cpu };
+
+  cpu.gte.execute_command(Instruction::new(instr));
+
+  cpu.execute_load_delay();
+}
+
+extern "sysv64" fn load_8(cpu: *mut CPU, address: u32, rt: u32) {
+  let cpu = unsafe { &mut *cpu };
+
+  let value = cpu.load_8(address);
+
+  cpu.update_load(rt as usize, value as u32);
+}
+
+extern "sysv64" fn load_8_signed(cpu: *mut CPU, address: u32, rt: u32) {
+  let cpu = unsafe { &mut *cpu };
+
+  let value = cpu.load_8(address);
+
+  cpu.update_load(rt as usize, value as i8 as u32);
+}
+
+extern "sysv64" fn load_16(cpu: *mut CPU, address: u32, rt: u32) {
+  let cpu = unsafe { &mut *cpu };
+
+  let value = cpu.load_16(address);
+
+  cpu.update_load(rt as usize, value as u32);
+}
+
+extern "sysv64" fn load_16_signed(cpu: *mut CPU, address: u32, rt: u32) {
+  let cpu = unsafe { &mut *cpu };
+
+  let value = cpu.load_16(address);
+
+  cpu.update_load(rt as usize, value as i16 as u32);
+}
+
+extern "sysv64" fn load_32(cpu: *mut CPU, address: u32, rt: u32) {
+  let cpu = unsafe { &mut *cpu };
+
+  let value = cpu.load_32(address);
+
+  cpu.update_load(rt as usize, value);
+}
+
+extern "sysv64" fn store_8(cpu: *mut CPU, address: u32, value: u32) {
+  unsafe { &mut *cpu }.store_8(address, value as u8);
+}
+
+extern "sysv64" fn store_16(cpu: *mut CPU, address: u32, value: u32) {
+  unsafe { &mut *cpu }.store_16(address, value as u16);
+}
+
+extern "sysv64" fn store_32(cpu: *mut CPU, address: u32, value: u32) {
+  unsafe { &mut *cpu }.store_32(address, value);
+}
@@ -0,0 +1,208 @@
+use std::{error::Error, fmt};
+
+use crate::util;
+
+// per https://psx-spx.consoledev.net/cdromfileformats/#cdrom-file-psx-executables-exe
+
+const MAGIC: &[u8] = b"PS-X EXE";
+const HEADER_SIZE: usize = 0x800;
+
+const RAM_SIZE: u32 = 0x20_0000;
+
+// the kernel keeps the boot command line here, 128 bytes long
+pub const ARGS_ADDRESS: u32 = 0x180;
+const ARGS_SIZE: usize = 0x80;
+
+#[derive(Debug, PartialEq)]
+pub enum ExeError {
+  TooSmall(usize),
+  InvalidMagic,
+  Truncated { expected: usize, actual: usize },
+  OutOfRange { address: u32, size: u32 },
+  ArgumentsTooLong(usize)
+}
+
+impl fmt::Display for ExeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ExeError::TooSmall(size) => write!(f, "file is too small for a PS-X EXE header ({size} bytes)"),
+      ExeError::InvalidMagic => write!(f, "missing PS-X EXE magic"),
+      ExeError::Truncated { expected, actual } => write!(f, "file is truncated, expected {expected} bytes but got {actual}"),
+      ExeError::OutOfRange { address, size } => write!(f, "{size:#x} bytes at {address:#x} don't fit in RAM"),
+      ExeError::ArgumentsTooLong(size) => write!(f, "arguments take {size} bytes, only {ARGS_SIZE} are available")
+    }
+  }
+}
+
+impl Error for ExeError {}
+
+pub struct PsxExe {
+  pub pc: u32,
+  pub gp: u32,
+  // t_addr
+  pub dest: u32,
+  pub data: Vec<u8>,
+  // b_addr and b_size, cleared before the exe runs
+  pub bss_start: u32,
+  pub bss_size: u32,
+  // s_addr + s_size, or None to keep the current stack
+  pub stack: Option<u32>,
+  // passed to main as argc and argv
+  pub args: Vec<String>
+}
+
+impl PsxExe {
+  pub fn parse(bytes: &[u8]) -> Result<Self, ExeError> {
+    if bytes.len() < HEADER_SIZE {
+      return Err(ExeError::TooSmall(bytes.len()));
+    }
+
+    if !bytes.starts_with(MAGIC) {
+      return Err(ExeError::InvalidMagic);
+    }
+
+    let pc = util::read_word(bytes, 0x10);
+    let gp = util::read_word(bytes, 0x14);
+    let dest = util::read_word(bytes, 0x18);
+    let size = util::read_word(bytes, 0x1c);
+    let bss_start = util::read_word(bytes, 0x28);
+    let bss_size = util::read_word(bytes, 0x2c);
+    let stack_base = util::read_word(bytes, 0x30);
+    let stack_offset = util::read_word(bytes, 0x34);
+
+    // checked first, so a huge size can't overflow the expected length on 32 bit targets
+    Self::check_range(dest, size)?;
+    Self::check_range(bss_start, bss_size)?;
+
+    let expected = HEADER_SIZE + size as usize;
+
+    if bytes.len() < expected {
+      return Err(ExeError::Truncated { expected, actual: bytes.len() });
+    }
+
+    Ok(Self {
+      pc,
+      gp,
+      dest,
+      data: bytes[HEADER_SIZE..expected].to_vec(),
+      bss_start,
+      bss_size,
+      stack: if stack_base != 0 { Some(stack_base.wrapping_add(stack_offset)) } else { None },
+      args: Vec::new()
+    })
+  }
+
+  // ram is mirrored in kuseg, kseg0 and kseg1
+  fn check_range(address: u32, size: u32) -> Result<(), ExeError> {
+    let end = (address & 0x1fff_ffff).checked_add(size);
+
+    if size != 0 && end.is_none_or(|end| end > RAM_SIZE) {
+      return Err(ExeError::OutOfRange { address, size });
+    }
+
+    Ok(())
+  }
+
+  pub fn with_args(mut self, args: Vec<String>) -> Result<Self, ExeError> {
+    let size = Self::args_size(&args);
+
+    if size > ARGS_SIZE {
+      return Err(ExeError::ArgumentsTooLong(size));
+    }
+
+    self.args = args;
+
+    Ok(self)
+  }
+
+  // the argv pointers (null terminated) followed by the strings
+  fn args_size(args: &[String]) -> usize {
+    (args.len() + 1) * 4 + args.iter().map(|arg| arg.len() + 1).sum::<usize>()
+  }
+
+  /// the argv block as it's laid out in memory at ARGS_ADDRESS
+  pub fn args_block(&self) -> Vec<u8> {
+    let mut pointers = Vec::new();
+    let mut strings = Vec::new();
+
+    let strings_address = 0x8000_0000 | (ARGS_ADDRESS + (self.args.len() as u32 + 1) * 4);
+
+    for arg in &self.args {
+      pointers.extend((strings_address + strings.len() as u32).to_le_bytes());
+
+      strings.extend(arg.as_bytes());
+      strings.push(0);
+    }
+
+    pointers.extend(0u32.to_le_bytes());
+    pointers.extend(strings);
+
+    pointers
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn image(dest: u32, size: u32, bss_start: u32, bss_size: u32) -> Vec<u8> {
+    let mut bytes = vec![0; HEADER_SIZE + size as usize];
+
+    bytes[..8].copy_from_slice(MAGIC);
+    bytes[0x10..0x14].copy_from_slice(&0x8001_0000u32.to_le_bytes());
+    bytes[0x18..0x1c].copy_from_slice(&dest.to_le_bytes());
+    bytes[0x1c..0x20].copy_from_slice(&size.to_le_bytes());
+    bytes[0x28..0x2c].copy_from_slice(&bss_start.to_le_bytes());
+    bytes[0x2c..0x30].copy_from_slice(&bss_size.to_le_bytes());
+
+    bytes
+  }
+
+  #[test]
+  fn parse() {
+    let exe = PsxExe::parse(&image(0x8001_0000, 0x800, 0x8001_0800, 0x100)).unwrap();
+
+    assert_eq!(exe.pc, 0x8001_0000);
+    assert_eq!(exe.data.len(), 0x800);
+    assert_eq!(exe.stack, None);
+
+    assert_eq!(PsxExe::parse(&[0; 0x10]).err(), Some(ExeError::TooSmall(0x10)));
+    assert_eq!(PsxExe::parse(&[0; HEADER_SIZE]).err(), Some(ExeError::InvalidMagic));
+
+    let mut truncated = image(0x8001_0000, 0x800, 0, 0);
+    truncated.truncate(HEADER_SIZE + 0x10);
+
+    assert_eq!(PsxExe::parse(&truncated).err(), Some(ExeError::Truncated { expected: HEADER_SIZE + 0x800, actual: HEADER_SIZE + 0x10 }));
+  }
+
+  #[test]
+  fn out_of_range() {
+    assert!(PsxExe::parse(&image(0x801f_f800, 0x800, 0, 0)).is_ok());
+    assert!(PsxExe::parse(&image(0x801f_f800, 0x1000, 0, 0)).is_err());
+    assert!(PsxExe::parse(&image(0x1f80_0000, 0x10, 0, 0)).is_err());
+
+    // sizes that would overflow the expected file length or the end address
+    let mut huge = image(0x8001_0000, 0, 0, 0);
+    huge[0x1c..0x20].copy_from_slice(&0xffff_f800u32.to_le_bytes());
+
+    assert_eq!(PsxExe::parse(&huge).err(), Some(ExeError::OutOfRange { address: 0x8001_0000, size: 0xffff_f800 }));
+
+    let error = PsxExe::parse(&image(0x8001_0000, 0, 0x801f_ffff, 0xffff_fff0)).err();
+
+    assert_eq!(error, Some(ExeError::OutOfRange { address: 0x801f_ffff, size: 0xffff_fff0 }));
+  }
+
+  #[test]
+  fn args() {
+    let exe = PsxExe::parse(&image(0x8001_0000, 0, 0, 0)).unwrap().with_args(vec!["a".to_string(), "bc".to_string()]).unwrap();
+
+    let block = exe.args_block();
+
+    assert_eq!(block[..12], [0x8c, 0x01, 0, 0x80, 0x8e, 0x01, 0, 0x80, 0, 0, 0, 0]);
+    assert_eq!(block[12..], *b"a\0bc\0");
+
+    let exe = PsxExe::parse(&image(0x8001_0000, 0, 0, 0)).unwrap();
+
+    assert_eq!(exe.with_args(vec!["x".repeat(0x80)]).err(), Some(ExeError::ArgumentsTooLong(0x89)));
+  }
+}
@@ -1,4 +1,4 @@
-use super::{CPU, instruction::Instruction, Cause};
+use super::{CPU, instruction::Instruction, Cause, DCIC_WRITE_MASK};
 
 
 const RA_REGISTER: usize = 31;
@@ -90,6 +90,14 @@ impl CPU {
     handler_fn(self, instr);
   }
 
+  /// the handler execute would end up calling, with the secondary opcode already resolved
+  pub fn decode(instr: &Instruction) -> fn(&mut CPU, Instruction) {
+    match instr.op_code() {
+      0 => SECONDARY_HANDLERS[instr.op_code_secondary() as usize],
+      op_code => PRIMARY_HANDLERS[op_code as usize]
+    }
+  }
+
   fn bcondz(&mut self, instr: Instruction) {
     let result = (((self.r[instr.rs()] as i32) < 0) as u32) ^ instr.bcond();
 
@@ -281,7 +289,9 @@ impl CPU {
     if address & 0b11 == 0 {
       let value = self.load_32(address);
 
-      self.gte.write_data(instr.rt(), value);
+      if !self.take_cancelled_access() {
+        self.gte.write_data(instr.rt(), value);
+      }
     } else {
       self.cop0.bad_vaddr = address;
       self.exception(Cause::LoadAddressError);
@@ -411,6 +421,8 @@ impl CPU {
   fn mfc0(&mut self, instr: Instruction) {
     let delayed_register = instr.rt();
     let delayed_load = match instr.rd() {
+      3 => self.cop0.bpc,
+      5 => self.cop0.bda,
       6 => self.cop0.jumpdest,
       7 => self.cop0.dcic,
       8 => self.cop0.bad_vaddr,
@@ -432,14 +444,15 @@ impl CPU {
     let value = self.r[instr.rt()];
 
     let prev_iec = self.cop0.sr & 0b1;
+    // is_cache_disabled is false while the cache is isolated
+    let was_isolated = !self.cop0.is_cache_disabled();
 
     match cop0_reg {
-      3 | 5 | 6 => {
-        if value != 0 {
-          panic!("unhandled write to debug registers: {cop0_reg}");
-        }
-      }
-      7 => self.cop0.dcic = value,
+      3 => self.cop0.bpc = value,
+      5 => self.cop0.bda = value,
+      // jumpdest is read only
+      6 => (),
+      7 => self.cop0.dcic = value & DCIC_WRITE_MASK,
       9 => self.cop0.bdam = value,
       11 => self.cop0.bpcm = value,
       12 => self.cop0.sr = value,
@@ -449,6 +462,11 @@ impl CPU {
 
     self.execute_load_delay();
 
+    // isolating the cache is how the BIOS flushes it after loading new code
+    if !was_isolated && !self.cop0.is_cache_disabled() {
+      self.clear_blocks();
+    }
+
     let curr_iec = self.cop0.sr & 0b1;
 
     if prev_iec != curr_iec && self.cop0.interrupts_ready() {
@@ -889,7 +907,11 @@ impl CPU {
     self.cop0.return_from_exception();
   }
 
-  fn update_load(&mut self, reg: usize, val: u32) {
+  pub(super) fn update_load(&mut self, reg: usize, val: u32) {
+    if self.take_cancelled_access() {
+      return;
+    }
+
     if let Some((pending_reg, _)) = self.load {
       if reg != pending_reg {
         self.execute_load_delay();
@@ -0,0 +1,536 @@
+use std::{io::{self, ErrorKind, Read, Write}, net::{TcpListener, TcpStream}, thread};
+
+use super::{debugger::{StopReason, WatchKind, Watchpoint}, CPU};
+
+// a gdb remote serial protocol stub, for debugging homebrew with ie: gdb-multiarch
+// and `target remote localhost:3333`.
+// per https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+
+// registers are numbered like in gdb's mips target: r0-r31, status, lo, hi, badvaddr,
+// cause and pc, then the fpu registers (which the psx doesn't have) and epc
+const PC_REGISTER: usize = 37;
+const FPU_REGISTERS: std::ops::RangeInclusive<usize> = 38..=71;
+const NUM_REGISTERS: usize = 73;
+
+const PACKET_SIZE: usize = 0x4000;
+
+pub struct GdbServer {
+  listener: TcpListener,
+  stream: Option<TcpStream>,
+  input: Vec<u8>,
+  // the cpu only runs while gdb has continued it
+  halted: bool,
+  last_stop: String
+}
+
+impl GdbServer {
+  /// listens on localhost for gdb to attach, which it can do at any time
+  pub fn bind(port: u16) -> io::Result<Self> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+
+    listener.set_nonblocking(true)?;
+
+    Ok(Self {
+      listener,
+      stream: None,
+      input: Vec::new(),
+      halted: false,
+      last_stop: "S05".to_string()
+    })
+  }
+
+  pub fn is_halted(&self) -> bool {
+    self.halted
+  }
+
+  /// reports the cpu stopping (ie: returned by run_frame) to gdb, halting it
+  pub fn stopped(&mut self, stop: StopReason) {
+    if self.stream.is_some() {
+      self.stop(Self::stop_reply(stop));
+    }
+  }
+
+  /// accepts gdb's connection and handles the packets received since the last call.
+  /// the cpu shouldn't run while halted
+  pub fn poll(&mut self, cpu: &mut CPU) {
+    if self.stream.is_none() {
+      self.accept();
+    }
+
+    if !self.receive() {
+      self.detach(cpu);
+
+      return;
+    }
+
+    while let Some(packet) = self.next_packet() {
+      match packet {
+        Packet::Interrupt => if !self.halted {
+          self.stop("S02".to_string());
+        }
+        Packet::Command(command) => {
+          if let Some(reply) = self.handle(cpu, &command) {
+            self.send(&reply);
+          }
+        }
+      }
+
+      if self.stream.is_none() {
+        break;
+      }
+    }
+  }
+
+  fn accept(&mut self) {
+    match self.listener.accept() {
+      Ok((stream, address)) => {
+        if stream.set_nonblocking(true).is_err() {
+          return;
+        }
+
+        stream.set_nodelay(true).ok();
+
+        println!("gdb attached from {address}");
+
+        self.stream = Some(stream);
+        self.input.clear();
+
+        // gdb expects the target to be stopped when it attaches
+        self.halted = true;
+        self.last_stop = "S05".to_string();
+      }
+      Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+      Err(e) => println!("couldn't accept gdb connection: {e}")
+    }
+  }
+
+  // reads whatever is available, returns false once gdb has disconnected
+  fn receive(&mut self) -> bool {
+    let Some(stream) = &mut self.stream else {
+      return true;
+    };
+
+    let mut buf = [0; 4096];
+
+    loop {
+      match stream.read(&mut buf) {
+        Ok(0) => return false,
+        Ok(n) => self.input.extend_from_slice(&buf[..n]),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+        Err(e) if e.kind() == ErrorKind::Interrupted => (),
+        Err(_) => return false
+      }
+    }
+  }
+
+  fn next_packet(&mut self) -> Option<Packet> {
+    loop {
+      match *self.input.first()? {
+        // ctrl-c
+        0x03 => {
+          self.input.remove(0);
+
+          return Some(Packet::Interrupt);
+        }
+        b'$' => break,
+        // acks and anything else outside of a packet
+        _ => {
+          self.input.remove(0);
+        }
+      }
+    }
+
+    let end = self.input.iter().position(|b| *b == b'#')?;
+
+    if self.input.len() < end + 3 {
+      return None;
+    }
+
+    let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+
+    let data = &packet[1..end];
+    let checksum = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+    if checksum != Some(Self::checksum(data)) {
+      self.write(b"-");
+
+      return self.next_packet();
+    }
+
+    self.write(b"+");
+
+    Some(Packet::Command(String::from_utf8_lossy(data).into_owned()))
+  }
+
+  fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+  }
+
+  fn send(&mut self, data: &str) {
+    let packet = format!("${data}#{:02x}", Self::checksum(data.as_bytes()));
+
+    self.write(packet.as_bytes());
+  }
+
+  fn write(&mut self, mut bytes: &[u8]) {
+    let Some(stream) = &mut self.stream else {
+      return;
+    };
+
+    while !bytes.is_empty() {
+      match stream.write(bytes) {
+        Ok(n) => bytes = &bytes[n..],
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => thread::yield_now(),
+        Err(_) => return
+      }
+    }
+  }
+
+  fn stop(&mut self, reply: String) {
+    self.halted = true;
+
+    self.send(&reply);
+
+    self.last_stop = reply;
+  }
+
+  fn stop_reply(stop: StopReason) -> String {
+    match stop {
+      StopReason::Watchpoint { address, kind, .. } => {
+        let kind = match kind {
+          WatchKind::Write => "watch",
+          WatchKind::Read => "rwatch",
+          WatchKind::Access => "awatch"
+        };
+
+        format!("T05{kind}:{address:08x};")
+      }
+      _ => "S05".to_string()
+    }
+  }
+
+  // breakpoints are removed and the cpu resumes running
+  fn detach(&mut self, cpu: &mut CPU) {
+    if self.stream.take().is_some() {
+      println!("gdb detached");
+    }
+
+    cpu.debugger.clear();
+    cpu.bus.watchpoints.clear();
+
+    self.halted = false;
+  }
+
+  // returns the reply, or None when there's none yet (ie: continuing)
+  fn handle(&mut self, cpu: &mut CPU, command: &str) -> Option<String> {
+    // empty packets and ones starting with a multi-byte character aren't commands
+    let Some((first, args)) = command.split_at_checked(1) else {
+      return Some(String::new());
+    };
+
+    let reply = match first {
+      "?" => self.last_stop.clone(),
+      "q" => Self::query(args),
+      "H" => "OK".to_string(),
+      "g" => (0..NUM_REGISTERS).map(|i| Self::hex_word(Self::read_register(cpu, i).unwrap_or(0))).collect(),
+      "G" => {
+        for i in 0..NUM_REGISTERS {
+          if let Some(value) = args.get(i * 8..i * 8 + 8).and_then(Self::parse_word) {
+            Self::write_register(cpu, i, value);
+          }
+        }
+
+        "OK".to_string()
+      }
+      "p" => usize::from_str_radix(args, 16).ok()
+        .and_then(|i| Self::read_register(cpu, i))
+        .map(Self::hex_word)
+        .unwrap_or_else(|| "E01".to_string()),
+      "P" => {
+        let written = args.split_once('=').and_then(|(i, value)| {
+          Self::write_register(cpu, usize::from_str_radix(i, 16).ok()?, Self::parse_word(value)?).then_some(())
+        });
+
+        if written.is_some() { "OK" } else { "E01" }.to_string()
+      }
+      "m" => Self::read_memory(cpu, args).unwrap_or_else(|| "E01".to_string()),
+      "M" => if Self::write_memory(cpu, args).is_some() { "OK" } else { "E01" }.to_string(),
+      "c" => {
+        Self::jump(cpu, args);
+
+        self.halted = false;
+
+        return None;
+      }
+      "s" => {
+        Self::jump(cpu, args);
+
+        let reply = Self::stop_reply(cpu.step_instruction());
+
+        self.last_stop = reply.clone();
+
+        reply
+      }
+      "Z" | "z" => if self.set_breakpoint(cpu, first == "Z", args).is_some() { "OK" } else { "E01" }.to_string(),
+      "D" => {
+        self.send("OK");
+        self.detach(cpu);
+
+        return None;
+      }
+      "k" => {
+        self.detach(cpu);
+
+        return None;
+      }
+      // anything unsupported gets an empty reply, ie: vCont makes gdb fall back to c and s
+      _ => String::new()
+    };
+
+    Some(reply)
+  }
+
+  fn query(query: &str) -> String {
+    if query.starts_with("Supported") {
+      format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+")
+    } else if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+      Self::read_target_xml(range).unwrap_or_else(|| "E01".to_string())
+    } else if query == "Attached" {
+      // detaching leaves the emulator running
+      "1".to_string()
+    } else {
+      String::new()
+    }
+  }
+
+  // offset,length of the target description
+  fn read_target_xml(range: &str) -> Option<String> {
+    let (offset, length) = range.split_once(',')?;
+
+    let (offset, length) = (usize::from_str_radix(offset, 16).ok()?, usize::from_str_radix(length, 16).ok()?);
+
+    let xml = Self::target_xml();
+
+    let start = offset.min(xml.len());
+    let end = (offset + length).min(xml.len());
+
+    // m when there's more to read, l for the last part
+    let prefix = if end < xml.len() { 'm' } else { 'l' };
+
+    Some(format!("{prefix}{}", &xml[start..end]))
+  }
+
+  fn target_xml() -> String {
+    let mut cpu = String::new();
+    let mut fpu = String::new();
+
+    for i in 0..32 {
+      cpu.push_str(&format!("<reg name=\"r{i}\" bitsize=\"32\" regnum=\"{i}\"/>"));
+      fpu.push_str(&format!("<reg name=\"f{i}\" bitsize=\"32\" type=\"ieee_single\" regnum=\"{}\"/>", i + 38));
+    }
+
+    format!(concat!(
+      "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\">",
+      "<architecture>mips:3000</architecture>",
+      "<feature name=\"org.gnu.gdb.mips.cpu\">{}",
+      "<reg name=\"lo\" bitsize=\"32\" regnum=\"33\"/><reg name=\"hi\" bitsize=\"32\" regnum=\"34\"/>",
+      "<reg name=\"pc\" bitsize=\"32\" regnum=\"37\"/></feature>",
+      "<feature name=\"org.gnu.gdb.mips.cp0\">",
+      "<reg name=\"status\" bitsize=\"32\" regnum=\"32\"/><reg name=\"badvaddr\" bitsize=\"32\" regnum=\"35\"/>",
+      "<reg name=\"cause\" bitsize=\"32\" regnum=\"36\"/><reg name=\"epc\" bitsize=\"32\" regnum=\"72\"/></feature>",
+      "<feature name=\"org.gnu.gdb.mips.fpu\">{}",
+      "<reg name=\"fcsr\" bitsize=\"32\" group=\"float\" regnum=\"70\"/><reg name=\"fir\" bitsize=\"32\" group=\"float\" regnum=\"71\"/></feature>",
+      "</target>"
+    ), cpu, fpu)
+  }
+
+  fn read_register(cpu: &CPU, index: usize) -> Option<u32> {
+    let value = match index {
+      0..=31 => cpu.r[index],
+      32 => cpu.cop0.sr,
+      33 => cpu.low,
+      34 => cpu.hi,
+      35 => cpu.cop0.bad_vaddr,
+      36 => cpu.cop0.cause,
+      PC_REGISTER => cpu.pc,
+      72 => cpu.cop0.epc,
+      _ if FPU_REGISTERS.contains(&index) => 0,
+      _ => return None
+    };
+
+    Some(value)
+  }
+
+  fn write_register(cpu: &mut CPU, index: usize, value: u32) -> bool {
+    match index {
+      0 => (),
+      1..=31 => cpu.r[index] = value,
+      32 => cpu.cop0.sr = value,
+      33 => cpu.low = value,
+      34 => cpu.hi = value,
+      35 => cpu.cop0.bad_vaddr = value,
+      36 => cpu.cop0.cause = value,
+      PC_REGISTER => {
+        cpu.pc = value;
+        cpu.next_pc = value.wrapping_add(4);
+      }
+      72 => cpu.cop0.epc = value,
+      _ if FPU_REGISTERS.contains(&index) => (),
+      _ => return false
+    }
+
+    true
+  }
+
+  // address,length. reads stop at the first byte that can't be read without side effects
+  fn read_memory(cpu: &CPU, args: &str) -> Option<String> {
+    let (address, length) = args.split_once(',')?;
+
+    let (address, length) = (u32::from_str_radix(address, 16).ok()?, usize::from_str_radix(length, 16).ok()?);
+
+    let bytes: String = (0..length.min(PACKET_SIZE / 2) as u32)
+      .map_while(|i| cpu.bus.peek_8(address.wrapping_add(i)))
+      .map(|byte| format!("{byte:02x}"))
+      .collect();
+
+    if bytes.is_empty() && length > 0 {
+      return None;
+    }
+
+    Some(bytes)
+  }
+
+  // address,length:data
+  fn write_memory(cpu: &mut CPU, args: &str) -> Option<()> {
+    let (range, data) = args.split_once(':')?;
+    let (address, _) = range.split_once(',')?;
+
+    let address = u32::from_str_radix(address, 16).ok()?;
+
+    for (i, byte) in data.as_bytes().chunks(2).enumerate() {
+      let byte = u8::from_str_radix(std::str::from_utf8(byte).ok()?, 16).ok()?;
+
+      if !cpu.bus.poke_8(address.wrapping_add(i as u32), byte) {
+        return None;
+      }
+    }
+
+    Some(())
+  }
+
+  // type,address,kind. 0 and 1 are software and hardware breakpoints, both work the same
+  // here since the emulator checks the pc itself. 2, 3 and 4 are write, read and access
+  // watchpoints
+  fn set_breakpoint(&mut self, cpu: &mut CPU, insert: bool, args: &str) -> Option<()> {
+    let mut parts = args.split(',');
+
+    let kind = parts.next()?;
+    let address = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let length = u32::from_str_radix(parts.next()?.split(';').next()?, 16).ok()?;
+
+    let watch_kind = match kind {
+      "0" | "1" => {
+        if insert {
+          cpu.debugger.add_breakpoint(address, None);
+        } else {
+          cpu.debugger.remove_breakpoint(address);
+        }
+
+        return Some(());
+      }
+      "2" => WatchKind::Write,
+      "3" => WatchKind::Read,
+      "4" => WatchKind::Access,
+      _ => return None
+    };
+
+    let watchpoint = Watchpoint { address, length, kind: watch_kind };
+
+    if insert {
+      cpu.bus.watchpoints.add(watchpoint);
+    } else {
+      cpu.bus.watchpoints.remove(watchpoint);
+    }
+
+    Some(())
+  }
+
+  // c and s can resume at a different address
+  fn jump(cpu: &mut CPU, address: &str) {
+    if let Ok(address) = u32::from_str_radix(address, 16) {
+      Self::write_register(cpu, PC_REGISTER, address);
+    }
+  }
+
+  // registers are sent in target byte order, little endian
+  fn hex_word(value: u32) -> String {
+    value.to_le_bytes().iter().map(|byte| format!("{byte:02x}")).collect()
+  }
+
+  fn parse_word(hex: &str) -> Option<u32> {
+    let value = u32::from_str_radix(hex, 16).ok()?;
+
+    Some(value.swap_bytes())
+  }
+}
+
+enum Packet {
+  Interrupt,
+  Command(String)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn server(input: &[u8]) -> GdbServer {
+    let mut server = GdbServer::bind(0).unwrap();
+
+    server.input.extend_from_slice(input);
+
+    server
+  }
+
+  fn command(packet: Option<Packet>) -> Option<String> {
+    match packet? {
+      Packet::Command(command) => Some(command),
+      Packet::Interrupt => None
+    }
+  }
+
+  #[test]
+  fn valid_checksum() {
+    let mut server = server(b"+$g#67$m0,4#fd");
+
+    assert_eq!(command(server.next_packet()).as_deref(), Some("g"));
+    assert_eq!(command(server.next_packet()).as_deref(), Some("m0,4"));
+    assert!(server.next_packet().is_none());
+  }
+
+  #[test]
+  fn bad_checksum_is_skipped() {
+    let mut server = server(b"$g#00$g#zz$?#3f");
+
+    assert_eq!(command(server.next_packet()).as_deref(), Some("?"));
+    assert!(server.input.is_empty());
+  }
+
+  #[test]
+  fn incomplete_packet_waits() {
+    let mut server = server(b"$m0,4#f");
+
+    assert!(server.next_packet().is_none());
+
+    server.input.push(b'd');
+
+    assert_eq!(command(server.next_packet()).as_deref(), Some("m0,4"));
+  }
+
+  #[test]
+  fn interrupt() {
+    let mut server = server(b"\x03$#00");
+
+    assert!(matches!(server.next_packet(), Some(Packet::Interrupt)));
+    assert_eq!(command(server.next_packet()).as_deref(), Some(""));
+  }
+}
@@ -0,0 +1,96 @@
+use super::CPU;
+
+/// the cpu state compared when running two cpus side by side
+#[derive(PartialEq, Debug)]
+pub struct CpuState {
+  pub pc: u32,
+  pub next_pc: u32,
+  pub r: [u32; 32],
+  pub hi: u32,
+  pub low: u32,
+  pub sr: u32,
+  pub cause: u32,
+  pub epc: u32,
+  pub cycles: i32
+}
+
+impl CpuState {
+  fn differences(&self, other: &CpuState) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    let mut compare = |name: &str, a: u32, b: u32| {
+      if a != b {
+        differences.push(format!("{name}: {a:08x} != {b:08x}"));
+      }
+    };
+
+    compare("pc", self.pc, other.pc);
+    compare("next_pc", self.next_pc, other.next_pc);
+
+    for i in 0..32 {
+      compare(&format!("r{i}"), self.r[i], other.r[i]);
+    }
+
+    compare("hi", self.hi, other.hi);
+    compare("lo", self.low, other.low);
+    compare("sr", self.sr, other.sr);
+    compare("cause", self.cause, other.cause);
+    compare("epc", self.epc, other.epc);
+    compare("cycles", self.cycles as u32, other.cycles as u32);
+
+    differences
+  }
+}
+
+impl CPU {
+  pub fn state(&self) -> CpuState {
+    CpuState {
+      pc: self.pc,
+      next_pc: self.next_pc,
+      r: self.r,
+      hi: self.hi,
+      low: self.low,
+      sr: self.cop0.sr,
+      cause: self.cop0.cause,
+      epc: self.cop0.epc,
+      cycles: self.bus.cycles
+    }
+  }
+}
+
+/// runs a frame on two cpus that started from the same state, ie: with different
+/// interpreters, comparing them at every device sync and RAM at the end of the frame.
+/// returns the first difference found
+pub fn run_frame_lockstep(reference: &mut CPU, other: &mut CPU) -> Result<(), String> {
+  loop {
+    let pc = reference.pc;
+
+    reference.run_until_sync();
+    other.run_until_sync();
+
+    let differences = reference.state().differences(&other.state());
+
+    if !differences.is_empty() {
+      return Err(format!("cpus diverged after running from {pc:08x}: {}", differences.join(", ")));
+    }
+
+    let frame_complete = reference.bus.gpu.frame_complete;
+
+    if frame_complete != other.bus.gpu.frame_complete {
+      return Err(format!("only one cpu completed a frame after running from {pc:08x}"));
+    }
+
+    if frame_complete {
+      break;
+    }
+  }
+
+  reference.bus.gpu.frame_complete = false;
+  other.bus.gpu.frame_complete = false;
+
+  if let Some(offset) = reference.bus.ram.iter().zip(other.bus.ram.iter()).position(|(a, b)| a != b) {
+    return Err(format!("RAM differs at {offset:06x}: {:02x} != {:02x}", reference.bus.ram[offset], other.bus.ram[offset]));
+  }
+
+  Ok(())
+}
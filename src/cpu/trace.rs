@@ -0,0 +1,445 @@
+use std::{collections::VecDeque, fs::File, io::{self, BufReader, BufWriter, ErrorKind, Read, Write}, path::Path};
+
+use super::{disassembler::{self, REGISTER_NAMES}, instruction::Instruction};
+
+// records every instruction the cpu runs into a ring buffer, optionally streaming them to a
+// file as they're recorded. an instruction interrupted before it runs isn't in the trace,
+// the interrupt handler is
+
+const MAGIC: &[u8; 4] = b"RSXT";
+const VERSION: u8 = 1;
+
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MemoryAccess {
+  pub address: u32,
+  pub value: u32,
+  pub size: u8,
+  pub write: bool
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct TraceEntry {
+  pub pc: u32,
+  pub opcode: u32,
+  // registers written and their values. an instruction writes at most one itself, plus
+  // one when a load's delay slot ends
+  pub writes: [Option<(u8, u32)>; 2],
+  pub access: Option<MemoryAccess>
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TraceFormat {
+  // a few bytes per instruction, for diffing
+  Binary,
+  // one disassembled instruction per line
+  Text
+}
+
+impl TraceFormat {
+  /// text for .txt and .log files, binary otherwise
+  pub fn from_path(path: &Path) -> Self {
+    match path.extension().and_then(|extension| extension.to_str()) {
+      Some("txt" | "log") => TraceFormat::Text,
+      _ => TraceFormat::Binary
+    }
+  }
+}
+
+pub struct Trace {
+  entries: VecDeque<TraceEntry>,
+  capacity: usize,
+  current: TraceEntry,
+  // set between begin and end, so a dump after a panic includes the instruction that panicked
+  in_progress: bool,
+  output: Option<(BufWriter<File>, TraceFormat)>
+}
+
+impl Trace {
+  /// keeps the last capacity instructions
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      entries: VecDeque::with_capacity(capacity),
+      capacity,
+      current: TraceEntry::default(),
+      in_progress: false,
+      output: None
+    }
+  }
+
+  /// also writes every instruction recorded from now on to a file
+  pub fn stream_to(&mut self, path: &Path, format: TraceFormat) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    if format == TraceFormat::Binary {
+      writer.write_all(MAGIC)?;
+      writer.write_all(&[VERSION])?;
+    }
+
+    self.output = Some((writer, format));
+
+    Ok(())
+  }
+
+  /// the recorded instructions, oldest first
+  pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+    self.entries.iter()
+  }
+
+  /// writes the instructions in the ring buffer to a file, ie: after a crash
+  pub fn dump(&self, path: &Path) -> io::Result<()> {
+    let format = TraceFormat::from_path(path);
+
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    if format == TraceFormat::Binary {
+      writer.write_all(MAGIC)?;
+      writer.write_all(&[VERSION])?;
+    }
+
+    let current = self.in_progress.then_some(&self.current);
+
+    for entry in self.entries.iter().chain(current) {
+      entry.write(&mut writer, format)?;
+    }
+
+    writer.flush()
+  }
+
+  pub(super) fn begin(&mut self, pc: u32, opcode: u32) {
+    self.current = TraceEntry {
+      pc,
+      opcode,
+      ..Default::default()
+    };
+
+    self.in_progress = true;
+  }
+
+  pub(super) fn register_write(&mut self, register: usize, value: u32) {
+    let writes = &mut self.current.writes;
+
+    // a register written twice only keeps its final value
+    match writes.iter_mut().find(|write| write.is_none_or(|(r, _)| r as usize == register)) {
+      Some(write) => *write = Some((register as u8, value)),
+      None => writes[1] = Some((register as u8, value))
+    }
+  }
+
+  pub(super) fn memory_access(&mut self, address: u32, value: u32, size: u8, write: bool) {
+    self.current.access = Some(MemoryAccess { address, value, size, write });
+  }
+
+  pub(super) fn end(&mut self) {
+    self.in_progress = false;
+
+    if self.entries.len() == self.capacity {
+      self.entries.pop_front();
+    }
+
+    if self.capacity > 0 {
+      self.entries.push_back(self.current);
+    }
+
+    if let Some((writer, format)) = &mut self.output {
+      if let Err(e) = self.current.write(writer, *format) {
+        println!("stopped streaming the trace: {e}");
+
+        self.output = None;
+      }
+    }
+  }
+}
+
+impl Drop for Trace {
+  fn drop(&mut self) {
+    if let Some((writer, _)) = &mut self.output {
+      let _ = writer.flush();
+    }
+  }
+}
+
+impl TraceEntry {
+  pub fn write(&self, writer: &mut impl Write, format: TraceFormat) -> io::Result<()> {
+    match format {
+      TraceFormat::Binary => self.write_binary(writer),
+      TraceFormat::Text => writeln!(writer, "{self}")
+    }
+  }
+
+  // pc, opcode and a flags byte with the number of register writes (bits 0-1), whether
+  // there's a memory access (bit 2), if it's a write (bit 3) and its size (log2, bits 4-5)
+  fn write_binary(&self, writer: &mut impl Write) -> io::Result<()> {
+    let writes = self.writes.iter().flatten().count() as u8;
+
+    let flags = writes | self.access.map_or(0, |access| {
+      0b100 | (access.write as u8) << 3 | (access.size.trailing_zeros() as u8) << 4
+    });
+
+    writer.write_all(&self.pc.to_le_bytes())?;
+    writer.write_all(&self.opcode.to_le_bytes())?;
+    writer.write_all(&[flags])?;
+
+    for (register, value) in self.writes.iter().flatten() {
+      writer.write_all(&[*register])?;
+      writer.write_all(&value.to_le_bytes())?;
+    }
+
+    if let Some(access) = self.access {
+      writer.write_all(&access.address.to_le_bytes())?;
+      writer.write_all(&access.value.to_le_bytes())?;
+    }
+
+    Ok(())
+  }
+
+  // returns None at the end of the trace
+  fn read_binary(reader: &mut impl Read) -> io::Result<Option<Self>> {
+    let mut header = [0; 9];
+
+    match reader.read_exact(&mut header) {
+      Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+      result => result?
+    }
+
+    let word = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+
+    let flags = header[8];
+
+    let mut entry = TraceEntry {
+      pc: word(&header[0..4]),
+      opcode: word(&header[4..8]),
+      ..Default::default()
+    };
+
+    for i in 0..(flags & 0b11) as usize {
+      let mut write = [0; 5];
+      reader.read_exact(&mut write)?;
+
+      entry.writes[i] = Some((write[0], word(&write[1..5])));
+    }
+
+    if flags & 0b100 != 0 {
+      let mut access = [0; 8];
+      reader.read_exact(&mut access)?;
+
+      entry.access = Some(MemoryAccess {
+        address: word(&access[0..4]),
+        value: word(&access[4..8]),
+        size: 1 << ((flags >> 4) & 0b11),
+        write: flags & 0b1000 != 0
+      });
+    }
+
+    Ok(Some(entry))
+  }
+}
+
+impl std::fmt::Display for TraceEntry {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let disassembly = disassembler::disassemble(self.pc, Instruction::new(self.opcode)).to_string();
+
+    write!(f, "{:08x}: {:08x}  {disassembly:<32}", self.pc, self.opcode)?;
+
+    for (register, value) in self.writes.iter().flatten() {
+      write!(f, " {}={value:08x}", REGISTER_NAMES[*register as usize])?;
+    }
+
+    if let Some(access) = self.access {
+      let direction = if access.write { "<-" } else { "->" };
+
+      write!(f, " [{:08x}]{direction}{:0width$x}", access.address, access.value, width = access.size as usize * 2)?;
+    }
+
+    Ok(())
+  }
+}
+
+/// reads a binary trace written by stream_to or dump
+pub struct TraceReader<R: Read> {
+  reader: R
+}
+
+impl TraceReader<BufReader<File>> {
+  pub fn open(path: &Path) -> Result<Self, String> {
+    let file = File::open(path).map_err(|e| format!("couldn't open {}: {e}", path.display()))?;
+
+    Self::new(BufReader::new(file))
+  }
+}
+
+impl<R: Read> TraceReader<R> {
+  pub fn new(mut reader: R) -> Result<Self, String> {
+    let mut header = [0; 5];
+
+    reader.read_exact(&mut header).map_err(|_| "not a binary trace".to_string())?;
+
+    if &header[0..4] != MAGIC {
+      return Err("not a binary trace".to_string());
+    }
+
+    if header[4] != VERSION {
+      return Err(format!("unsupported trace version {}", header[4]));
+    }
+
+    Ok(Self { reader })
+  }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+  type Item = Result<TraceEntry, String>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    TraceEntry::read_binary(&mut self.reader).map_err(|e| format!("corrupt trace: {e}")).transpose()
+  }
+}
+
+/// where two traces first differ. an entry is None when its trace ended first
+#[derive(Debug)]
+pub struct Divergence {
+  pub index: usize,
+  pub previous: Option<TraceEntry>,
+  pub left: Option<TraceEntry>,
+  pub right: Option<TraceEntry>
+}
+
+/// compares two binary traces, returning the first instruction they differ at
+pub fn diff(mut left: TraceReader<impl Read>, mut right: TraceReader<impl Read>) -> Result<Option<Divergence>, String> {
+  let mut previous = None;
+  let mut index = 0;
+
+  loop {
+    let (a, b) = (left.next().transpose()?, right.next().transpose()?);
+
+    if a != b {
+      return Ok(Some(Divergence { index, previous, left: a, right: b }));
+    }
+
+    if a.is_none() {
+      return Ok(None);
+    }
+
+    previous = a;
+    index += 1;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entries() -> Vec<TraceEntry> {
+    vec![
+      // lui v0, 0x8001
+      TraceEntry { pc: 0xbfc0_0000, opcode: 0x3c02_8001, writes: [Some((2, 0x8001_0000)), None], access: None },
+      // lw a0, 4(v0) ending the delay slot of an earlier load
+      TraceEntry {
+        pc: 0xbfc0_0004,
+        opcode: 0x8c44_0004,
+        writes: [Some((3, 0x1234)), Some((4, 0xdead_beef))],
+        access: Some(MemoryAccess { address: 0x8001_0004, value: 0xdead_beef, size: 4, write: false })
+      },
+      // sb a0, 0(v0)
+      TraceEntry {
+        pc: 0xbfc0_0008,
+        opcode: 0xa044_0000,
+        writes: [None, None],
+        access: Some(MemoryAccess { address: 0x8001_0000, value: 0xef, size: 1, write: true })
+      },
+      // sh a0, 2(v0)
+      TraceEntry {
+        pc: 0xbfc0_000c,
+        opcode: 0xa444_0002,
+        writes: [None, None],
+        access: Some(MemoryAccess { address: 0x8001_0002, value: 0xbeef, size: 2, write: true })
+      }
+    ]
+  }
+
+  fn binary(entries: &[TraceEntry]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+
+    for entry in entries {
+      entry.write(&mut bytes, TraceFormat::Binary).unwrap();
+    }
+
+    bytes
+  }
+
+  #[test]
+  fn binary_round_trip() {
+    let entries = entries();
+    let bytes = binary(&entries);
+
+    // the file header, then 9 bytes per entry plus 5 per register write and 8 for a memory access
+    assert_eq!(bytes.len(), 5 + 14 + 27 + 17 + 17);
+
+    let read: Vec<TraceEntry> = TraceReader::new(bytes.as_slice()).unwrap().collect::<Result<_, _>>().unwrap();
+
+    assert_eq!(read, entries);
+  }
+
+  #[test]
+  fn truncated_entry() {
+    let mut bytes = binary(&entries()[..2]);
+    bytes.pop();
+
+    let mut reader = TraceReader::new(bytes.as_slice()).unwrap();
+
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_err());
+  }
+
+  #[test]
+  fn invalid_header() {
+    assert!(TraceReader::new(&b"RSX"[..]).is_err());
+    assert!(TraceReader::new(&b"RSXX\x01"[..]).is_err());
+    assert!(TraceReader::new(&b"RSXT\x02"[..]).is_err());
+  }
+
+  #[test]
+  fn register_writes() {
+    let mut trace = Trace::new(2);
+
+    for pc in 0..3 {
+      trace.begin(pc * 4, 0);
+      trace.register_write(2, 1);
+      trace.register_write(3, 2);
+      // written twice, only the last value is kept
+      trace.register_write(2, 3);
+      trace.end();
+    }
+
+    let entries: Vec<&TraceEntry> = trace.entries().collect();
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].pc, 4);
+    assert_eq!(entries[1].writes, [Some((2, 3)), Some((3, 2))]);
+  }
+
+  #[test]
+  fn diff_traces() {
+    let left = entries();
+    let mut right = entries();
+    right[2].access = Some(MemoryAccess { address: 0x8001_0000, value: 0xee, size: 1, write: true });
+
+    let reader = |entries: &[TraceEntry]| TraceReader::new(std::io::Cursor::new(binary(entries))).unwrap();
+
+    assert!(diff(reader(&left), reader(&left)).unwrap().is_none());
+
+    let divergence = diff(reader(&left), reader(&right)).unwrap().unwrap();
+
+    assert_eq!(divergence.index, 2);
+    assert_eq!(divergence.previous, Some(left[1]));
+    assert_eq!(divergence.left, Some(left[2]));
+    assert_eq!(divergence.right, Some(right[2]));
+
+    // one trace ending early
+    let divergence = diff(reader(&left), reader(&left[..3])).unwrap().unwrap();
+
+    assert_eq!(divergence.index, 3);
+    assert_eq!(divergence.right, None);
+  }
+}
@@ -0,0 +1,215 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{cdrom::{disc::DiscImage, iso9660::IsoFilesystem, region::Region, DriveTiming}, cpu::CPU};
+
+// the built in database. user databases use the same format and are applied on
+// top of it, so they can add games or change settings of existing ones
+const BUILTIN_DB: &str = include_str!("game_db.txt");
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ControllerType {
+  Digital,
+  Analog
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MemoryCardMode {
+  // one card shared by every game
+  Shared,
+  // a separate card file per game serial
+  PerGame,
+  // no card inserted, for games that misbehave when they find a card
+  Disabled
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct GameSettings {
+  // cpu speed in percent
+  pub overclock: Option<u32>,
+  pub fast_cd: Option<bool>,
+  pub controller: Option<ControllerType>,
+  pub memory_card: Option<MemoryCardMode>,
+  // known problems, shown to the user when the game is loaded
+  pub issues: Vec<String>
+}
+
+impl GameSettings {
+  /// per game memory cards are stored in card_dir as <serial>.mcd. without one, the
+  /// frontend manages cards itself
+  pub fn apply(&self, cpu: &mut CPU, serial: &str, card_dir: Option<&Path>) {
+    if let Some(overclock) = self.overclock {
+      cpu.set_overclock(overclock);
+    }
+
+    if let Some(fast_cd) = self.fast_cd {
+      cpu.bus.cdrom.set_timing(if fast_cd { DriveTiming::Fast } else { DriveTiming::Accurate });
+    }
+
+    if let Some(controller) = self.controller {
+      cpu.bus.controllers.joypad.digital_mode = controller == ControllerType::Digital;
+    }
+
+    let memory_card = &mut cpu.bus.controllers.memory_card;
+
+    match (self.memory_card, card_dir) {
+      (Some(MemoryCardMode::PerGame), Some(card_dir)) => memory_card.use_file(&card_dir.join(format!("{serial}.mcd"))),
+      (Some(MemoryCardMode::Disabled), _) => memory_card.inserted = false,
+      _ => ()
+    }
+  }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct GameEntry {
+  pub title: String,
+  pub region: Option<Region>,
+  pub discs: u8,
+  pub settings: GameSettings
+}
+
+pub struct GameDb {
+  entries: HashMap<String, GameEntry>
+}
+
+impl GameDb {
+  pub fn builtin() -> Self {
+    let mut db = Self {
+      entries: HashMap::new()
+    };
+
+    db.extend(BUILTIN_DB).unwrap();
+
+    db
+  }
+
+  /// adds the entries of a database file, ie:
+  ///
+  /// [SCUS-94423]
+  /// title = Ape Escape
+  /// controller = analog
+  ///
+  /// keys left out of an entry keep their current value
+  pub fn extend(&mut self, contents: &str) -> Result<(), String> {
+    let mut serial = None;
+
+    for (i, line) in contents.lines().enumerate() {
+      let line = line.trim();
+
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let error = |message: &str| format!("game database line {}: {message}", i + 1);
+
+      if let Some(section) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+        let section = normalize_serial(section).ok_or_else(|| error("invalid serial"))?;
+
+        self.entries.entry(section.clone()).or_insert_with(|| GameEntry {
+          discs: 1,
+          ..Default::default()
+        });
+
+        serial = Some(section);
+
+        continue;
+      }
+
+      let entry = serial.as_ref().and_then(|serial| self.entries.get_mut(serial)).ok_or_else(|| error("setting outside of a game entry"))?;
+
+      let (key, value) = line.split_once('=').ok_or_else(|| error("expected key = value"))?;
+
+      let value = value.trim();
+      let settings = &mut entry.settings;
+
+      match key.trim() {
+        "title" => entry.title = value.to_string(),
+        "region" => entry.region = Some(Region::parse(value).ok_or_else(|| error("invalid region"))?),
+        "discs" => entry.discs = value.parse().map_err(|_| error("invalid disc count"))?,
+        "overclock" => settings.overclock = Some(value.parse().map_err(|_| error("invalid overclock"))?),
+        "fast_cd" => settings.fast_cd = Some(value.parse().map_err(|_| error("fast_cd must be true or false"))?),
+        "controller" => settings.controller = Some(match value {
+          "digital" => ControllerType::Digital,
+          "analog" => ControllerType::Analog,
+          _ => return Err(error("controller must be digital or analog"))
+        }),
+        "memory_card" => settings.memory_card = Some(match value {
+          "shared" => MemoryCardMode::Shared,
+          "per-game" => MemoryCardMode::PerGame,
+          "disabled" => MemoryCardMode::Disabled,
+          _ => return Err(error("memory_card must be shared, per-game or disabled"))
+        }),
+        "issues" => settings.issues = value.split(',').map(|issue| issue.trim().to_string()).filter(|issue| !issue.is_empty()).collect(),
+        _ => return Err(error(&format!("unknown key {}", key.trim())))
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn lookup(&self, serial: &str) -> Option<&GameEntry> {
+    self.entries.get(serial)
+  }
+}
+
+/// converts a serial like "slus_005.94" or "SLUS00594" to the "SLUS-00594" form
+fn normalize_serial(serial: &str) -> Option<String> {
+  let serial: String = serial.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_uppercase();
+
+  let (prefix, number) = serial.split_at_checked(4)?;
+
+  if !prefix.chars().all(|c| c.is_ascii_alphabetic()) || number.len() != 5 || !number.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+
+  Some(format!("{prefix}-{number}"))
+}
+
+/// finds the game serial from the boot executable's name in SYSTEM.CNF, ie:
+/// "cdrom:\SLUS_005.94;1" is SLUS-00594
+pub fn detect_serial(disc: &mut dyn DiscImage) -> Option<String> {
+  let cnf = IsoFilesystem::new(disc).ok()?.system_cnf().ok()?;
+
+  let boot_path = cnf.boot_path();
+
+  let name = boot_path.rsplit(['\\', '/']).next()?;
+
+  normalize_serial(name)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn normalize_serial_formats() {
+    assert_eq!(normalize_serial("SLUS_005.94").as_deref(), Some("SLUS-00594"));
+    assert_eq!(normalize_serial("scus_944.23").as_deref(), Some("SCUS-94423"));
+    assert_eq!(normalize_serial("SLES-01234").as_deref(), Some("SLES-01234"));
+    assert_eq!(normalize_serial("SLPS00001").as_deref(), Some("SLPS-00001"));
+  }
+
+  #[test]
+  fn normalize_serial_rejects_other_names() {
+    assert_eq!(normalize_serial("PSX.EXE"), None);
+    assert_eq!(normalize_serial("SLUS_005.945"), None);
+    assert_eq!(normalize_serial("SL1S_005.94"), None);
+    assert_eq!(normalize_serial(""), None);
+  }
+
+  #[test]
+  fn extend_entries() {
+    let mut db = GameDb { entries: HashMap::new() };
+
+    db.extend("# comment\n[slus_005.94]\ntitle = Test\nregion = ntsc-u\nmemory_card = per-game\n").unwrap();
+
+    let entry = db.lookup("SLUS-00594").unwrap();
+
+    assert_eq!(entry.title, "Test");
+    assert_eq!(entry.region, Some(Region::NtscU));
+    assert_eq!(entry.discs, 1);
+    assert_eq!(entry.settings.memory_card, Some(MemoryCardMode::PerGame));
+
+    assert!(db.extend("title = Orphan").is_err());
+    assert!(db.extend("[SLUS-00594]\ncontroller = paddle").is_err());
+  }
+}
@@ -2,5 +2,7 @@ pub mod cpu;
 pub mod gpu;
 pub mod spu;
 pub mod cdrom;
+pub mod game_db;
+pub mod psf;
 pub mod controllers;
 pub mod util;
\ No newline at end of file
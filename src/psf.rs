@@ -0,0 +1,380 @@
+use std::{collections::HashMap, fs, io::Read, path::Path};
+
+use flate2::{read::ZlibDecoder, Crc};
+
+use crate::{cpu::{exe::PsxExe, CPU, DEFAULT_STACK}, util};
+
+// PSF rips are a PS-X EXE of the game's sound driver and music data, which is
+// booted like any other exe and played through the SPU.
+// per https://gist.github.com/SaxxonPike/a0b47f8579aad703b842001b24d40c00 (psf_format.txt)
+
+const MAGIC: &[u8] = b"PSF";
+const PSF1_VERSION: u8 = 0x01;
+const HEADER_SIZE: usize = 16;
+const TAG_MAGIC: &[u8] = b"[TAG]";
+
+const EXE_HEADER_SIZE: usize = 0x800;
+// libraries can include other libraries, guard against loops
+const MAX_LIB_DEPTH: usize = 10;
+
+const SAMPLE_RATE: usize = 44100;
+
+pub struct Psf {
+  pub exe: PsxExe,
+  pub tags: HashMap<String, String>
+}
+
+impl Psf {
+  /// loads a PSF or MiniPSF, with libraries named in _lib tags looked up next to it
+  pub fn open(path: &Path) -> Result<Self, String> {
+    let bytes = fs::read(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+
+    Self::parse(&bytes, &mut |name| fs::read(dir.join(name)).map_err(|e| format!("couldn't read library {name}: {e}")))
+  }
+
+  /// parses a PSF, using load_lib to read the files its _lib tags refer to
+  pub fn parse(bytes: &[u8], load_lib: &mut dyn FnMut(&str) -> Result<Vec<u8>, String>) -> Result<Self, String> {
+    let mut image = RamImage::default();
+
+    let tags = Self::load(bytes, load_lib, &mut image, 0)?;
+
+    Ok(Self {
+      exe: image.into_exe()?,
+      tags
+    })
+  }
+
+  // the _lib file is loaded first and sets the initial registers, then the file
+  // itself, then _lib2, _lib3 and so on on top of it
+  fn load(bytes: &[u8], load_lib: &mut dyn FnMut(&str) -> Result<Vec<u8>, String>, image: &mut RamImage, depth: usize) -> Result<HashMap<String, String>, String> {
+    if depth > MAX_LIB_DEPTH {
+      return Err("too many nested _lib files".to_string());
+    }
+
+    let (program, tags) = Self::read_file(bytes)?;
+
+    if let Some(lib) = tags.get("_lib") {
+      Self::load(&load_lib(lib)?, load_lib, image, depth + 1)?;
+    }
+
+    image.load(&program)?;
+
+    for i in 2.. {
+      let Some(lib) = tags.get(&format!("_lib{i}")) else {
+        break;
+      };
+
+      let mut lib_image = RamImage::default();
+
+      Self::load(&load_lib(lib)?, load_lib, &mut lib_image, depth + 1)?;
+
+      image.merge(lib_image);
+    }
+
+    Ok(tags)
+  }
+
+  fn read_file(bytes: &[u8]) -> Result<(Vec<u8>, HashMap<String, String>), String> {
+    if bytes.len() < HEADER_SIZE || !bytes.starts_with(MAGIC) {
+      return Err("not a psf file".to_string());
+    }
+
+    if bytes[3] != PSF1_VERSION {
+      return Err(format!("unsupported psf version {:x}, only playstation rips are supported", bytes[3]));
+    }
+
+    let reserved_size = util::read_word(bytes, 4) as usize;
+    let program_size = util::read_word(bytes, 8) as usize;
+    let program_crc = util::read_word(bytes, 12);
+
+    let program_start = HEADER_SIZE + reserved_size;
+    let program_end = program_start + program_size;
+
+    let compressed = bytes.get(program_start..program_end).ok_or("truncated psf file")?;
+
+    let mut crc = Crc::new();
+    crc.update(compressed);
+
+    if crc.sum() != program_crc {
+      return Err("psf program crc mismatch".to_string());
+    }
+
+    let mut program = Vec::new();
+
+    ZlibDecoder::new(compressed).read_to_end(&mut program).map_err(|e| format!("couldn't decompress psf program: {e}"))?;
+
+    let tags = match bytes.get(program_end..) {
+      Some(tag) if tag.starts_with(TAG_MAGIC) => Self::parse_tags(&String::from_utf8_lossy(&tag[TAG_MAGIC.len()..])),
+      _ => HashMap::new()
+    };
+
+    Ok((program, tags))
+  }
+
+  // one key=value per line. keys are case insensitive, and a key repeated on
+  // consecutive lines continues a multiline value
+  fn parse_tags(text: &str) -> HashMap<String, String> {
+    let mut tags: HashMap<String, String> = HashMap::new();
+
+    for line in text.lines() {
+      let Some((key, value)) = line.split_once('=') else {
+        continue;
+      };
+
+      let key = key.trim().to_lowercase();
+      let value = value.trim();
+
+      tags.entry(key).and_modify(|existing| {
+        existing.push('\n');
+        existing.push_str(value);
+      }).or_insert_with(|| value.to_string());
+    }
+
+    tags
+  }
+
+  /// parses a length tag such as "1:23.5", "83" or "1:02:03" into seconds
+  pub fn parse_duration(value: &str) -> Option<f64> {
+    value.trim().split(':').try_fold(0.0, |total, part| part.trim().replace(',', ".").parse::<f64>().ok().map(|part| total * 60.0 + part))
+  }
+
+  pub fn title(&self) -> Option<&str> {
+    self.tags.get("title").map(|title| title.as_str())
+  }
+
+  /// the song length in seconds, not counting the fade
+  pub fn length(&self) -> Option<f64> {
+    self.tags.get("length").and_then(|length| Self::parse_duration(length))
+  }
+
+  pub fn fade(&self) -> Option<f64> {
+    self.tags.get("fade").and_then(|fade| Self::parse_duration(fade))
+  }
+}
+
+// the text sections of every exe in a psf, merged into one
+#[derive(Default)]
+struct RamImage {
+  registers: Option<(u32, u32, u32)>,
+  start: u32,
+  data: Vec<u8>
+}
+
+impl RamImage {
+  fn load(&mut self, program: &[u8]) -> Result<(), String> {
+    if program.len() < EXE_HEADER_SIZE {
+      return Err("psf program is too small".to_string());
+    }
+
+    // rips are often trimmed to the data that's actually used, shorter than the
+    // size in their header
+    let size = (util::read_word(program, 0x1c) as usize).min(program.len() - EXE_HEADER_SIZE);
+
+    let mut exe = program[..EXE_HEADER_SIZE + size].to_vec();
+    exe[0x1c..0x20].copy_from_slice(&(size as u32).to_le_bytes());
+
+    let exe = PsxExe::parse(&exe).map_err(|e| format!("invalid psf program: {e}"))?;
+
+    if self.registers.is_none() {
+      self.registers = Some((exe.pc, exe.gp, exe.stack.unwrap_or(DEFAULT_STACK)));
+    }
+
+    self.write(exe.dest & 0x1f_ffff, &exe.data);
+
+    Ok(())
+  }
+
+  fn write(&mut self, address: u32, bytes: &[u8]) {
+    if bytes.is_empty() {
+      return;
+    }
+
+    if self.data.is_empty() {
+      self.start = address;
+    }
+
+    let end = (self.start as usize + self.data.len()).max(address as usize + bytes.len());
+
+    if address < self.start {
+      let mut data = vec![0; (self.start - address) as usize];
+      data.append(&mut self.data);
+
+      self.data = data;
+      self.start = address;
+    }
+
+    self.data.resize(end - self.start as usize, 0);
+
+    let offset = (address - self.start) as usize;
+
+    self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+  }
+
+  // _lib2 onwards only add data, the registers stay the same
+  fn merge(&mut self, other: RamImage) {
+    self.write(other.start, &other.data);
+  }
+
+  fn into_exe(self) -> Result<PsxExe, String> {
+    let (pc, gp, stack) = self.registers.ok_or("psf has no program")?;
+
+    Ok(PsxExe {
+      pc,
+      gp,
+      dest: 0x8000_0000 | self.start,
+      data: self.data,
+      bss_start: 0,
+      bss_size: 0,
+      stack: Some(stack),
+      args: Vec::new()
+    })
+  }
+}
+
+/// plays a psf without video output, the BIOS initializes the kernel and then the
+/// psf's sound driver takes over
+pub struct PsfPlayer {
+  cpu: CPU,
+  samples: Vec<i16>
+}
+
+impl PsfPlayer {
+  pub fn new(bios: Vec<u8>, psf: Psf) -> Self {
+    // rips don't save, so there's no memory card file
+    let mut cpu = CPU::new(bios, None, None);
+
+    cpu.set_boot_exe(psf.exe);
+
+    Self {
+      cpu,
+      samples: Vec::new()
+    }
+  }
+
+  /// renders the given number of stereo frames at 44.1khz, interleaved left and right
+  pub fn render(&mut self, frames: usize) -> Vec<i16> {
+    while self.samples.len() < frames * 2 {
+      self.cpu.run_frame();
+      self.cpu.bus.reset_cycles();
+
+      self.samples.append(&mut self.cpu.bus.spu.audio_buffer);
+    }
+
+    self.samples.drain(..frames * 2).collect()
+  }
+
+  /// renders a song of the given length, followed by a linear fade out
+  pub fn render_song(&mut self, length: f64, fade: f64) -> Vec<i16> {
+    let length_frames = (length * SAMPLE_RATE as f64) as usize;
+    let fade_frames = (fade * SAMPLE_RATE as f64) as usize;
+
+    let mut samples = self.render(length_frames + fade_frames);
+
+    for (i, frame) in samples[length_frames * 2..].chunks_exact_mut(2).enumerate() {
+      let volume = 1.0 - i as f64 / fade_frames as f64;
+
+      for sample in frame {
+        *sample = (*sample as f64 * volume) as i16;
+      }
+    }
+
+    samples
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use flate2::{write::ZlibEncoder, Compression};
+
+  use super::*;
+
+  fn program(pc: u32, dest: u32, data: &[u8]) -> Vec<u8> {
+    let mut program = vec![0; EXE_HEADER_SIZE];
+
+    program[..8].copy_from_slice(b"PS-X EXE");
+    program[0x10..0x14].copy_from_slice(&pc.to_le_bytes());
+    program[0x18..0x1c].copy_from_slice(&dest.to_le_bytes());
+    program[0x1c..0x20].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    program.extend_from_slice(data);
+
+    program
+  }
+
+  fn psf(program: &[u8], tags: &str) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(program).unwrap();
+
+    let compressed = encoder.finish().unwrap();
+
+    let mut crc = Crc::new();
+    crc.update(&compressed);
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(PSF1_VERSION);
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc.sum().to_le_bytes());
+    bytes.extend_from_slice(&compressed);
+
+    if !tags.is_empty() {
+      bytes.extend_from_slice(TAG_MAGIC);
+      bytes.extend_from_slice(tags.as_bytes());
+    }
+
+    bytes
+  }
+
+  #[test]
+  fn parse_duration() {
+    assert_eq!(Psf::parse_duration("83"), Some(83.0));
+    assert_eq!(Psf::parse_duration("1:23.5"), Some(83.5));
+    assert_eq!(Psf::parse_duration(" 1:02:03 "), Some(3723.0));
+    assert_eq!(Psf::parse_duration("0:10,5"), Some(10.5));
+    assert_eq!(Psf::parse_duration(""), None);
+    assert_eq!(Psf::parse_duration("1:"), None);
+    assert_eq!(Psf::parse_duration("forever"), None);
+  }
+
+  #[test]
+  fn parse_tags() {
+    let tags = Psf::parse_tags("Title=Song\ncomment=line 1\ncomment=line 2\nno value\nLENGTH = 1:30\n");
+
+    assert_eq!(tags.get("title").map(|title| title.as_str()), Some("Song"));
+    assert_eq!(tags.get("comment").map(|comment| comment.as_str()), Some("line 1\nline 2"));
+    assert_eq!(tags.get("length").map(|length| length.as_str()), Some("1:30"));
+    assert_eq!(tags.len(), 3);
+  }
+
+  #[test]
+  fn minipsf_with_libs() {
+    // the library sets the registers, the minipsf and _lib2 patch its data
+    let lib = psf(&program(0x8001_0000, 0x8001_0000, &[1; 8]), "");
+    let lib2 = psf(&program(0x8002_0000, 0x8001_0010, &[3; 4]), "");
+    let minipsf = psf(&program(0x8003_0000, 0x8001_0004, &[2; 2]), "_lib=song.psflib\n_lib2=extra.psflib\nlength=0:05\nfade=1\n");
+
+    let psf = Psf::parse(&minipsf, &mut |name| match name {
+      "song.psflib" => Ok(lib.clone()),
+      "extra.psflib" => Ok(lib2.clone()),
+      _ => Err(format!("unknown library {name}"))
+    }).unwrap();
+
+    assert_eq!(psf.exe.pc, 0x8001_0000);
+    assert_eq!(psf.exe.dest, 0x8001_0000);
+    assert_eq!(psf.exe.stack, Some(DEFAULT_STACK));
+    assert_eq!(psf.exe.data, [1, 1, 1, 1, 2, 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 3, 3, 3, 3]);
+    assert_eq!(psf.length(), Some(5.0));
+    assert_eq!(psf.fade(), Some(1.0));
+  }
+
+  #[test]
+  fn crc_mismatch() {
+    let mut bytes = psf(&program(0x8001_0000, 0x8001_0000, &[0; 4]), "");
+    bytes[12] ^= 0xff;
+
+    assert!(Psf::parse(&bytes, &mut |_| Err("no libraries".to_string())).is_err());
+  }
+}
@@ -1,9 +1,9 @@
 extern crate rsx;
 extern crate console_error_panic_hook;
 
-use rsx::{cpu::CPU, spu::SPU};
+use rsx::{cpu::{block_cache::Interpreter, exe::PsxExe, CPU}, spu::SPU, cdrom::{chd::Chd, disc::{DiscImage, MemoryImage}}, game_db::{self, GameDb}};
 use wasm_bindgen::prelude::*;
-use std::{panic, collections::VecDeque};
+use std::{panic, collections::VecDeque, io::Cursor};
 
 #[wasm_bindgen]
 #[derive(Clone, Copy)]
@@ -45,7 +45,9 @@ macro_rules! console_log {
 #[wasm_bindgen]
 pub struct WasmEmulator {
   cpu: CPU,
-  audio_samples: VecDeque<i16>
+  audio_samples: VecDeque<i16>,
+  game_db: GameDb,
+  serial: Option<String>
 }
 
 #[wasm_bindgen]
@@ -53,11 +55,91 @@ impl WasmEmulator {
   #[wasm_bindgen(constructor)]
   pub fn new(bios: &[u8], game_data: &[u8]) -> Self {
     panic::set_hook(Box::new(console_error_panic_hook::hook));
-    Self {
-      cpu: CPU::new(bios.to_vec(), None, Some(game_data.to_vec()), true),
-      audio_samples: VecDeque::new()
+
+    let disc: Box<dyn DiscImage> = if Chd::is_chd(game_data) {
+      // chd hunks are decompressed on demand from the in-memory image
+      Box::new(Chd::open(Box::new(Cursor::new(game_data.to_vec()))).unwrap())
+    } else {
+      Box::new(MemoryImage::new(game_data.to_vec()))
+    };
+
+    let mut cpu = CPU::new(bios.to_vec(), Some(disc), None);
+
+    cpu.set_interpreter(Interpreter::Cached);
+
+    let serial = cpu.bus.cdrom.disc_mut().and_then(game_db::detect_serial);
+
+    let mut emulator = Self {
+      cpu,
+      audio_samples: VecDeque::new(),
+      game_db: GameDb::builtin(),
+      serial
+    };
+
+    emulator.apply_game_settings();
+
+    emulator
+  }
+
+  fn apply_game_settings(&mut self) {
+    let Some(serial) = &self.serial else {
+      return;
+    };
+
+    if let Some(entry) = self.game_db.lookup(serial) {
+      for issue in &entry.settings.issues {
+        console_log!("known issue: {issue}");
+      }
+
+      if let Some(region) = entry.region {
+        self.cpu.bus.cdrom.set_region(Some(region));
+      }
+
+      // memory cards are stored by the page, per game_serial
+      entry.settings.apply(&mut self.cpu, serial, None);
     }
   }
+
+  /// adds user entries to the game database and reapplies the current game's settings
+  pub fn load_game_db(&mut self, contents: &str) -> Result<(), String> {
+    self.game_db.extend(contents)?;
+
+    self.apply_game_settings();
+
+    Ok(())
+  }
+
+  /// boots a PS-X EXE instead of the disc, either right away without running the BIOS
+  /// or once the BIOS has initialized the kernel
+  pub fn load_exe(&mut self, exe: &[u8], direct_boot: bool) -> Result<(), String> {
+    let exe = PsxExe::parse(exe).map_err(|e| e.to_string())?;
+
+    if direct_boot {
+      self.cpu.load_exe(&exe);
+    } else {
+      self.cpu.set_boot_exe(exe);
+    }
+
+    Ok(())
+  }
+
+  /// skips the BIOS logo and license screens. has to be set before the first frame
+  pub fn set_fast_boot(&mut self, enabled: bool) {
+    self.cpu.set_fast_boot(enabled);
+  }
+
+  pub fn set_region_bypass(&mut self, enabled: bool) {
+    self.cpu.set_region_bypass(enabled);
+  }
+
+  /// the serial of the loaded game, ie: to store memory cards per game
+  pub fn game_serial(&self) -> Option<String> {
+    self.serial.clone()
+  }
+
+  pub fn game_title(&self) -> Option<String> {
+    self.serial.as_ref().and_then(|serial| self.game_db.lookup(serial)).map(|entry| entry.title.clone())
+  }
   pub fn run_frame(&mut self) {
     self.cpu.run_frame();
     self.cpu.bus.gpu.update_picture();